@@ -0,0 +1,130 @@
+//! the bulletin boards a feed can be scraped from
+
+use crate::{FeedCategory, Gakubu};
+use serde_derive::{Deserialize, Serialize};
+
+/// the host of the official boards
+pub const T_BOARD_HOST: &str = "http://t-board.office.tuat.ac.jp";
+
+/// a bulletin board.
+///
+/// the four official boards are built by [`Board::new`], or by [`Board::at`] for a copy of t-board elsewhere;
+/// other boards served by the same software can be described in a config file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Board {
+    /// key of the board, e.g. `technology_academic`.
+    /// used as the key in storage and in urls, so it should not change.
+    pub id: String,
+    /// name shown to people, e.g. `工学部 教務情報`
+    pub name: String,
+    /// the group of boards it belongs to, e.g. `technology` for the boards of 工学部.
+    /// any key can be used for other boards, e.g. `graduate`.
+    pub group: String,
+    /// the kind of posts on the board, e.g. `academic`
+    pub category: String,
+    /// url of the list pages, without the paging parameters
+    pub feed_url: String,
+    /// base of the url of each post. the post id is appended to it.
+    pub info_url: String,
+    /// url of the top page of the board
+    pub board_url: String,
+}
+
+impl Board {
+    /// the official board of `gakubu` and `category`
+    pub fn new(gakubu: Gakubu, category: FeedCategory) -> Self {
+        Self::at(T_BOARD_HOST, gakubu, category)
+    }
+
+    /// the board of `gakubu` and `category` on the t-board at `base_url`, e.g. a mock server
+    pub fn at(base_url: &str, gakubu: Gakubu, category: FeedCategory) -> Self {
+        let (prefix, gakubu_name) = match gakubu {
+            Gakubu::Technology => ("T", "工学部"),
+            Gakubu::Agriculture => ("A", "農学部"),
+        };
+        let (anno, category_name) = match category {
+            FeedCategory::Academic => (1, "教務情報"),
+            FeedCategory::Campus => (0, "学生生活情報"),
+        };
+        let base = format!("{}/{}/boar", base_url.trim_end_matches('/'), prefix);
+        Self {
+            id: format!("{}_{}", gakubu.id(), category.id()),
+            name: format!("{} {}", gakubu_name, category_name),
+            group: gakubu.id().to_string(),
+            category: category.id().to_string(),
+            feed_url: format!("{}/resAjax.php?bAnno={}", base, anno),
+            info_url: format!("{}/vewAjax.php?i=", base),
+            board_url: format!("{}/index.php", base),
+        }
+    }
+
+    /// the four official boards, academic before campus for each gakubu
+    pub fn defaults() -> Vec<Self> {
+        Self::defaults_at(T_BOARD_HOST)
+    }
+
+    /// the four boards of the t-board at `base_url`, in the order of [`Board::defaults`]
+    pub fn defaults_at(base_url: &str) -> Vec<Self> {
+        [Gakubu::Technology, Gakubu::Agriculture]
+            .into_iter()
+            .flat_map(|gakubu| {
+                [FeedCategory::Academic, FeedCategory::Campus]
+                    .map(|category| Self::at(base_url, gakubu, category))
+            })
+            .collect()
+    }
+
+    /// url of the page of a post
+    pub fn post_url(&self, post_id: u32) -> String {
+        format!("{}{}", self.info_url, post_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Board;
+    use crate::{FeedCategory, Gakubu};
+
+    #[test]
+    fn official_boards() {
+        let board = Board::new(Gakubu::Technology, FeedCategory::Campus);
+        assert_eq!(board.id, "technology_campus");
+        assert_eq!(board.group, "technology");
+        assert_eq!(board.category, "campus");
+        assert_eq!(
+            board.feed_url,
+            "http://t-board.office.tuat.ac.jp/T/boar/resAjax.php?bAnno=0"
+        );
+        assert_eq!(
+            board.post_url(1),
+            "http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=1"
+        );
+        assert_eq!(
+            Board::new(Gakubu::Agriculture, FeedCategory::Academic).board_url,
+            "http://t-board.office.tuat.ac.jp/A/boar/index.php"
+        );
+        assert_eq!(
+            Board::at(
+                "http://127.0.0.1:8000/",
+                Gakubu::Technology,
+                FeedCategory::Academic
+            )
+            .feed_url,
+            "http://127.0.0.1:8000/T/boar/resAjax.php?bAnno=1"
+        );
+
+        let ids = Board::defaults()
+            .into_iter()
+            .map(|board| board.id)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                "technology_academic",
+                "technology_campus",
+                "agriculture_academic",
+                "agriculture_campus"
+            ]
+        );
+    }
+}
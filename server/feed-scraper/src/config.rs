@@ -0,0 +1,116 @@
+//! how feeds reach their boards
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    board::{Board, T_BOARD_HOST},
+    get::{HttpClient, ReqwestClient, RetryPolicy},
+    rate_limit::RateLimiter,
+    FeedCategory, Gakubu,
+};
+
+/// where the boards are and how they are accessed.
+/// shared by the feeds of a t-board; clones are cheap and share the rate limit.
+#[derive(Debug, Clone)]
+pub struct FeedConfig {
+    base_url: String,
+    client: Arc<dyn HttpClient>,
+    rate_limiter: RateLimiter,
+    retry_policy: RetryPolicy,
+    max_pages: Option<usize>,
+    max_age: Option<Duration>,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            base_url: T_BOARD_HOST.to_string(),
+            client: Arc::new(ReqwestClient::default()),
+            rate_limiter: RateLimiter::default(),
+            retry_policy: RetryPolicy::default(),
+            max_pages: None,
+            max_age: None,
+        }
+    }
+}
+
+impl FeedConfig {
+    /// scrape the t-board at `base_url` (e.g. `http://127.0.0.1:8000`) instead of the official one
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// access the boards through `client`
+    pub fn with_client(mut self, client: Arc<dyn HttpClient>) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// limit the requests to the boards with `rate_limiter`
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// retry failed requests for pages as `retry_policy` says
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// limit how many list pages are walked on each fetch.
+    /// `None` (the default) walks until the board is exhausted.
+    pub fn with_max_pages(mut self, max_pages: Option<usize>) -> Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// leave out posts last updated more than `max_age` ago,
+    /// and stop walking the list once a whole page is older than that.
+    /// posts without a date are kept. `None` (the default) keeps every post.
+    pub fn with_max_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// url of the t-board, without a trailing `/`
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// the client the boards are accessed through
+    pub fn client(&self) -> &dyn HttpClient {
+        self.client.as_ref()
+    }
+
+    /// the limit shared by the requests to the boards
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// how failed requests for pages are retried
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// how many list pages are walked on each fetch
+    pub fn max_pages(&self) -> Option<usize> {
+        self.max_pages
+    }
+
+    /// how old a post may be to be kept
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// the board of `gakubu` and `category` on the t-board
+    pub fn board(&self, gakubu: Gakubu, category: FeedCategory) -> Board {
+        Board::at(&self.base_url, gakubu, category)
+    }
+
+    /// the four boards of the t-board, in the order of [`Board::defaults`]
+    pub fn boards(&self) -> Vec<Board> {
+        Board::defaults_at(&self.base_url)
+    }
+}
@@ -0,0 +1,555 @@
+use ego_tree::NodeRef;
+use scraper::{ElementRef, Node};
+use url::Url;
+
+/// elements kept in the sanitized html. other elements are replaced by their children.
+const ALLOWED_TAGS: [&str; 32] = [
+    "a",
+    "b",
+    "strong",
+    "i",
+    "em",
+    "u",
+    "s",
+    "del",
+    "ins",
+    "sub",
+    "sup",
+    "code",
+    "br",
+    "hr",
+    "p",
+    "div",
+    "pre",
+    "blockquote",
+    "ul",
+    "ol",
+    "li",
+    "table",
+    "caption",
+    "thead",
+    "tbody",
+    "tfoot",
+    "tr",
+    "th",
+    "td",
+    "h1",
+    "h2",
+    "h3",
+];
+
+/// elements dropped together with their children
+const DROPPED_TAGS: [&str; 12] = [
+    "script", "style", "noscript", "iframe", "object", "embed", "form", "input", "button",
+    "select", "textarea", "img",
+];
+
+/// elements that start a block of their own in markdown
+const BLOCK_TAGS: [&str; 15] = [
+    "p",
+    "div",
+    "pre",
+    "blockquote",
+    "ul",
+    "ol",
+    "table",
+    "hr",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "center",
+];
+
+/// the board only links to these
+const ALLOWED_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// whether the element is not shown on the board, like the `テーブル表示` toggle and hidden parts
+fn is_hidden(element: ElementRef) -> bool {
+    let value = element.value();
+    let toggle = value
+        .attr("class")
+        .is_some_and(|class| class.split_whitespace().any(|class| class == "tgl"));
+    let hidden = value.attr("style").is_some_and(|style| {
+        style
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .contains("display:none")
+    });
+    toggle || hidden
+}
+
+/// `href` as an absolute url against the page at `base`.
+/// `None` for links that go nowhere useful, like `javascript:`.
+fn resolve(base: Option<&Url>, href: &str) -> Option<String> {
+    let url = Url::options().base_url(base).parse(href.trim()).ok()?;
+    ALLOWED_SCHEMES
+        .contains(&url.scheme())
+        .then(|| url.to_string())
+}
+
+/// escapes `text` for html text and quoted attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_html(node: NodeRef<Node>, base: Option<&Url>, html: &mut String) {
+    match node.value() {
+        Node::Text(text) => html.push_str(&escape_html(text)),
+        Node::Element(_) => {
+            let element = ElementRef::wrap(node).unwrap();
+            let name = element.value().name();
+            if DROPPED_TAGS.contains(&name) || is_hidden(element) {
+                return;
+            }
+            let href = match name {
+                "a" => element
+                    .value()
+                    .attr("href")
+                    .and_then(|href| resolve(base, href)),
+                _ => None,
+            };
+            let allowed = ALLOWED_TAGS.contains(&name) && (name != "a" || href.is_some());
+            if allowed {
+                html.push('<');
+                html.push_str(name);
+                if let Some(href) = &href {
+                    html.push_str(&format!(
+                        r#" href="{}" rel="noopener noreferrer""#,
+                        escape_html(href)
+                    ));
+                }
+                for span in ["colspan", "rowspan"] {
+                    if let Some(value) = element
+                        .value()
+                        .attr(span)
+                        .filter(|_| name == "td" || name == "th")
+                        .and_then(|value| value.trim().parse::<u32>().ok())
+                    {
+                        html.push_str(&format!(r#" {}="{}""#, span, value));
+                    }
+                }
+                html.push('>');
+                if name == "br" || name == "hr" {
+                    return;
+                }
+            }
+            for child in node.children() {
+                write_html(child, base, html);
+            }
+            if allowed {
+                html.push_str(&format!("</{}>", name));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// the children of `cell` as html, with only the formatting elements kept.
+/// links are absolute, against the page at `base`.
+pub fn render_html(cell: ElementRef, base: &str) -> String {
+    let base = Url::parse(base).ok();
+    let mut html = String::new();
+    for child in cell.children() {
+        write_html(child, base.as_ref(), &mut html);
+    }
+    html.trim().to_string()
+}
+
+/// text with markdown syntax escaped and runs of whitespace collapsed into one space
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut space = false;
+    for c in text.chars() {
+        if c.is_whitespace() && c != '\u{3000}' {
+            space = true;
+            continue;
+        }
+        if space {
+            escaped.push(' ');
+            space = false;
+        }
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '|') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    if space {
+        escaped.push(' ');
+    }
+    escaped
+}
+
+/// escapes what would start a heading, quote or list at the start of a line
+fn escape_line_start(line: &str) -> String {
+    if line.starts_with(['#', '>', '-', '+', '=']) {
+        return format!("\\{}", line);
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    match line[digits..].chars().next() {
+        Some(marker @ ('.' | ')')) if digits > 0 => {
+            format!("{}\\{}{}", &line[..digits], marker, &line[digits + 1..])
+        }
+        _ => line.to_string(),
+    }
+}
+
+/// wraps `inner` in `marker`, keeping the surrounding spaces outside so the markdown stays valid
+fn wrap(inner: &str, marker: &str) -> String {
+    let trimmed = inner.trim();
+    if trimmed.is_empty() {
+        return inner.to_string();
+    }
+    let leading = if inner.starts_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    let trailing = if inner.ends_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    format!("{}{}{}{}{}", leading, marker, trimmed, marker, trailing)
+}
+
+/// collects the blocks of markdown.
+/// inline content is kept in `inline` until a block starts, with `\n` for each line break.
+struct Markdown<'a> {
+    base: Option<&'a Url>,
+    blocks: Vec<String>,
+    inline: String,
+}
+
+impl<'a> Markdown<'a> {
+    fn new(base: Option<&'a Url>) -> Self {
+        Self {
+            base,
+            blocks: Vec::new(),
+            inline: String::new(),
+        }
+    }
+
+    /// turns the inline content into paragraphs.
+    /// a line break becomes a hard break, and an empty line starts a new paragraph.
+    fn flush(&mut self) {
+        let inline = std::mem::take(&mut self.inline);
+        let mut paragraph = Vec::new();
+        for line in inline.split('\n').map(str::trim) {
+            if line.is_empty() {
+                if !paragraph.is_empty() {
+                    self.blocks.push(paragraph.join("\\\n"));
+                    paragraph.clear();
+                }
+            } else {
+                paragraph.push(escape_line_start(line));
+            }
+        }
+        if !paragraph.is_empty() {
+            self.blocks.push(paragraph.join("\\\n"));
+        }
+    }
+
+    /// the blocks of the children of `node`
+    fn children_blocks(&self, node: NodeRef<Node>) -> Vec<String> {
+        let mut markdown = Markdown::new(self.base);
+        for child in node.children() {
+            markdown.write(child);
+        }
+        markdown.finish()
+    }
+
+    /// the children of `node` as inline markdown, on one line unless they contain line breaks
+    fn inline_text(&self, node: NodeRef<Node>) -> String {
+        let mut markdown = Markdown::new(self.base);
+        for child in node.children() {
+            markdown.write_inline(child);
+        }
+        markdown.inline
+    }
+
+    fn write_inline(&mut self, node: NodeRef<Node>) {
+        match node.value() {
+            Node::Text(text) => self.inline.push_str(&escape_markdown(text)),
+            Node::Element(_) => {
+                let element = ElementRef::wrap(node).unwrap();
+                let name = element.value().name();
+                if DROPPED_TAGS.contains(&name) || is_hidden(element) {
+                    return;
+                }
+                match name {
+                    "br" => self.inline.push('\n'),
+                    "b" | "strong" => {
+                        let inner = self.inline_text(node);
+                        self.inline.push_str(&wrap(&inner, "**"));
+                    }
+                    "i" | "em" => {
+                        let inner = self.inline_text(node);
+                        self.inline.push_str(&wrap(&inner, "*"));
+                    }
+                    "s" | "del" | "strike" => {
+                        let inner = self.inline_text(node);
+                        self.inline.push_str(&wrap(&inner, "~~"));
+                    }
+                    "code" => {
+                        let inner = element.text().collect::<String>();
+                        self.inline
+                            .push_str(&format!("`{}`", inner.replace('`', "'")));
+                    }
+                    "a" => {
+                        let inner = self.inline_text(node);
+                        match element
+                            .value()
+                            .attr("href")
+                            .and_then(|href| resolve(self.base, href))
+                        {
+                            Some(href) if inner.trim().is_empty() => {
+                                self.inline.push_str(&format!("<{}>", href))
+                            }
+                            Some(href) => self.inline.push_str(&format!(
+                                "[{}]({})",
+                                inner.trim().replace('\n', " "),
+                                href.replace('(', "%28").replace(')', "%29")
+                            )),
+                            None => self.inline.push_str(&inner),
+                        }
+                    }
+                    _ if BLOCK_TAGS.contains(&name) || name == "li" => {
+                        // blocks inside inline elements only break the line
+                        self.inline.push('\n');
+                        for child in node.children() {
+                            self.write_inline(child);
+                        }
+                        self.inline.push('\n');
+                    }
+                    _ => {
+                        for child in node.children() {
+                            self.write_inline(child);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn write(&mut self, node: NodeRef<Node>) {
+        let Some(element) = ElementRef::wrap(node) else {
+            self.write_inline(node);
+            return;
+        };
+        let name = element.value().name();
+        if !BLOCK_TAGS.contains(&name) || is_hidden(element) {
+            // spans and fonts may still hold blocks
+            if matches!(name, "span" | "font") && !is_hidden(element) {
+                for child in node.children() {
+                    self.write(child);
+                }
+            } else {
+                self.write_inline(node);
+            }
+            return;
+        }
+        self.flush();
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse().unwrap_or(1);
+                let text = self.inline_text(node);
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !text.is_empty() {
+                    self.blocks.push(format!("{} {}", "#".repeat(level), text));
+                }
+            }
+            "hr" => self.blocks.push("---".to_string()),
+            "blockquote" => {
+                let quoted = self.children_blocks(node).join("\n\n");
+                if !quoted.is_empty() {
+                    let lines = quoted
+                        .lines()
+                        .map(|line| format!("> {}", line).trim_end().to_string())
+                        .collect::<Vec<_>>();
+                    self.blocks.push(lines.join("\n"));
+                }
+            }
+            "ul" | "ol" => {
+                let items = node
+                    .children()
+                    .filter(|child| {
+                        ElementRef::wrap(*child).is_some_and(|item| item.value().name() == "li")
+                    })
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let marker = match name {
+                            "ol" => format!("{}. ", i + 1),
+                            _ => "- ".to_string(),
+                        };
+                        let content = self.children_blocks(item).join("\n\n");
+                        let indent = " ".repeat(marker.len());
+                        let mut lines = content.lines();
+                        let mut item = format!("{}{}", marker, lines.next().unwrap_or_default());
+                        for line in lines {
+                            item.push('\n');
+                            if !line.is_empty() {
+                                item.push_str(&indent);
+                                item.push_str(line);
+                            }
+                        }
+                        item.trim_end().to_string()
+                    })
+                    .collect::<Vec<_>>();
+                if !items.is_empty() {
+                    self.blocks.push(items.join("\n"));
+                }
+            }
+            "table" => {
+                if let Some(table) = self.table(element) {
+                    self.blocks.push(table);
+                }
+            }
+            _ => {
+                let blocks = self.children_blocks(node);
+                self.blocks.extend(blocks);
+            }
+        }
+    }
+
+    /// a pipe table, with the first row as the header
+    fn table(&self, table: ElementRef) -> Option<String> {
+        let rows = table
+            .descendants()
+            .filter_map(ElementRef::wrap)
+            .filter(|row| row.value().name() == "tr")
+            // rows of nested tables belong to those tables
+            .filter(|row| {
+                row.ancestors()
+                    .filter_map(ElementRef::wrap)
+                    .find(|ancestor| ancestor.value().name() == "table")
+                    .is_some_and(|ancestor| ancestor.id() == table.id())
+            })
+            .map(|row| {
+                row.children()
+                    .filter_map(ElementRef::wrap)
+                    .filter(|cell| matches!(cell.value().name(), "td" | "th"))
+                    .map(|cell| {
+                        self.inline_text(*cell)
+                            .split('\n')
+                            .map(str::trim)
+                            .filter(|line| !line.is_empty())
+                            .collect::<Vec<_>>()
+                            .join("<br>")
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|row| !row.is_empty())
+            .collect::<Vec<_>>();
+        let columns = rows.iter().map(Vec::len).max()?;
+        let line = |row: &[String]| {
+            let cells = (0..columns)
+                .map(|i| row.get(i).map(String::as_str).unwrap_or_default())
+                .collect::<Vec<_>>();
+            format!("| {} |", cells.join(" | "))
+        };
+        let mut lines = vec![line(&rows[0]), line(&vec!["---".to_string(); columns])];
+        lines.extend(rows[1..].iter().map(|row| line(row)));
+        Some(lines.join("\n"))
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.flush();
+        self.blocks
+    }
+}
+
+/// the children of `cell` as markdown.
+/// links are absolute, against the page at `base`.
+pub fn render_markdown(cell: ElementRef, base: &str) -> String {
+    let base = Url::parse(base).ok();
+    let mut markdown = Markdown::new(base.as_ref());
+    for child in cell.children() {
+        markdown.write(child);
+    }
+    markdown.finish().join("\n\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render_html, render_markdown};
+    use scraper::{Html, Selector};
+
+    const BASE: &str = "http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=1";
+
+    fn render(body: &str) -> (String, String) {
+        let document = Html::parse_document(&format!(
+            "<table><tbody><tr><td>{}</td></tr></tbody></table>",
+            body
+        ));
+        let cell = document
+            .select(&Selector::parse("td").unwrap())
+            .next()
+            .unwrap();
+        (render_html(cell, BASE), render_markdown(cell, BASE))
+    }
+
+    #[test]
+    fn links_and_emphasis() {
+        let (html, markdown) = render(
+            r#"<font color=#ff0000>10/7更新</font>：<b>必ず</b>確認してください。<br />
+            詳細は<a href="/T/boar/vewAjax.php?i=2" onclick="x()">こちら</a>
+            <script>alert(1)</script><a href="javascript:alert(1)">リンク</a>"#,
+        );
+        assert_eq!(
+            html,
+            "10/7更新：<b>必ず</b>確認してください。<br>\n            詳細は\
+            <a href=\"http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=2\" rel=\"noopener noreferrer\">こちら</a>\
+            \n            リンク"
+        );
+        assert_eq!(
+            markdown,
+            "10/7更新：**必ず**確認してください。\\\n\
+            詳細は[こちら](http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=2) リンク"
+        );
+    }
+
+    #[test]
+    fn paragraphs_and_lists() {
+        let (_, markdown) = render(
+            "1. 日程<br /><br />持ち物:<ul><li>学生証</li><li>筆記用具<ol><li>鉛筆</li></ol></li></ul>\
+            <span class=\"tgl\">テーブル表示</span><div style=\"display: none\">非表示</div>",
+        );
+        assert_eq!(
+            markdown,
+            "1\\. 日程\n\n持ち物:\n\n- 学生証\n- 筆記用具\n\n  1. 鉛筆"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let (html, markdown) = render(
+            r#"<table border="1"><tr><th>時限</th><th colspan="2" style="color:red">教室</th></tr>
+            <tr><td>1限</td><td>L0011|L0012</td></tr></table>"#,
+        );
+        assert_eq!(
+            html,
+            "<table><tbody><tr><th>時限</th><th colspan=\"2\">教室</th></tr>\n            \
+            <tr><td>1限</td><td>L0011|L0012</td></tr></tbody></table>"
+        );
+        assert_eq!(
+            markdown,
+            "| 時限 | 教室 |\n| --- | --- |\n| 1限 | L0011\\|L0012 |"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use super::error::ParseError;
+use chrono::{FixedOffset, NaiveDate};
+
+/// japan standard time, the time zone of the dates on the boards
+pub fn jst() -> FixedOffset {
+    FixedOffset::east_opt(9 * 60 * 60).expect("+09:00 is a valid offset")
+}
+
+/// normalizes full-width digits and punctuation to ascii
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            '\u{3000}' => ' ',
+            c => c,
+        })
+        .collect()
+}
+
+/// parses a date as shown on the board.
+///
+/// accepts `2021/10/07(Thu)` as well as variations like `2021/10/7`, `2021-10-07`,
+/// `2021年10月7日（木）` and full-width digits.
+/// anything in parentheses (the day of the week) is ignored.
+pub fn parse_date(text: &str) -> Result<NaiveDate, ParseError> {
+    let normalized = normalize(text);
+    let date = normalized.split('(').next().unwrap_or_default();
+    let numbers = date
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    let [year, month, day] = numbers[..] else {
+        return Err(ParseError::DateError(text.trim().to_string()));
+    };
+    let (Ok(year), Ok(month), Ok(day)) = (year.parse(), month.parse(), day.parse()) else {
+        return Err(ParseError::DateError(text.trim().to_string()));
+    };
+    NaiveDate::from_ymd_opt(year, month, day)
+        .ok_or_else(|| ParseError::DateError(text.trim().to_string()))
+}
+
+/// splits a 公開期間 like `2021/10/07(Thu) 〜 2022/03/31(Thu)` into its trimmed start and end.
+/// `None` when there is no `〜`.
+pub fn split_date_range(text: &str) -> Option<(&str, &str)> {
+    let (start, end) = text.split_once(['〜', '～', '~'])?;
+    Some((start.trim(), end.trim()))
+}
+
+/// parses a 公開期間 like `2021/10/07(Thu) 〜 2022/03/31(Thu)`.
+/// either side may be empty.
+pub fn parse_date_range(text: &str) -> Result<(Option<NaiveDate>, Option<NaiveDate>), ParseError> {
+    let (start, end) = split_date_range(text)
+        .ok_or_else(|| ParseError::DateRangeError(text.trim().to_string()))?;
+    let parse = |part: &str| -> Result<Option<NaiveDate>, ParseError> {
+        if part.trim().is_empty() {
+            Ok(None)
+        } else {
+            parse_date(part).map(Some)
+        }
+    };
+    Ok((parse(start)?, parse(end)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_date, parse_date_range};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn dates() {
+        let date = NaiveDate::from_ymd_opt(2021, 10, 7).unwrap();
+        assert_eq!(parse_date("2021/10/07(Thu)").unwrap(), date);
+        assert_eq!(parse_date(" 2021/10/7 ").unwrap(), date);
+        assert_eq!(parse_date("2021-10-07").unwrap(), date);
+        assert_eq!(parse_date("2021年10月7日（木）").unwrap(), date);
+        assert_eq!(parse_date("２０２１/１０/０７").unwrap(), date);
+
+        assert!(parse_date("").is_err());
+        assert!(parse_date("10/07").is_err());
+        assert!(parse_date("2021/13/01").is_err());
+    }
+
+    #[test]
+    fn ranges() {
+        let start = NaiveDate::from_ymd_opt(2021, 10, 7).unwrap();
+        let end = NaiveDate::from_ymd_opt(2022, 3, 31).unwrap();
+        assert_eq!(
+            parse_date_range("2021/10/07(Thu) 〜 2022/03/31(Thu)").unwrap(),
+            (Some(start), Some(end))
+        );
+        assert_eq!(
+            parse_date_range("2021/10/07(Thu) 〜 ").unwrap(),
+            (Some(start), None)
+        );
+        assert!(parse_date_range("2021/10/07(Thu)").is_err());
+        assert!(parse_date_range("2021/10/07(Thu) 〜 someday").is_err());
+    }
+}
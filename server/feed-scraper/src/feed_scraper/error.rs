@@ -2,6 +2,7 @@ use thiserror::Error;
 
 /// the error that happens when parseing the web page
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ParseError {
     /// an error that happens when scraping
     #[error("scraping error")]
@@ -9,4 +10,45 @@ pub enum ParseError {
     /// when parsing an invalid int
     #[error("int parse error")]
     IntParseError(#[from] std::num::ParseIntError),
+    /// when a date could not be parsed
+    #[error("invalid date {0:?}")]
+    DateError(String),
+    /// when a 公開期間 could not be parsed
+    #[error("invalid date range {0:?}")]
+    DateRangeError(String),
+    /// a labelled row of a post has no cell with the value
+    #[error("post {post_id}: no value for {label}")]
+    MissingCell {
+        /// id of the post
+        post_id: u32,
+        /// label of the row
+        label: String,
+    },
+    /// the value of a field of a post could not be parsed, e.g. a bad date range
+    #[error("post {post_id}: invalid {label}")]
+    InvalidField {
+        /// id of the post
+        post_id: u32,
+        /// label of the row
+        label: String,
+        /// why the value is invalid
+        #[source]
+        source: Box<ParseError>,
+    },
+    /// the markup of a field of a post is not as expected
+    #[error("post {post_id}: unexpected markup in {label}: {reason}")]
+    UnexpectedMarkup {
+        /// id of the post
+        post_id: u32,
+        /// label of the row
+        label: String,
+        /// what was expected
+        reason: String,
+    },
+    /// the page of a post has no labelled fields, e.g. an error page
+    #[error("post {0}: no fields on the page")]
+    NoFields(u32),
+    /// the parser task panicked
+    #[error("parser task failed")]
+    TaskError(#[from] tokio::task::JoinError),
 }
@@ -1,127 +1,345 @@
+use super::body_parser::{render_html, render_markdown};
+use super::date_parser::{parse_date, parse_date_range, split_date_range};
 use super::error::ParseError;
-use crate::Post;
-use scraper::{Html, Selector};
+use crate::{
+    post::{Attachment, BoardDate},
+    Post,
+};
+use scraper::{ElementRef, Html, Selector};
+use tracing::warn;
+use url::Url;
 
+/// parses the page of a post.
+/// `page_url` is where the page was fetched from, to make the links in 本文 and the attachments absolute.
+/// fails on the first field whose cell or markup is missing; see [`info_parser_lenient`] to keep going.
+/// dates that can not be parsed are kept as shown and do not fail.
 #[tracing::instrument(skip(content))]
-pub async fn info_parser(content: String, id: u32) -> Result<Post, ParseError> {
-    tokio::task::spawn_blocking(move || {
-        let mut information = Post::new(id);
-
-        let info_doc = Html::parse_document(&content);
-        let tr_selector = Selector::parse("table>tbody>tr").unwrap();
-
-        for infos in info_doc.select(&tr_selector) {
-            let data_selector = Selector::parse("td").unwrap();
-            let mut data = infos.select(&data_selector);
-            if let Some(label_elem) = data.next() {
-                if label_elem.value().attr("class") != Some("defLabel") {
-                    continue;
+pub async fn info_parser(content: String, id: u32, page_url: String) -> Result<Post, ParseError> {
+    let (post, warnings) = info_parser_lenient(content, id, page_url).await?;
+    for warning in warnings {
+        match warning {
+            ParseError::InvalidField { .. } => warn!("could not parse a field: {:?}", warning),
+            error => return Err(error),
+        }
+    }
+    Ok(post)
+}
+
+/// parses the page of a post, leaving empty the fields that could not be parsed.
+/// returns the post with why each of those fields could not be parsed.
+/// fails only when the page has no fields at all, e.g. an error page.
+#[tracing::instrument(skip(content))]
+pub async fn info_parser_lenient(
+    content: String,
+    id: u32,
+    page_url: String,
+) -> Result<(Post, Vec<ParseError>), ParseError> {
+    tokio::task::spawn_blocking(move || parse(&content, id, &page_url)).await?
+}
+
+fn parse(content: &str, id: u32, page_url: &str) -> Result<(Post, Vec<ParseError>), ParseError> {
+    let mut information = Post::new(id);
+    let mut warnings = Vec::new();
+    let mut fields = 0;
+
+    let info_doc = Html::parse_document(content);
+    let tr_selector = Selector::parse("table>tbody>tr").unwrap();
+    let data_selector = Selector::parse("td").unwrap();
+
+    for infos in info_doc.select(&tr_selector) {
+        let mut data = infos.select(&data_selector);
+        let Some(label_elem) = data.next() else {
+            continue;
+        };
+        if label_elem.value().attr("class") != Some("defLabel") {
+            continue;
+        }
+        fields += 1;
+        let label = label_elem.text().collect::<String>();
+        if let Err(error) = parse_field(&mut information, &label, data.collect(), page_url) {
+            warnings.push(error.in_field(id, label.trim().to_string()));
+        }
+    }
+
+    if fields == 0 {
+        return Err(ParseError::NoFields(id));
+    }
+    Ok((information, warnings))
+}
+
+/// what went wrong in a field, before the post and label are known
+enum FieldError {
+    MissingCell,
+    UnexpectedMarkup(&'static str),
+    Invalid(ParseError),
+}
+
+impl FieldError {
+    fn in_field(self, post_id: u32, label: String) -> ParseError {
+        match self {
+            FieldError::MissingCell => ParseError::MissingCell { post_id, label },
+            FieldError::UnexpectedMarkup(reason) => ParseError::UnexpectedMarkup {
+                post_id,
+                label,
+                reason: reason.to_string(),
+            },
+            FieldError::Invalid(source) => ParseError::InvalidField {
+                post_id,
+                label,
+                source: Box::new(source),
+            },
+        }
+    }
+}
+
+/// fills the field `label` of `information` from the cells after the label.
+/// fields other than 対象, 本文 and 添付ファイル may have no cell and are left empty then.
+fn parse_field(
+    information: &mut Post,
+    label: &str,
+    cells: Vec<ElementRef>,
+    page_url: &str,
+) -> Result<(), FieldError> {
+    let cell = || cells.first().copied().ok_or(FieldError::MissingCell);
+    match label.trim() {
+        "対象" => {
+            let cell = cell()?;
+            let span = Selector::parse("span").unwrap();
+            information.target = cell
+                .select(&span)
+                .next()
+                .ok_or(FieldError::UnexpectedMarkup("no span in the cell"))?
+                .text()
+                .collect::<String>()
+                .trim()
+                .to_string();
+        }
+        "本文" => {
+            cell()?;
+            let cells = cells
+                .iter()
+                .map(|cell| (*cell, cell_text(*cell)))
+                .filter(|(_, text)| !text.contains("テーブル表示"))
+                .collect::<Vec<_>>();
+            information.contents_html = cells
+                .iter()
+                .map(|(cell, _)| render_html(*cell, page_url))
+                .collect::<Vec<String>>()
+                .join("<br>\n");
+            information.contents_markdown = cells
+                .iter()
+                .map(|(cell, _)| render_markdown(*cell, page_url))
+                .collect::<Vec<String>>()
+                .join("\n\n");
+            information.contents = cells
+                .into_iter()
+                .map(|(_, text)| text)
+                .collect::<Vec<String>>()
+                .join("\n");
+        }
+        label if label.starts_with("添付ファイル") => {
+            let cell = cell()?;
+            let ancor = Selector::parse("a").unwrap();
+            let base = Url::parse(page_url).ok();
+            let attachment_iter = cell
+                .select(&ancor)
+                .filter_map(|elem| -> Option<Attachment> {
+                    let href = elem.value().attr("href")?;
+                    Some(Attachment::new(
+                        elem.text().collect::<String>().trim().to_string(),
+                        base.as_ref()?.join(href).ok()?.to_string(),
+                    ))
+                });
+            information.attachments.extend(attachment_iter);
+        }
+        _ => {
+            let data_text = cells
+                .iter()
+                .map(|elem| cell_text(*elem))
+                .filter(|val| !val.contains("テーブル表示"))
+                .collect::<Vec<String>>()
+                .join("\n");
+            match label.trim() {
+                "タイトル" => information.title = data_text,
+                "担当者" => information.person_in_charge = data_text,
+                "発信元" => information.origin = data_text,
+                "カテゴリー" => information.category = data_text,
+                "最終更新日" => {
+                    information.updated_date = BoardDate::parse(&data_text);
+                    parse_date(&data_text).map_err(FieldError::Invalid)?;
                 }
-                let label_text = label_elem.text().collect::<String>();
-                match label_text.trim() {
-                    "対象" => {
-                        let span = Selector::parse("span").unwrap();
-                        information.target = data
-                            .next()
-                            .unwrap()
-                            .select(&span)
-                            .next()
-                            .unwrap()
-                            .text()
-                            .collect::<String>()
-                            .trim()
-                            .to_string();
-                    }
-                    label_text if label_text.starts_with("添付ファイル") => {
-                        let ancor = Selector::parse("a").unwrap();
-                        let attachment_iter = data.next().unwrap().select(&ancor).filter_map(
-                            |elem| -> Option<(String, String)> {
-                                Some((
-                                    elem.text().collect::<String>().trim().to_string(),
-                                    format!(
-                                        "http://t-board.office.tuat.ac.jp{}",
-                                        elem.value().attr("href")?
-                                    ),
-                                ))
-                            },
-                        );
-                        for (key, val) in attachment_iter {
-                            information.attachment.insert(key, val);
-                        }
-                    }
-                    _ => {
-                        let data_text = data
-                            .map(|elem| {
-                                let mut string: String = elem
-                                    .text()
-                                    .map(|s| s.trim().to_owned())
-                                    .collect::<Vec<String>>()
-                                    .join("\n");
-                                if string.ends_with('\n') {
-                                    string.pop();
-                                }
-                                string
-                            })
-                            .filter(|val| !val.contains("テーブル表示"))
-                            .collect::<Vec<String>>()
-                            .join("\n");
-                        match label_text.trim() {
-                            "タイトル" => information.title = data_text,
-                            "本文" => information.contents = data_text,
-                            "担当者" => information.person_in_charge = data_text,
-                            "発信元" => information.origin = data_text,
-                            "カテゴリー" => information.category = data_text,
-                            "最終更新日" => information.updated_date = data_text,
-                            "公開期間" => {
-                                let (start, end) = data_text.split_once(" 〜 ").unwrap();
-                                information.show_date = (start.to_string(), end.to_string())
-                            }
-                            _ => {
-                                information.other.insert(label_text, data_text);
-                            }
-                        };
-                    }
+                "公開期間" => {
+                    // keep the text as shown even when it can not be parsed
+                    information.show_date = match split_date_range(&data_text) {
+                        Some((start, end)) => (BoardDate::parse(start), BoardDate::parse(end)),
+                        None => (BoardDate::parse(&data_text), BoardDate::default()),
+                    };
+                    parse_date_range(&data_text).map_err(FieldError::Invalid)?;
                 }
-            }
+                _ => {
+                    information.other.insert(label.to_string(), data_text);
+                }
+            };
         }
+    }
+    Ok(())
+}
 
-        Ok(information)
-    })
-    .await
-    .unwrap()
+/// the text of a cell, one line per text node
+fn cell_text(cell: ElementRef) -> String {
+    let mut string: String = cell
+        .text()
+        .map(|s| s.trim().to_owned())
+        .collect::<Vec<String>>()
+        .join("\n");
+    if string.ends_with('\n') {
+        string.pop();
+    }
+    string
 }
 
 #[cfg(test)]
 mod test {
     const TEST_DATA: &str = include_str!("../../test_assets/test_info.html");
 
-    use super::info_parser;
-    use crate::Post;
+    use super::{info_parser, info_parser_lenient};
+    use crate::{
+        feed_scraper::error::ParseError,
+        post::{Attachment, BoardDate, Target},
+        Post,
+    };
     use std::collections::HashMap;
 
     #[tokio::test]
     async fn info_parse() {
-        let info: Post = info_parser(TEST_DATA.to_owned(), 8000).await.unwrap();
+        let info: Post = info_parser(
+            TEST_DATA.to_owned(),
+            8000,
+            "http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=8000".to_string(),
+        )
+        .await
+        .unwrap();
 
-        let mut attachment = HashMap::new();
-        attachment.insert("【工学府】R3後期集中講義一覧1007.pdf".to_string(), "http://t-board.office.tuat.ac.jp/T/fmapi/getFile.php?path=%2Ffmi%2Fxml%2Fcnt%2F%25E3%2580%2590%25E5%25B7%25A5%25E5%25AD%25A6%25E5%25BA%259C%25E3%2580%2591R3%25E5%25BE%258C%25E6%259C%259F%25E9%259B%2586%25E4%25B8%25AD%25E8%25AC%259B%25E7%25BE%25A9%25E4%25B8%2580%25E8%25A6%25A71007.pdf%3F-db%3DTUTw%26-lay%3DBoarVewType0%26-recid%3D11367%26-field%3DBoarFile%3A%3ArFile%281%29.15534&name=%E3%80%90%E5%B7%A5%E5%AD%A6%E5%BA%9C%E3%80%91R3%E5%BE%8C%E6%9C%9F%E9%9B%86%E4%B8%AD%E8%AC%9B%E7%BE%A9%E4%B8%80%E8%A6%A71007.pdf".to_string());
+        let attachments = vec![Attachment::new("【工学府】R3後期集中講義一覧1007.pdf".to_string(), "http://t-board.office.tuat.ac.jp/T/fmapi/getFile.php?path=%2Ffmi%2Fxml%2Fcnt%2F%25E3%2580%2590%25E5%25B7%25A5%25E5%25AD%25A6%25E5%25BA%259C%25E3%2580%2591R3%25E5%25BE%258C%25E6%259C%259F%25E9%259B%2586%25E4%25B8%25AD%25E8%25AC%259B%25E7%25BE%25A9%25E4%25B8%2580%25E8%25A6%25A71007.pdf%3F-db%3DTUTw%26-lay%3DBoarVewType0%26-recid%3D11367%26-field%3DBoarFile%3A%3ArFile%281%29.15534&name=%E3%80%90%E5%B7%A5%E5%AD%A6%E5%BA%9C%E3%80%91R3%E5%BE%8C%E6%9C%9F%E9%9B%86%E4%B8%AD%E8%AC%9B%E7%BE%A9%E4%B8%80%E8%A6%A71007.pdf".to_string())];
 
         let correct = Post {
             post_id: 8000,
                 title: "10/7更新\n【工学府】 2021年度後期集中講義の開講について".to_string(),
                 contents: "10/7更新：ゲノム情報解析工学特論、先端ゲノム情報解析工学特論について追記しました。\n\n詳細は添付ファイルを参照。\n※講義ごとの開講案内を随時掲載します。\n※未定については、わかり次第お知らせします。".to_string(),
-                updated_date: "2021/10/07(Thu)".to_string(),
-                show_date: ("2021/10/07(Thu)".to_string(),"2022/03/31(Thu)".to_string()),
+                contents_html: "10/7更新：ゲノム情報解析工学特論、先端ゲノム情報解析工学特論について追記しました。<br>\n<br>\n詳細は添付ファイルを参照。<br>\n※講義ごとの開講案内を随時掲載します。<br>\n※未定については、わかり次第お知らせします。".to_string(),
+                contents_markdown: "10/7更新：ゲノム情報解析工学特論、先端ゲノム情報解析工学特論について追記しました。\n\n詳細は添付ファイルを参照。\\\n※講義ごとの開講案内を随時掲載します。\\\n※未定については、わかり次第お知らせします。".to_string(),
+                updated_date: BoardDate::parse("2021/10/07(Thu)"),
+                show_date: (BoardDate::parse("2021/10/07(Thu)"), BoardDate::parse("2022/03/31(Thu)")),
                 person_in_charge: "教務係".to_string(),
                 origin: "教務係".to_string(),
                 category: "集中講義 Intensive Lectures".to_string(),
                 target:"LM[All] / CM(C1)[All] / CM(C2)[All] / CM(C3)[All] / MM[All] / PM[All] / EM[All] / SM(CS)[All] / LD[All] / CD(C1)[All] / CD(C2)[All] / CD(C3)[All] / MD[All] / AD(A1)[All] / AD(A2)[All] / AD(A3)[All]".to_string(),
-                attachment,
+                attachments,
                 other: HashMap::new(),
         };
 
         let _ = dbg!(serde_json::to_string(&correct));
 
         assert_eq!(info, correct);
+        assert_eq!(info.attachments[0].file_type, "application/pdf");
+
+        let targets = info.targets();
+        assert_eq!(targets.len(), 16);
+        assert_eq!(
+            targets[1],
+            Target {
+                program: "CM".to_string(),
+                course: Some("C1".to_string()),
+                years: None,
+            }
+        );
+    }
+
+    const PAGE_URL: &str = "http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=1";
+
+    #[tokio::test]
+    async fn broken_fields() {
+        let content = format!(
+            "<html><body><table><tbody>{}</tbody></table></body></html>",
+            concat!(
+                r#"<tr><td class="defLabel">タイトル</td><td>試験について</td></tr>"#,
+                r#"<tr><td class="defLabel">対象</td><td>学部1年</td></tr>"#,
+                r#"<tr><td class="defLabel">公開期間</td><td>2021/10/07(Thu) から</td></tr>"#,
+                r#"<tr><td class="defLabel">本文</td></tr>"#,
+            )
+        );
+
+        let error = info_parser(content.clone(), 1, PAGE_URL.to_string())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ParseError::UnexpectedMarkup { post_id: 1, ref label, .. } if label == "対象"
+        ));
+
+        let (post, warnings) = info_parser_lenient(content, 1, PAGE_URL.to_string())
+            .await
+            .unwrap();
+        assert_eq!(post.title, "試験について");
+        assert_eq!(post.target, "");
+        assert_eq!(post.show_date.0.text, "2021/10/07(Thu) から");
+        assert_eq!(post.show_date.0.date, BoardDate::parse("2021/10/07").date);
+        assert_eq!(post.show_date.1, BoardDate::default());
+        assert_eq!(warnings.len(), 3);
+        assert!(matches!(
+            &warnings[1],
+            ParseError::InvalidField { post_id: 1, label, source }
+                if label == "公開期間" && matches!(**source, ParseError::DateRangeError(_))
+        ));
+        assert!(matches!(
+            &warnings[2],
+            ParseError::MissingCell { post_id: 1, label } if label == "本文"
+        ));
+    }
+
+    #[tokio::test]
+    async fn not_a_post() {
+        let error = info_parser_lenient(
+            "<html><body>エラー</body></html>".to_string(),
+            1,
+            PAGE_URL.to_string(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, ParseError::NoFields(1)));
+    }
+
+    #[tokio::test]
+    async fn bad_dates_are_not_fatal() {
+        let content = format!(
+            "<html><body><table><tbody>{}</tbody></table></body></html>",
+            concat!(
+                r#"<tr><td class="defLabel">タイトル</td><td>試験について</td></tr>"#,
+                r#"<tr><td class="defLabel">最終更新日</td><td>近日中</td></tr>"#,
+                r#"<tr><td class="defLabel">本文</td><td>本文です</td></tr>"#,
+            )
+        );
+
+        let post = info_parser(content, 1, PAGE_URL.to_string()).await.unwrap();
+        assert_eq!(post.title, "試験について");
+        assert_eq!(post.updated_date.text, "近日中");
+        assert_eq!(post.contents, "本文です");
+    }
+
+    #[tokio::test]
+    async fn contents_skip_table_cells() {
+        let content = format!(
+            "<html><body><table><tbody>{}</tbody></table></body></html>",
+            concat!(
+                r#"<tr><td class="defLabel">本文</td><td>テーブル表示</td>"#,
+                r#"<td>一行目<br>二行目</td><td>三行目</td></tr>"#,
+            )
+        );
+
+        let post = info_parser(content, 1, PAGE_URL.to_string()).await.unwrap();
+        assert_eq!(post.contents, "一行目\n二行目\n三行目");
+        assert!(!post.contents_html.contains("テーブル表示"));
+        assert!(post.contents_html.contains("三行目"));
+        assert!(!post.contents_markdown.contains("テーブル表示"));
     }
 }
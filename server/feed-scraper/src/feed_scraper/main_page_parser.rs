@@ -1,14 +1,35 @@
 use super::error::ParseError;
 use scraper::Selector;
 
+/// a row of the feed list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedEntry {
+    /// id of the post
+    pub id: u32,
+    /// title as shown in the list.
+    /// the office usually edits this (e.g. "10/7更新") when a post is updated.
+    pub title: Option<String>,
+}
+
+/// one page of the feed list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedPage {
+    /// the posts listed on this page
+    pub entries: Vec<FeedEntry>,
+    /// whether the board has a "次へ" link to a following page
+    pub has_next: bool,
+}
+
 #[tracing::instrument(skip(content))]
-pub async fn main_page_parser(content: String) -> Result<Vec<u32>, ParseError> {
+pub async fn main_page_parser(content: String) -> Result<FeedPage, ParseError> {
     tokio::task::spawn_blocking(move || {
         let document = scraper::Html::parse_document(&content);
         let selector = Selector::parse("table>tbody>tr").unwrap();
         let infos = document.select(&selector);
+        let next_selector = Selector::parse("p.paging>a.paging_prevnext").unwrap();
+        let title_selector = Selector::parse("p.standout>a").unwrap();
 
-        let mut ids = Vec::new();
+        let mut entries = Vec::new();
 
         for info in infos {
             let id = info
@@ -16,13 +37,20 @@ pub async fn main_page_parser(content: String) -> Result<Vec<u32>, ParseError> {
                 .attr("i")
                 .ok_or_else(|| ParseError::ScrapingError("could not find attr 'i'".into()))?
                 .parse::<u32>()?;
-            ids.push(id);
+            let title = info
+                .select(&title_selector)
+                .next()
+                .map(|elem| elem.text().collect::<String>().trim().to_string());
+            entries.push(FeedEntry { id, title });
         }
 
-        Ok(ids)
+        let has_next = document
+            .select(&next_selector)
+            .any(|elem| elem.text().collect::<String>().trim() == "次へ");
+
+        Ok(FeedPage { entries, has_next })
     })
-    .await
-    .unwrap()
+    .await?
 }
 
 #[cfg(test)]
@@ -32,13 +60,45 @@ mod test {
 
     #[tokio::test]
     async fn info_parse() {
-        let info = main_page_parser(TEST_DATA.to_owned()).await.unwrap();
+        let page = main_page_parser(TEST_DATA.to_owned()).await.unwrap();
 
         let correct = vec![
             10641, 10636, 10634, 10146, 10635, 10633, 10632, 10630, 10628, 10627, 10624, 10623,
             10622, 10597, 10621, 10620, 10619, 10611, 10577, 10576,
         ];
 
-        assert_eq!(info, correct);
+        let ids = page
+            .entries
+            .iter()
+            .map(|entry| entry.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, correct);
+        assert_eq!(
+            page.entries[1].title.as_deref(),
+            Some("＊1/20更新＊令和2年度　３学期オンライン定期試験日程")
+        );
+        assert!(page.has_next);
+    }
+
+    #[tokio::test]
+    async fn last_page() {
+        let content = TEST_DATA.replace(
+            r#"<a class="paging_prevnext" href="javascript:void(0);" alt="2">次へ</a>"#,
+            "",
+        );
+        let page = main_page_parser(content).await.unwrap();
+
+        assert_eq!(page.entries.len(), 20);
+        assert!(!page.has_next);
+    }
+
+    #[tokio::test]
+    async fn empty_page() {
+        let page = main_page_parser("<table><tbody></tbody></table>".to_owned())
+            .await
+            .unwrap();
+
+        assert!(page.entries.is_empty());
+        assert!(!page.has_next);
     }
 }
@@ -1,5 +1,8 @@
+pub(crate) mod body_parser;
+pub mod date_parser;
 pub mod error;
 mod info_parser;
 mod main_page_parser;
-pub use info_parser::info_parser;
-pub use main_page_parser::main_page_parser;
+pub mod target_parser;
+pub use info_parser::{info_parser, info_parser_lenient};
+pub use main_page_parser::{main_page_parser, FeedEntry, FeedPage};
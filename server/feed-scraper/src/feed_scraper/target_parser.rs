@@ -0,0 +1,143 @@
+use super::date_parser::normalize;
+use crate::post::Target;
+
+/// the last year of the longest program. larger years are not valid.
+const MAX_YEAR: u32 = 10;
+
+/// parses the years in the brackets of an entry like `1`, `1,2` or `1-3`.
+/// `Some(None)` for `All`, and `None` for anything that is not a list of years
+/// from 1 to [`MAX_YEAR`].
+fn parse_years(selector: &str) -> Option<Option<Vec<u32>>> {
+    let selector = selector.trim();
+    if selector.is_empty() || selector.eq_ignore_ascii_case("all") || selector == "全" {
+        return Some(None);
+    }
+    let valid = |year: &str| {
+        year.trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|year| (1..=MAX_YEAR).contains(year))
+    };
+    let mut years = Vec::new();
+    for part in selector
+        .split([',', '、', '・', ' '])
+        .filter(|part| !part.is_empty())
+    {
+        match part.split_once(['-', '~', '〜']) {
+            Some((start, end)) => {
+                let (start, end) = (valid(start)?, valid(end)?);
+                if start > end {
+                    return None;
+                }
+                years.extend(start..=end);
+            }
+            None => years.push(valid(part)?),
+        }
+    }
+    years.sort_unstable();
+    years.dedup();
+    (!years.is_empty()).then_some(Some(years))
+}
+
+/// parses an entry of 対象 like `CM(C1)[All]`.
+/// both the course in parentheses and the years in brackets may be left out.
+/// `None` when the brackets hold something else than years, e.g. `D[M1]`.
+pub fn parse_target(entry: &str) -> Option<Target> {
+    let entry = normalize(entry);
+    let entry = entry.trim();
+    let program_end = entry.find(['(', '[']).unwrap_or(entry.len());
+    let program = entry[..program_end].trim();
+    if program.is_empty() {
+        return None;
+    }
+    let mut rest = &entry[program_end..];
+
+    let mut course = None;
+    if let Some(after) = rest.strip_prefix('(') {
+        let (inside, after) = after.split_once(')')?;
+        course = Some(inside.trim().to_string()).filter(|course| !course.is_empty());
+        rest = after.trim_start();
+    }
+    let mut years = None;
+    if let Some(after) = rest.strip_prefix('[') {
+        let (inside, after) = after.split_once(']')?;
+        years = parse_years(inside)?;
+        rest = after;
+    }
+    if !rest.trim().is_empty() {
+        return None;
+    }
+    Some(Target {
+        program: program.to_string(),
+        course,
+        years,
+    })
+}
+
+/// parses a whole 対象 like `LM[All] / CM(C1)[All]`.
+/// entries that can not be parsed are skipped.
+pub fn parse_targets(text: &str) -> Vec<Target> {
+    text.split(['/', '\n']).filter_map(parse_target).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_target, parse_targets};
+    use crate::post::Target;
+
+    fn target(program: &str, course: Option<&str>, years: Option<Vec<u32>>) -> Target {
+        Target {
+            program: program.to_string(),
+            course: course.map(str::to_string),
+            years,
+        }
+    }
+
+    #[test]
+    fn entries() {
+        assert_eq!(
+            parse_target("CM(C1)[All]"),
+            Some(target("CM", Some("C1"), None))
+        );
+        assert_eq!(parse_target(" LM[All] "), Some(target("LM", None, None)));
+        assert_eq!(parse_target("SM(CS)"), Some(target("SM", Some("CS"), None)));
+        assert_eq!(parse_target("B[2]"), Some(target("B", None, Some(vec![2]))));
+        assert_eq!(
+            parse_target("ＢＴ［１，３］"),
+            Some(target("BT", None, Some(vec![1, 3])))
+        );
+        assert_eq!(
+            parse_target("B(L)[1-3]"),
+            Some(target("B", Some("L"), Some(vec![1, 2, 3])))
+        );
+        // not a list of years
+        assert_eq!(parse_target("D[M1]"), None);
+        assert_eq!(parse_target("B[3-1]"), None);
+        assert_eq!(parse_target("B[0]"), None);
+        assert_eq!(parse_target("B[11]"), None);
+        // a huge range is rejected without being expanded
+        assert_eq!(parse_target("B[1-4000000000]"), None);
+        assert_eq!(
+            parse_target("B[1-10]"),
+            Some(target("B", None, Some((1..=10).collect())))
+        );
+
+        assert_eq!(parse_target(""), None);
+        assert_eq!(parse_target("[All]"), None);
+        assert_eq!(parse_target("CM(C1[All]"), None);
+        assert_eq!(parse_target("CM[All] extra"), None);
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            parse_targets("LM[All] / CM(C1)[All] / / D[M1] / SM(CS)[1]"),
+            vec![
+                target("LM", None, None),
+                target("CM", Some("C1"), None),
+                target("SM", Some("CS"), Some(vec![1])),
+            ]
+        );
+        assert!(parse_targets("").is_empty());
+    }
+}
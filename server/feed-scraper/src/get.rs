@@ -1,44 +1,532 @@
-use std::{future::Future, time::Duration};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    time::{Duration, Instant},
+};
 
+use rand::Rng;
+use reqwest::StatusCode;
 use thiserror::Error;
+use tracing::debug;
+
+/// default time a request may take, including reading the body
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// the error that happens when accessing the internet
 #[derive(Error, Debug)]
 pub enum GetError {
-    /// when there is a problem regarding networking
+    /// when there is a problem regarding networking, e.g. dns, connection or timeout
     #[error("internet connention error")]
     ConnectionError(#[from] reqwest::Error),
     /// when it can't get the text
     #[error("invalid text on page")]
     InvalidTextError,
+    /// when the server answers with an error status
+    #[error("server answered {0}")]
+    HttpStatus(StatusCode),
+    /// when a downloaded file is larger than allowed
+    #[error("the file is larger than {0} bytes")]
+    TooLarge(usize),
+}
+
+impl GetError {
+    /// whether the same request may succeed later.
+    /// timeouts, connection errors (including dns), `408`, `429` and `5xx` are retryable;
+    /// other statuses like `404` and malformed requests are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            GetError::ConnectionError(e) => e.is_timeout() || e.is_connect() || e.is_body(),
+            GetError::InvalidTextError => true,
+            GetError::HttpStatus(status) => {
+                status.is_server_error()
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            GetError::TooLarge(_) => false,
+        }
+    }
+}
+
+/// how failed requests are retried: exponential backoff with jitter,
+/// for retryable errors only, until a number of retries or a total time is used up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    max_elapsed: Duration,
 }
 
-#[tracing::instrument(skip(fut))]
-async fn retry<T, E, F>(fut: impl Fn() -> F) -> Result<T, E>
-where
-    F: Future<Output = Result<T, E>>,
-{
-    let mut result = fut().await;
-    for _ in 0..5 {
-        if result.is_ok() {
-            break;
+impl Default for RetryPolicy {
+    /// up to 5 retries, waiting about 1s, 2s, 4s... (at most 30s), giving up after 2 minutes
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_elapsed: Duration::from_secs(2 * 60),
         }
-        tokio::time::sleep(Duration::from_secs(5)).await;
-        result = fut().await;
     }
-    result
 }
 
-/// does the actual getting from the internet part
+impl RetryPolicy {
+    /// never retry
+    pub fn none() -> Self {
+        Self::default().with_max_retries(0)
+    }
+
+    /// retry at most `max_retries` times
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// wait `initial` before the first retry, `multiplier` times longer before each next one,
+    /// but never longer than `max`
+    pub fn with_backoff(mut self, initial: Duration, multiplier: f64, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.multiplier = multiplier;
+        self.max_backoff = max;
+        self
+    }
+
+    /// do not retry when the next attempt would start more than `max_elapsed` after the first
+    pub fn with_max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = max_elapsed;
+        self
+    }
+
+    /// the wait before retry number `retry` (from 0), without jitter
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(retry as i32);
+        Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    /// runs `request` until it succeeds, fails with an error that is not retryable,
+    /// or the retries are used up. returns the last result.
+    pub async fn retry<T, F>(&self, request: impl Fn() -> F) -> Result<T, GetError>
+    where
+        F: Future<Output = Result<T, GetError>>,
+    {
+        let start = Instant::now();
+        let mut retries = 0;
+        loop {
+            let error = match request().await {
+                Err(e) if e.is_retryable() && retries < self.max_retries => e,
+                result => return result,
+            };
+            // between half and all of the backoff, so that clients do not retry in lockstep
+            let delay = self
+                .backoff(retries)
+                .mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+            if start.elapsed() + delay > self.max_elapsed {
+                return Err(error);
+            }
+            debug!("retrying in {:?} after {:?}", delay, error);
+            tokio::time::sleep(delay).await;
+            retries += 1;
+        }
+    }
+}
+
+/// does the actual getting from the internet part, with the default timeout and retry policy
 #[tracing::instrument]
 pub async fn get(feed_url: &str) -> Result<String, GetError> {
-    retry(|| async {
-        reqwest::get(feed_url)
-            .await
-            .map_err(GetError::ConnectionError)?
-            .text()
+    let client = ReqwestClient::default();
+    RetryPolicy::default().retry(|| client.get(feed_url)).await
+}
+
+/// what the server tells about a file without downloading it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileInfo {
+    /// `Content-Length` in bytes
+    pub size: Option<u64>,
+    /// `Content-Type` without parameters
+    pub file_type: Option<String>,
+}
+
+/// a file downloaded in full
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileContent {
+    /// `Content-Type` without parameters
+    pub file_type: Option<String>,
+    /// the contents
+    pub data: Vec<u8>,
+}
+
+/// asks for the size and type of a file with a `HEAD` request. not retried.
+#[tracing::instrument]
+pub async fn head(url: &str) -> Result<FileInfo, GetError> {
+    ReqwestClient::default().head(url).await
+}
+
+/// a future that can be returned by a trait object
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// what identifies a version of a page, to ask the server whether it changed since
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    /// `ETag` of the page
+    pub etag: Option<String>,
+    /// `Last-Modified` of the page
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn of(headers: &reqwest::header::HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        }
+    }
+
+    /// whether there is nothing to ask with
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// the answer to a conditional request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conditional {
+    /// the page changed, or the server does not support conditional requests
+    Modified {
+        /// the page
+        body: String,
+        /// to ask with next time
+        validators: Validators,
+    },
+    /// the page is the same as when `validators` were given (`304 Not Modified`)
+    NotModified,
+}
+
+/// how a [`Feed`](crate::Feed) talks to its board.
+/// the default is [`ReqwestClient`]; tests can serve pages from memory instead.
+/// requests are not retried by the client; see [`RetryPolicy`].
+pub trait HttpClient: fmt::Debug + Send + Sync {
+    /// downloads the page at `url` as text
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<String, GetError>>;
+
+    /// asks for the size and type of the file at `url` without downloading it
+    fn head<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<FileInfo, GetError>>;
+
+    /// downloads the file at `url`.
+    /// fails with [`GetError::TooLarge`] as soon as it turns out to be larger than `max_size` bytes.
+    fn download<'a>(
+        &'a self,
+        url: &'a str,
+        max_size: usize,
+    ) -> BoxFuture<'a, Result<FileContent, GetError>>;
+
+    /// downloads the page at `url` unless it is the same as when `validators` were given.
+    /// by default the page is always downloaded.
+    fn get_if_modified<'a>(
+        &'a self,
+        url: &'a str,
+        validators: &'a Validators,
+    ) -> BoxFuture<'a, Result<Conditional, GetError>> {
+        let _ = validators;
+        Box::pin(async move {
+            Ok(Conditional::Modified {
+                body: self.get(url).await?,
+                validators: Validators::default(),
+            })
+        })
+    }
+}
+
+/// the client that accesses the internet.
+/// an error status is returned as [`GetError::HttpStatus`].
+#[derive(Debug, Clone)]
+pub struct ReqwestClient {
+    client: reqwest::Client,
+}
+
+impl Default for ReqwestClient {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEOUT)
+    }
+}
+
+impl ReqwestClient {
+    /// a client whose requests fail after `timeout`
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("could not build the http client"),
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, GetError> {
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(GetError::HttpStatus(response.status()));
+        }
+        Ok(response)
+    }
+
+    async fn text(response: reqwest::Response) -> Result<String, GetError> {
+        response.text().await.map_err(|e| {
+            if e.is_timeout() {
+                GetError::ConnectionError(e)
+            } else {
+                GetError::InvalidTextError
+            }
+        })
+    }
+
+    async fn get_text(&self, url: &str) -> Result<String, GetError> {
+        Self::text(self.send(self.client.get(url)).await?).await
+    }
+
+    async fn get_text_if_modified(
+        &self,
+        url: &str,
+        validators: &Validators,
+    ) -> Result<Conditional, GetError> {
+        let mut request = self.client.get(url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Conditional::NotModified);
+        }
+        if !response.status().is_success() {
+            return Err(GetError::HttpStatus(response.status()));
+        }
+        let validators = Validators::of(response.headers());
+        Ok(Conditional::Modified {
+            body: Self::text(response).await?,
+            validators,
+        })
+    }
+
+    /// `Content-Type` of `response` without parameters
+    fn file_type(response: &reqwest::Response) -> Option<String> {
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|file_type| file_type.split(';').next())
+            .map(|file_type| file_type.trim().to_lowercase())
+            .filter(|file_type| !file_type.is_empty())
+    }
+
+    async fn file_info(&self, url: &str) -> Result<FileInfo, GetError> {
+        let response = self.send(self.client.head(url)).await?;
+        Ok(FileInfo {
+            size: response
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|size| size.trim().parse().ok()),
+            file_type: Self::file_type(&response),
+        })
+    }
+
+    async fn download_file(&self, url: &str, max_size: usize) -> Result<FileContent, GetError> {
+        let mut response = self.send(self.client.get(url)).await?;
+        if response
+            .content_length()
+            .is_some_and(|length| length > max_size as u64)
+        {
+            return Err(GetError::TooLarge(max_size));
+        }
+        let file_type = Self::file_type(&response);
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if data.len() + chunk.len() > max_size {
+                return Err(GetError::TooLarge(max_size));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(FileContent { file_type, data })
+    }
+}
+
+impl HttpClient for ReqwestClient {
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<String, GetError>> {
+        Box::pin(self.get_text(url))
+    }
+
+    fn head<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<FileInfo, GetError>> {
+        Box::pin(self.file_info(url))
+    }
+
+    fn download<'a>(
+        &'a self,
+        url: &'a str,
+        max_size: usize,
+    ) -> BoxFuture<'a, Result<FileContent, GetError>> {
+        Box::pin(self.download_file(url, max_size))
+    }
+
+    fn get_if_modified<'a>(
+        &'a self,
+        url: &'a str,
+        validators: &'a Validators,
+    ) -> BoxFuture<'a, Result<Conditional, GetError>> {
+        Box::pin(self.get_text_if_modified(url, validators))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Conditional, GetError, HttpClient, ReqwestClient, RetryPolicy, Validators};
+    use reqwest::StatusCode;
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn quick() -> RetryPolicy {
+        RetryPolicy::default().with_backoff(Duration::from_millis(1), 2.0, Duration::from_millis(4))
+    }
+
+    #[test]
+    fn classifies_errors() {
+        assert!(GetError::HttpStatus(StatusCode::SERVICE_UNAVAILABLE).is_retryable());
+        assert!(GetError::HttpStatus(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!GetError::HttpStatus(StatusCode::NOT_FOUND).is_retryable());
+        assert!(!GetError::HttpStatus(StatusCode::FORBIDDEN).is_retryable());
+
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(4));
+        assert_eq!(policy.backoff(10), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn retries_retryable_errors() {
+        let attempts = AtomicU32::new(0);
+        let flaky = || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(GetError::HttpStatus(StatusCode::BAD_GATEWAY)),
+                _ => Ok("page"),
+            }
+        };
+        assert_eq!(quick().retry(flaky).await.unwrap(), "page");
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+
+        let missing = || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(GetError::HttpStatus(StatusCode::NOT_FOUND))
+        };
+        assert!(quick().retry(missing).await.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        let down = || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(GetError::HttpStatus(StatusCode::SERVICE_UNAVAILABLE))
+        };
+        assert!(quick().with_max_retries(2).retry(down).await.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+        assert!(RetryPolicy::none().retry(down).await.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        // the next wait would go past the time limit
+        let policy = RetryPolicy::default().with_max_elapsed(Duration::from_millis(100));
+        assert!(policy.retry(down).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    /// a server answering `/ok` with a page, `/slow` after a second,
+    /// `/etag` with a page or `304` when it is asked with its etag, and anything else with 404
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buffer = [0; 1024];
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+                    let response = if request.starts_with("GET /etag ") {
+                        if request.contains("if-none-match: \"v1\"\r\n") {
+                            "HTTP/1.1 304 Not Modified\r\n\r\n"
+                        } else {
+                            "HTTP/1.1 200 OK\r\netag: \"v1\"\r\nlast-modified: Wed, 21 Oct 2015 07:28:00 GMT\r\ncontent-length: 4\r\n\r\npage"
+                        }
+                    } else if request.starts_with("GET /ok ") {
+                        "HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\npage"
+                    } else if request.starts_with("GET /slow ") {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        "HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nslow"
+                    } else {
+                        "HTTP/1.1 404 Not Found\r\ncontent-length: 9\r\n\r\nnot found"
+                    };
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn checks_status_and_timeout() {
+        let base = serve().await;
+        let client = ReqwestClient::new(Duration::from_millis(200));
+
+        assert_eq!(client.get(&format!("{}/ok", base)).await.unwrap(), "page");
+        assert!(matches!(
+            client.get(&format!("{}/gone", base)).await,
+            Err(GetError::HttpStatus(StatusCode::NOT_FOUND))
+        ));
+
+        let file = client.download(&format!("{}/ok", base), 4).await.unwrap();
+        assert_eq!(file.data, b"page");
+        assert!(matches!(
+            client.download(&format!("{}/ok", base), 3).await,
+            Err(GetError::TooLarge(3))
+        ));
+
+        let url = format!("{}/etag", base);
+        let validators = match client
+            .get_if_modified(&url, &Validators::default())
             .await
-            .map_err(|_e| GetError::InvalidTextError)
-    })
-    .await
+            .unwrap()
+        {
+            Conditional::Modified { body, validators } => {
+                assert_eq!(body, "page");
+                validators
+            }
+            Conditional::NotModified => panic!("asked without validators"),
+        };
+        assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            validators.last_modified.as_deref(),
+            Some("Wed, 21 Oct 2015 07:28:00 GMT")
+        );
+        assert_eq!(
+            client.get_if_modified(&url, &validators).await.unwrap(),
+            Conditional::NotModified
+        );
+
+        match client.get(&format!("{}/slow", base)).await {
+            Err(e @ GetError::ConnectionError(_)) => assert!(e.is_retryable()),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
 }
@@ -3,37 +3,55 @@
 //! # tuat-feed-parser
 //! this crate provides a api to access the tuat feed as a struct.
 
-use std::{collections::HashMap, fmt, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    time::{Duration, Instant, SystemTime},
+};
 
+use chrono::{DateTime, NaiveDate, Utc};
+
+use futures_util::future::join_all;
+
+use board::Board;
 use post::Post;
+use serde_derive::{Deserialize, Serialize};
 use thiserror::Error;
 
+pub mod board;
+mod config;
 mod feed_scraper;
 mod get;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod post;
+mod rate_limit;
 
-use feed_scraper::{error::ParseError, info_parser, main_page_parser};
-pub use get::{get, GetError};
+pub use config::FeedConfig;
+pub use feed_scraper::{body_parser::escape_html, date_parser::jst, error::ParseError};
+use feed_scraper::{info_parser, info_parser_lenient, main_page_parser, FeedEntry, FeedPage};
+pub use get::{
+    get, head, BoxFuture, Conditional, FileContent, FileInfo, GetError, HttpClient, ReqwestClient,
+    RetryPolicy, Validators,
+};
+pub use rate_limit::{
+    RateLimitError, RateLimiter, DEFAULT_MAX_IN_FLIGHT, DEFAULT_REQUESTS_PER_SECOND,
+};
 
-use tracing::{debug, info, Instrument};
+use tracing::{debug, info, warn};
 
-/// campas feed url
-const T_CAMPUS_FEED_URL: &str =
-    "http://t-board.office.tuat.ac.jp/T/boar/resAjax.php?bAnno=0&par=20&skip=0";
-/// academic feed url
-const T_ACADEMIC_FEED_URL: &str =
-    "http://t-board.office.tuat.ac.jp/T/boar/resAjax.php?bAnno=1&par=20&skip=0";
-const T_INFO_URL_BASE: &str = "http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=";
+/// number of posts requested per list page (`par`)
+const PAGE_SIZE: usize = 20;
 
-/// campas feed url
-const A_CAMPUS_FEED_URL: &str =
-    "http://t-board.office.tuat.ac.jp/A/boar/resAjax.php?bAnno=0&par=20&skip=0";
-/// academic feed url
-const A_ACADEMIC_FEED_URL: &str =
-    "http://t-board.office.tuat.ac.jp/A/boar/resAjax.php?bAnno=1&par=20&skip=0";
-const A_INFO_URL_BASE: &str = "http://t-board.office.tuat.ac.jp/A/boar/vewAjax.php?i=";
+/// default time after which a cached post is fetched again to look for edits
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
 
-#[derive(Debug)]
+/// maximum number of stale posts fetched again on each fetch.
+/// keeps the load bounded e.g. right after a restart, when nothing has been checked yet.
+const MAX_REFRESH_PER_FETCH: usize = PAGE_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 /// 学部
 pub enum Gakubu {
     /// 工学部
@@ -42,7 +60,7 @@ pub enum Gakubu {
     Agriculture,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 /// カテゴリ
 pub enum FeedCategory {
     /// キャンパス情報
@@ -51,6 +69,26 @@ pub enum FeedCategory {
     Academic,
 }
 
+impl Gakubu {
+    /// the group of the official boards of the gakubu, e.g. `technology`
+    pub fn id(self) -> &'static str {
+        match self {
+            Gakubu::Technology => "technology",
+            Gakubu::Agriculture => "agriculture",
+        }
+    }
+}
+
+impl FeedCategory {
+    /// the category of the official boards, e.g. `academic`
+    pub fn id(self) -> &'static str {
+        match self {
+            FeedCategory::Academic => "academic",
+            FeedCategory::Campus => "campus",
+        }
+    }
+}
+
 /// Any Error That may happen in this library
 #[derive(Error, Debug)]
 pub enum TuatFeedParserError {
@@ -65,71 +103,578 @@ pub enum TuatFeedParserError {
 /// For academic and Campus
 #[derive(Clone)]
 pub struct Feed {
-    name: String,
-    feed_url: &'static str,
-    info_url: &'static str,
+    board: Board,
+    config: FeedConfig,
     buffer: HashMap<u32, Post>,
+    /// when each cached post was last fetched
+    checked: HashMap<u32, Instant>,
+    /// the list title of each post at the last fetch
+    list_titles: HashMap<u32, String>,
+    /// each list page at the last fetch, by url
+    pages: HashMap<String, CachedPage>,
+    /// every post on the list at the last fetch, `None` when it stopped before the end
+    listed: Option<HashSet<u32>>,
+    refresh_interval: Option<Duration>,
+    /// keep posts with fields that could not be parsed
+    lenient: bool,
 }
 
 impl fmt::Debug for Feed {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Feed")
-            .field("name", &self.name)
+            .field("board", &self.board.id)
             .finish_non_exhaustive()
     }
 }
 
+/// what a fetch has done so far, across the list pages
+#[derive(Debug, Default)]
+struct FetchProgress {
+    /// cached posts that are due for a refresh
+    outdated: HashSet<u32>,
+    /// posts downloaded by this fetch
+    fetched: HashSet<u32>,
+    /// number of outdated posts downloaded again
+    refreshed: usize,
+}
+
+/// the oldest day a post may have been updated on to be younger than `max_age`, in japan time.
+/// `None` when that is before the epoch.
+fn oldest_kept_date(max_age: Duration) -> Option<NaiveDate> {
+    let oldest = SystemTime::now().checked_sub(max_age)?;
+    Some(
+        DateTime::<Utc>::from(oldest)
+            .with_timezone(&jst())
+            .date_naive(),
+    )
+}
+
+/// a list page as it was last fetched
+#[derive(Debug, Clone)]
+struct CachedPage {
+    validators: Validators,
+    /// hash of the html, to skip parsing it again when the server does not send validators
+    hash: u64,
+    page: FeedPage,
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// fills in the size and type of each attachment from what the board reports.
+/// generic types like `application/octet-stream` keep the type guessed from the name.
+/// when the board does not answer, the description in `previous` is kept,
+/// so a refresh does not look like an edit.
+async fn describe_attachments(config: &FeedConfig, post: &mut Post, previous: Option<&Post>) {
+    let infos = join_all(post.attachments.iter().map(|attachment| {
+        config
+            .rate_limiter()
+            .run(config.client().head(&attachment.url))
+    }))
+    .await;
+    for (attachment, info) in post.attachments.iter_mut().zip(infos) {
+        match info {
+            Ok(FileInfo { size, file_type }) => {
+                attachment.size = size;
+                if let Some(file_type) =
+                    file_type.filter(|file_type| file_type != "application/octet-stream")
+                {
+                    attachment.file_type = file_type;
+                }
+            }
+            Err(e) => {
+                debug!("could not describe {}: {:?}", attachment.name, e);
+                if let Some(old) = previous.and_then(|previous| {
+                    previous
+                        .attachments
+                        .iter()
+                        .find(|old| old.url == attachment.url)
+                }) {
+                    attachment.clone_from(old);
+                }
+            }
+        }
+    }
+}
+
 impl Feed {
-    /// initialize feed
+    /// initialize feed of the official board of `gakubu` and `category`
     pub fn new(gakubu: Gakubu, category: FeedCategory) -> Self {
-        let (feed_url, info_url) = match gakubu {
-            Gakubu::Technology => match category {
-                FeedCategory::Campus => (T_CAMPUS_FEED_URL, T_INFO_URL_BASE),
-                FeedCategory::Academic => (T_ACADEMIC_FEED_URL, T_INFO_URL_BASE),
-            },
-            Gakubu::Agriculture => match category {
-                FeedCategory::Campus => (A_CAMPUS_FEED_URL, A_INFO_URL_BASE),
-                FeedCategory::Academic => (A_ACADEMIC_FEED_URL, A_INFO_URL_BASE),
-            },
-        };
+        Self::from_board(Board::new(gakubu, category))
+    }
+
+    /// initialize feed of any board
+    pub fn from_board(board: Board) -> Self {
         Self {
-            name: format!("{:?} {:?}", gakubu, category),
-            feed_url,
-            info_url,
+            board,
+            config: FeedConfig::default(),
             buffer: HashMap::new(),
+            checked: HashMap::new(),
+            list_titles: HashMap::new(),
+            pages: HashMap::new(),
+            listed: None,
+            refresh_interval: Some(DEFAULT_REFRESH_INTERVAL),
+            lenient: true,
         }
     }
 
-    /// get the actual feed
+    /// the board the feed is scraped from
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// base of the url of each post. the post id is appended to it.
+    pub fn info_url(&self) -> &str {
+        &self.board.info_url
+    }
+
+    /// url of the top page of the board
+    pub fn board_url(&self) -> &str {
+        &self.board.board_url
+    }
+
+    /// access the board as set in `config`.
+    /// the urls of the board are not changed; build the board with [`FeedConfig::board`] to use its base url.
+    /// give the feeds of a t-board clones of the same config, so that they share the rate limit.
+    pub fn with_config(mut self, config: FeedConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// how long a cached post is trusted before it is fetched again to pick up edits.
+    /// posts whose title changed in the list are always fetched again.
+    /// `None` never refreshes cached posts on a timer.
+    pub fn with_refresh_interval(mut self, refresh_interval: Option<Duration>) -> Self {
+        self.refresh_interval = refresh_interval;
+        self
+    }
+
+    /// whether a post with fields that could not be parsed is kept with those fields empty
+    /// (the default), or left out until it can be parsed.
+    /// dates that can not be parsed never leave a post out.
+    /// the fields that could not be parsed are logged either way.
+    pub fn with_lenient_parsing(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// seed the post cache, e.g. with posts restored from storage.
+    /// seeded posts are only fetched again once they are due for a refresh.
+    pub fn extend_cache(&mut self, posts: impl IntoIterator<Item = Post>) {
+        self.buffer
+            .extend(posts.into_iter().map(|post| (post.post_id, post)));
+    }
+
+    /// posts currently held in the cache. posts that left the board are dropped on each fetch.
+    pub fn cached_posts(&self) -> impl Iterator<Item = &Post> {
+        self.buffer.values()
+    }
+
+    /// ids of every post on the board at the last fetch, including the ones too old to be returned.
+    /// `None` when the fetch stopped before the end of the list, e.g. at the page or age limit,
+    /// so a post missing from the fetch may still be on the board.
+    pub fn listed_ids(&self) -> Option<&HashSet<u32>> {
+        self.listed.as_ref()
+    }
+
+    /// url of the list page starting at `skip`
+    fn page_url(&self, skip: usize) -> String {
+        format!("{}&par={}&skip={}", self.board.feed_url, PAGE_SIZE, skip)
+    }
+
+    /// the list page at `url`.
+    /// asks the server whether the page changed since the last fetch,
+    /// and does not parse it again when it did not.
+    async fn list_page(&mut self, url: String) -> Result<FeedPage, TuatFeedParserError> {
+        let cached = self.pages.get(&url);
+        let validators = cached
+            .map(|cached| cached.validators.clone())
+            .unwrap_or_default();
+        let response = self
+            .config
+            .retry_policy()
+            .retry(|| {
+                self.config
+                    .rate_limiter()
+                    .run(self.config.client().get_if_modified(&url, &validators))
+            })
+            .await?;
+        let (content, validators) = match (response, cached) {
+            (Conditional::NotModified, Some(cached)) => {
+                debug!("{} was not modified", url);
+                return Ok(cached.page.clone());
+            }
+            (Conditional::NotModified, None) => (self.get(&url).await?, Validators::default()),
+            (Conditional::Modified { body, validators }, _) => (body, validators),
+        };
+        let hash = content_hash(&content);
+        if let Some(cached) = self
+            .pages
+            .get_mut(&url)
+            .filter(|cached| cached.hash == hash)
+        {
+            debug!("{} did not change", url);
+            cached.validators = validators;
+            return Ok(cached.page.clone());
+        }
+        let page = main_page_parser(content).await?;
+        self.pages.insert(
+            url,
+            CachedPage {
+                validators,
+                hash,
+                page: page.clone(),
+            },
+        );
+        Ok(page)
+    }
+
+    /// whether the cached version of `entry` should be fetched again
+    fn is_outdated(&self, entry: &FeedEntry) -> bool {
+        let title_changed = match (self.list_titles.get(&entry.id), &entry.title) {
+            (Some(old), Some(new)) => old != new,
+            _ => false,
+        };
+        if title_changed {
+            debug!("title of {} changed in {}", entry.id, self.board.id);
+            return true;
+        }
+        match (self.refresh_interval, self.checked.get(&entry.id)) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(interval), Some(checked)) => checked.elapsed() >= interval,
+        }
+    }
+
+    /// downloads the page at `url` once the rate limit allows, retrying as the config says
+    async fn get(&self, url: &str) -> Result<String, GetError> {
+        self.config
+            .retry_policy()
+            .retry(|| {
+                self.config
+                    .rate_limiter()
+                    .run(self.config.client().get(url))
+            })
+            .await
+    }
+
+    /// downloads the post `id`. `None` when it could not be downloaded or parsed.
+    async fn fetch_post(&self, id: u32) -> Option<Post> {
+        let page_url = self.board.post_url(id);
+        let content = match self.get(&page_url).await {
+            Ok(content) => content,
+            Err(e) => {
+                debug!("could not get info {} from {}: {:?}", id, self.board.id, e);
+                return None;
+            }
+        };
+        let parsed = if self.lenient {
+            info_parser_lenient(content, id, page_url).await
+        } else {
+            info_parser(content, id, page_url)
+                .await
+                .map(|post| (post, Vec::new()))
+        };
+        let mut post = match parsed {
+            Ok((post, warnings)) => {
+                for warning in warnings {
+                    warn!(
+                        "could not parse a field on {}: {:?}",
+                        self.board.id, warning
+                    );
+                }
+                post
+            }
+            Err(e) => {
+                warn!(
+                    "could not parse info {} from {}: {:?}",
+                    id, self.board.id, e
+                );
+                return None;
+            }
+        };
+        describe_attachments(&self.config, &mut post, self.buffer.get(&id)).await;
+        Some(post)
+    }
+
+    /// downloads the posts of `entries` that are not cached or are outdated, concurrently
+    async fn fetch_posts(&mut self, entries: &[FeedEntry], progress: &mut FetchProgress) {
+        let mut to_fetch = Vec::new();
+        for entry in entries {
+            let id = entry.id;
+            if !self.buffer.contains_key(&id) {
+                debug!("fetching new info {} from {}", id, self.board.id);
+                to_fetch.push(id);
+            } else if self.is_outdated(entry) {
+                progress.outdated.insert(id);
+                if progress.refreshed < MAX_REFRESH_PER_FETCH {
+                    debug!("refreshing info {} from {}", id, self.board.id);
+                    progress.refreshed += 1;
+                    to_fetch.push(id);
+                }
+            }
+        }
+
+        let fetched_posts = join_all(to_fetch.iter().map(|&id| self.fetch_post(id))).await;
+        let now = Instant::now();
+        for (id, post) in to_fetch.into_iter().zip(fetched_posts) {
+            if let Some(post) = post {
+                self.buffer.insert(id, post);
+                self.checked.insert(id, now);
+                progress.fetched.insert(id);
+            }
+        }
+    }
+
+    /// whether the cached post `id` was last updated before `cutoff`.
+    /// posts without a date are never older.
+    fn is_older(&self, id: u32, cutoff: NaiveDate) -> bool {
+        self.buffer
+            .get(&id)
+            .and_then(|post| post.updated_date.date)
+            .is_some_and(|date| date < cutoff)
+    }
+
+    /// get the actual feed.
+    /// the posts are downloaded concurrently, within the rate limit of the config.
     #[tracing::instrument]
     pub async fn fetch(&mut self) -> Result<Vec<Post>, TuatFeedParserError> {
-        info!("fetching {} feed start", self.name);
-        let content = get(self.feed_url).await?;
-        let ids = main_page_parser(content).await?;
+        info!("fetching {} feed start", self.board.id);
+        let cutoff = self.config.max_age().and_then(oldest_kept_date);
+        let mut progress = FetchProgress::default();
+        let mut entries: Vec<FeedEntry> = Vec::new();
+        let mut page_count = 0;
+        let mut visited = HashSet::new();
+        let mut complete = false;
+        loop {
+            let url = self.page_url(page_count * PAGE_SIZE);
+            visited.insert(url.clone());
+            let page = self.list_page(url).await?;
+            page_count += 1;
+
+            let new_entries = page
+                .entries
+                .into_iter()
+                .filter(|entry| !entries.iter().any(|e| e.id == entry.id))
+                .collect::<Vec<_>>();
+            debug!(
+                "page {} of {} had {} posts",
+                page_count,
+                self.board.id,
+                new_entries.len()
+            );
+            self.fetch_posts(&new_entries, &mut progress).await;
+            let too_old = cutoff.is_some_and(|cutoff| {
+                new_entries
+                    .iter()
+                    .all(|entry| self.is_older(entry.id, cutoff))
+            });
+            let added = !new_entries.is_empty();
+            entries.extend(new_entries);
+
+            // stop when the board says so, or when the page did not add anything new
+            // (guards against a server that ignores `skip`).
+            if !page.has_next || !added {
+                complete = true;
+                break;
+            }
+            if too_old {
+                debug!("reached age limit for {}", self.board.id);
+                break;
+            }
+            if self.config.max_pages().is_some_and(|max| page_count >= max) {
+                debug!("reached page limit for {}", self.board.id);
+                break;
+            }
+        }
+        // forget pages past the end, e.g. when the board got shorter
+        self.pages.retain(|url, _| visited.contains(url));
+        // forget posts that left the board
+        let listed = entries.iter().map(|entry| entry.id).collect::<HashSet<_>>();
+        self.buffer.retain(|id, _| listed.contains(id));
+        self.checked.retain(|id, _| listed.contains(id));
+        self.list_titles.retain(|id, _| listed.contains(id));
+        self.listed = complete.then_some(listed);
 
         let mut informations = Vec::new();
-        for id in ids {
-            let mut info = self.buffer.get(&id).cloned();
-            if info.is_none() {
-                debug!("fetching new info {} from {}", id, self.name);
-                tokio::time::sleep(Duration::from_secs(1))
-                    .instrument(tracing::debug_span!("delay"))
-                    .await;
-                let content_result = get(&format!("{}{}", self.info_url, id)).await;
-                if content_result.is_err() {
-                    continue;
-                }
-                let info_result = info_parser(content_result.unwrap(), id).await;
-                if info_result.is_err() {
-                    continue;
-                }
-                info = Some(info_result.unwrap());
-                self.buffer.insert(id, info.clone().unwrap());
+        for entry in entries {
+            let id = entry.id;
+            // keep serving the cached version if a refresh failed
+            let Some(info) = self.buffer.get(&id).cloned() else {
+                continue;
+            };
+            // remember the title only once the post is up to date,
+            // so a failed or postponed refresh is tried again next time
+            if let (true, Some(title)) = (
+                progress.fetched.contains(&id) || !progress.outdated.contains(&id),
+                entry.title,
+            ) {
+                self.list_titles.insert(id, title);
             }
-            informations.push(info.unwrap());
+            if cutoff.is_some_and(|cutoff| self.is_older(id, cutoff)) {
+                continue;
+            }
+            informations.push(info);
         }
 
-        info!("fetching {} feed done", self.name);
+        info!("fetching {} feed done", self.board.id);
         Ok(informations)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Feed, FeedCategory, Gakubu, Post};
+    use crate::mock::{MockBoard, MOCK_ATTACHMENT_SIZE, MOCK_BASE_URL};
+    use std::{sync::Arc, time::Duration};
+
+    #[tokio::test]
+    async fn fetch_from_mock() {
+        let mock = Arc::new(MockBoard::default());
+        let config = mock.config();
+        let mut feed = Feed::from_board(config.board(Gakubu::Technology, FeedCategory::Academic))
+            .with_config(config);
+
+        let posts = feed.fetch().await.unwrap();
+        let ids = posts.iter().map(|post| post.post_id).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 20);
+        assert_eq!(ids[..3], [10641, 10636, 10634]);
+        assert_eq!(
+            posts[0].title,
+            "10/7更新\n【工学府】 2021年度後期集中講義の開講について"
+        );
+        let attachment = &posts[0].attachments[0];
+        assert!(attachment
+            .url
+            .starts_with("http://t-board.test/T/fmapi/getFile.php?"));
+        assert_eq!(attachment.size, Some(MOCK_ATTACHMENT_SIZE));
+        assert_eq!(attachment.file_type, "application/pdf");
+
+        let requests = mock.requests();
+        let lists = requests
+            .iter()
+            .filter(|url| url.contains("resAjax.php"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lists,
+            [
+                format!("{}/T/boar/resAjax.php?bAnno=1&par=20&skip=0", MOCK_BASE_URL),
+                format!(
+                    "{}/T/boar/resAjax.php?bAnno=1&par=20&skip=20",
+                    MOCK_BASE_URL
+                ),
+            ]
+            .iter()
+            .collect::<Vec<_>>()
+        );
+        assert!(requests.contains(&format!("{}/T/boar/vewAjax.php?i=10641", MOCK_BASE_URL)));
+
+        // cached posts are not fetched again, and the list did not change
+        assert_eq!(feed.fetch().await.unwrap(), posts);
+        assert_eq!(mock.requests().len(), requests.len() + 2);
+        assert_eq!(mock.not_modified(), 2);
+    }
+
+    #[tokio::test]
+    async fn forgets_removed_posts() {
+        let mock = Arc::new(MockBoard::default());
+        let config = mock.config();
+        let mut feed = Feed::from_board(config.board(Gakubu::Technology, FeedCategory::Academic))
+            .with_config(config);
+        feed.extend_cache([Post::new(1)]);
+
+        feed.fetch().await.unwrap();
+        assert_eq!(feed.cached_posts().count(), 20);
+        assert!(feed.cached_posts().all(|post| post.post_id != 1));
+    }
+
+    #[tokio::test]
+    async fn page_limit() {
+        let mock = Arc::new(MockBoard::default());
+        let config = mock.config().with_max_pages(Some(1));
+        let mut feed = Feed::from_board(config.board(Gakubu::Technology, FeedCategory::Academic))
+            .with_config(config);
+
+        assert_eq!(feed.fetch().await.unwrap().len(), 20);
+        let lists = mock
+            .requests()
+            .into_iter()
+            .filter(|url| url.contains("resAjax.php"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lists,
+            [format!(
+                "{}/T/boar/resAjax.php?bAnno=1&par=20&skip=0",
+                MOCK_BASE_URL
+            )]
+        );
+        // the second page was not looked at
+        assert!(feed.listed_ids().is_none());
+    }
+
+    #[tokio::test]
+    async fn age_limit() {
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+        // every post of the mock was updated on 2021/10/07
+        let mock = Arc::new(MockBoard::default());
+        let config = mock.config().with_max_age(Some(DAY));
+        let mut feed = Feed::from_board(config.board(Gakubu::Technology, FeedCategory::Academic))
+            .with_config(config);
+
+        assert!(feed.fetch().await.unwrap().is_empty());
+        let lists = mock
+            .requests()
+            .iter()
+            .filter(|url| url.contains("resAjax.php"))
+            .count();
+        assert_eq!(lists, 1);
+        assert!(feed.listed_ids().is_none());
+
+        let mock = Arc::new(MockBoard::default());
+        let config = mock.config().with_max_age(Some(100 * 366 * DAY));
+        let mut feed = Feed::from_board(config.board(Gakubu::Technology, FeedCategory::Academic))
+            .with_config(config);
+        assert_eq!(feed.fetch().await.unwrap().len(), 20);
+        assert_eq!(feed.listed_ids().map(|ids| ids.len()), Some(20));
+    }
+
+    #[tokio::test]
+    async fn fetch_without_validators() {
+        let mock = Arc::new(MockBoard::default().without_validators());
+        let config = mock.config();
+        let mut feed = Feed::from_board(config.board(Gakubu::Agriculture, FeedCategory::Campus))
+            .with_config(config);
+
+        let posts = feed.fetch().await.unwrap();
+        assert_eq!(posts.len(), 20);
+        let cached = feed
+            .pages
+            .values()
+            .map(|page| page.hash)
+            .collect::<Vec<_>>();
+        assert_eq!(cached.len(), 2);
+
+        assert_eq!(feed.fetch().await.unwrap(), posts);
+        assert_eq!(mock.not_modified(), 0);
+        assert!(feed
+            .pages
+            .values()
+            .all(|page| cached.contains(&page.hash) && page.validators.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn mock_only_serves_its_base_url() {
+        let mock = Arc::new(MockBoard::default());
+        let mut feed =
+            Feed::new(Gakubu::Technology, FeedCategory::Academic).with_config(mock.config());
+
+        assert!(feed.fetch().await.is_err());
+        assert_eq!(
+            mock.requests(),
+            ["http://t-board.office.tuat.ac.jp/T/boar/resAjax.php?bAnno=1&par=20&skip=0"]
+        );
+    }
+}
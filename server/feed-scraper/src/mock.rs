@@ -0,0 +1,194 @@
+//! a t-board in memory, to test scraping without the campus server.
+//! enabled by the `mock` feature.
+
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use reqwest::StatusCode;
+use url::Url;
+
+use crate::{
+    get::{BoxFuture, Conditional, FileContent, FileInfo, GetError, HttpClient, Validators},
+    rate_limit::RateLimiter,
+    FeedConfig,
+};
+
+/// the first list page of every board
+const LIST_PAGE: &str = include_str!("../test_assets/test_academic_feed.html");
+/// the page of every post
+const INFO_PAGE: &str = include_str!("../test_assets/test_info.html");
+/// the list pages after the first
+const EMPTY_PAGE: &str = "<table><tbody></tbody></table>";
+
+/// the url a [`MockBoard`] answers on by default
+pub const MOCK_BASE_URL: &str = "http://t-board.test";
+
+/// size of every attachment
+pub const MOCK_ATTACHMENT_SIZE: u64 = 1024;
+
+/// a mock of t-board serving the pages in `test_assets`.
+///
+/// the first list page of every board has the 20 posts of `test_academic_feed.html`
+/// and the following pages are empty. every post is `test_info.html`,
+/// and every attachment is a pdf of [`MOCK_ATTACHMENT_SIZE`] bytes, which are all spaces.
+/// urls outside the base url are not found.
+/// pages have an `ETag`, and conditional requests for an unchanged page are answered with `304`.
+#[derive(Debug)]
+pub struct MockBoard {
+    base_url: String,
+    validators: bool,
+    requests: Mutex<Vec<String>>,
+    not_modified: AtomicUsize,
+}
+
+impl Default for MockBoard {
+    fn default() -> Self {
+        Self::new(MOCK_BASE_URL)
+    }
+}
+
+impl MockBoard {
+    /// a mock answering on `base_url`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            validators: true,
+            requests: Mutex::new(Vec::new()),
+            not_modified: AtomicUsize::new(0),
+        }
+    }
+
+    /// send no `ETag`, like a server that does not support conditional requests
+    pub fn without_validators(mut self) -> Self {
+        self.validators = false;
+        self
+    }
+
+    /// a config that scrapes this mock without a rate limit
+    pub fn config(self: &Arc<Self>) -> FeedConfig {
+        FeedConfig::default()
+            .with_base_url(self.base_url.clone())
+            .with_client(self.clone())
+            .with_rate_limiter(RateLimiter::unlimited())
+    }
+
+    /// every url requested so far, in order
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// number of requests answered with `304 Not Modified`
+    pub fn not_modified(&self) -> usize {
+        self.not_modified.load(Ordering::SeqCst)
+    }
+
+    /// the path and query of `url` when it is on this board
+    fn route(&self, url: &str) -> Result<Url, GetError> {
+        self.requests.lock().unwrap().push(url.to_string());
+        let not_found = || GetError::HttpStatus(StatusCode::NOT_FOUND);
+        let path = url.strip_prefix(&self.base_url).ok_or_else(not_found)?;
+        if !path.starts_with('/') {
+            return Err(not_found());
+        }
+        Url::parse(MOCK_BASE_URL)
+            .and_then(|base| base.join(path))
+            .map_err(|_| not_found())
+    }
+
+    fn page(&self, url: &str) -> Result<String, GetError> {
+        let route = self.route(url)?;
+        let query = |name| {
+            route
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        if route.path().ends_with("/boar/resAjax.php") {
+            let page = match query("skip").as_deref() {
+                None | Some("0") => LIST_PAGE,
+                Some(_) => EMPTY_PAGE,
+            };
+            return Ok(page.to_string());
+        }
+        if route.path().ends_with("/boar/vewAjax.php")
+            && query("i").is_some_and(|id| id.parse::<u32>().is_ok())
+        {
+            return Ok(INFO_PAGE.to_string());
+        }
+        Err(GetError::HttpStatus(StatusCode::NOT_FOUND))
+    }
+
+    fn file(&self, url: &str) -> Result<FileInfo, GetError> {
+        if self.route(url)?.path().ends_with("/fmapi/getFile.php") {
+            Ok(FileInfo {
+                size: Some(MOCK_ATTACHMENT_SIZE),
+                file_type: Some("application/pdf".to_string()),
+            })
+        } else {
+            Err(GetError::HttpStatus(StatusCode::NOT_FOUND))
+        }
+    }
+}
+
+impl HttpClient for MockBoard {
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<String, GetError>> {
+        Box::pin(async move { self.page(url) })
+    }
+
+    fn head<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<FileInfo, GetError>> {
+        Box::pin(async move { self.file(url) })
+    }
+
+    fn download<'a>(
+        &'a self,
+        url: &'a str,
+        max_size: usize,
+    ) -> BoxFuture<'a, Result<FileContent, GetError>> {
+        Box::pin(async move {
+            let info = self.file(url)?;
+            let size = MOCK_ATTACHMENT_SIZE as usize;
+            if size > max_size {
+                return Err(GetError::TooLarge(max_size));
+            }
+            Ok(FileContent {
+                file_type: info.file_type,
+                data: vec![b' '; size],
+            })
+        })
+    }
+
+    fn get_if_modified<'a>(
+        &'a self,
+        url: &'a str,
+        validators: &'a Validators,
+    ) -> BoxFuture<'a, Result<Conditional, GetError>> {
+        Box::pin(async move {
+            let body = self.page(url)?;
+            if !self.validators {
+                return Ok(Conditional::Modified {
+                    body,
+                    validators: Validators::default(),
+                });
+            }
+            let mut hasher = DefaultHasher::new();
+            body.hash(&mut hasher);
+            let etag = format!("\"{:x}\"", hasher.finish());
+            if validators.etag.as_ref() == Some(&etag) {
+                self.not_modified.fetch_add(1, Ordering::SeqCst);
+                return Ok(Conditional::NotModified);
+            }
+            Ok(Conditional::Modified {
+                body,
+                validators: Validators {
+                    etag: Some(etag),
+                    last_modified: None,
+                },
+            })
+        })
+    }
+}
@@ -1,11 +1,206 @@
 #![warn(missing_docs)]
 //! This crate holds some common data structures for both server and client
 
+use crate::feed_scraper::{
+    date_parser::parse_date,
+    target_parser::{parse_target, parse_targets},
+};
+use chrono::NaiveDate;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// a date as shown on the board, e.g. `2021/10/07(Thu)`, with the day it means.
+/// v1 and v2 serve the text as the board shows it; the day is used for v3, sorting and filtering.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BoardDate {
+    /// as shown on the board
+    pub text: String,
+    /// the day, when the text could be parsed
+    pub date: Option<NaiveDate>,
+}
+
+impl BoardDate {
+    /// a date shown as `text`. the day is `None` when `text` is not a date.
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        Self {
+            text: text.to_string(),
+            date: parse_date(text).ok(),
+        }
+    }
+}
+
+impl From<NaiveDate> for BoardDate {
+    /// the day written the way the board shows it
+    fn from(date: NaiveDate) -> Self {
+        Self {
+            text: date.format("%Y/%m/%d(%a)").to_string(),
+            date: Some(date),
+        }
+    }
+}
+
+impl serde::Serialize for BoardDate {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for BoardDate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(Self::parse(&text))
+    }
+}
+
+/// (de)serializes the attachments for the v2 api and storage.
+///
+/// `添付ファイル` keeps the old map of name to url for existing clients,
+/// and `添付ファイル一覧` holds the ordered list with the file type and size.
+/// data stored before the list existed is read from the map.
+mod board_attachments {
+    use super::Attachment;
+    use serde::{Deserialize as _, Deserializer, Serialize as _, Serializer};
+    use serde_derive::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize)]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    pub struct Fields {
+        /// 添付ファイル, name to url. only one of the attachments sharing a name is kept.
+        #[serde(rename = "添付ファイル")]
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        #[serde(default)]
+        map: BTreeMap<String, String>,
+        /// 添付ファイル in the order shown on the board
+        #[serde(rename = "添付ファイル一覧")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(default)]
+        list: Option<Vec<Attachment>>,
+    }
+
+    pub fn serialize<S: Serializer>(
+        attachments: &[Attachment],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Fields {
+            map: attachments
+                .iter()
+                .map(|attachment| (attachment.name.clone(), attachment.url.clone()))
+                .collect(),
+            list: (!attachments.is_empty()).then(|| attachments.to_vec()),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Attachment>, D::Error> {
+        let fields = Fields::deserialize(deserializer)?;
+        Ok(fields.list.unwrap_or_else(|| {
+            fields
+                .map
+                .into_iter()
+                .map(|(name, url)| Attachment::new(name, url))
+                .collect()
+        }))
+    }
+}
+
+/// guesses the mime type of a file from its name
+pub fn guess_file_type(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "zip" => "application/zip",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "txt" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+/// an attached file of a post
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Attachment {
+    /// file name
+    pub name: String,
+    /// where the board serves the file
+    pub url: String,
+    /// mime type, e.g. `application/pdf`
+    pub file_type: String,
+    /// size in bytes, if the board told it
+    pub size: Option<u64>,
+    /// text extracted from the file, if the server extracts attachments and the format is supported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+impl Attachment {
+    /// creates an attachment with the file type guessed from the name and no size
+    pub fn new(name: String, url: String) -> Self {
+        let file_type = guess_file_type(&name).to_string();
+        Self {
+            name,
+            url,
+            file_type,
+            size: None,
+            text: None,
+        }
+    }
+}
+
+/// an entry of 対象 like `CM(C1)[All]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Target {
+    /// code of the program, e.g. `CM`
+    pub program: String,
+    /// course in the parentheses, e.g. `C1`. the whole program when `None`.
+    pub course: Option<String>,
+    /// years in the brackets. every year when `None`, e.g. for `[All]`.
+    pub years: Option<Vec<u32>>,
+}
+
+impl Target {
+    /// parses an entry like `CM(C1)[All]`, `CM(C1)` or `CM[2]`.
+    /// full-width characters are accepted.
+    pub fn parse(entry: &str) -> Option<Self> {
+        parse_target(entry)
+    }
+
+    /// whether this entry includes the students selected by `query`.
+    ///
+    /// programs and courses are compared ignoring case.
+    /// a query without a course or years matches every course or year of the program,
+    /// and an entry without a course or years includes every course or year.
+    pub fn includes(&self, query: &Target) -> bool {
+        let course_matches = match (&self.course, &query.course) {
+            (Some(course), Some(query)) => course.eq_ignore_ascii_case(query),
+            _ => true,
+        };
+        let years_match = match (&self.years, &query.years) {
+            (Some(years), Some(query)) => query.iter().any(|year| years.contains(year)),
+            _ => true,
+        };
+        self.program.eq_ignore_ascii_case(&query.program) && course_matches && years_match
+    }
+}
+
 /// data for a post on feed
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Post {
     /// the id of the information. found in the tuat feed.
     #[serde(rename = "投稿ID")]
@@ -16,12 +211,28 @@ pub struct Post {
     /// 本文
     #[serde(rename = "本文")]
     pub contents: String,
+    /// 本文 as html, with only the formatting elements (links, emphasis, lists and tables) kept
+    /// and links made absolute
+    #[serde(rename = "本文HTML")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    pub contents_html: String,
+    /// 本文 as markdown, with links made absolute
+    #[serde(rename = "本文Markdown")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    pub contents_markdown: String,
     /// 最終更新日
     #[serde(rename = "最終更新日")]
-    pub updated_date: String,
+    #[cfg_attr(
+        feature = "openapi",
+        schema(value_type = String, example = "2021/10/07(Thu)")
+    )]
+    pub updated_date: BoardDate,
     /// 公開期間
     #[serde(rename = "公開期間")]
-    pub show_date: (String, String),
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub show_date: (BoardDate, BoardDate),
     ///担当者
     #[serde(rename = "担当者")]
     pub person_in_charge: String,
@@ -34,11 +245,13 @@ pub struct Post {
     /// 対象
     #[serde(rename = "対象")]
     pub target: String,
-    /// 添付ファイル
-    #[serde(rename = "添付ファイル")]
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
-    #[serde(default = "HashMap::default")]
-    pub attachment: HashMap<String, String>,
+    /// 添付ファイル in the order shown on the board
+    #[serde(flatten, with = "board_attachments")]
+    #[cfg_attr(
+        feature = "openapi",
+        schema(inline, value_type = board_attachments::Fields)
+    )]
+    pub attachments: Vec<Attachment>,
     /// その他のフィールド
     #[serde(rename = "その他")]
     #[serde(skip_serializing_if = "HashMap::is_empty")]
@@ -53,20 +266,56 @@ impl Post {
             post_id,
             title: String::new(),
             contents: String::new(),
-            updated_date: String::new(),
-            show_date: (String::new(), String::new()),
+            contents_html: String::new(),
+            contents_markdown: String::new(),
+            updated_date: BoardDate::default(),
+            show_date: (BoardDate::default(), BoardDate::default()),
             person_in_charge: String::new(),
             origin: String::new(),
             category: String::new(),
             target: String::new(),
-            attachment: HashMap::new(),
+            attachments: Vec::new(),
             other: HashMap::new(),
         }
     }
+
+    /// whether `other` shows the same thing on the board.
+    /// what the scraper adds to a post (the size and type of the attachments,
+    /// their extracted text and the formatted 本文) is not compared,
+    /// so a post is only seen as edited when the office edited it.
+    pub fn same_content(&self, other: &Post) -> bool {
+        self.post_id == other.post_id
+            && self.title == other.title
+            && self.contents == other.contents
+            && self.updated_date == other.updated_date
+            && self.show_date == other.show_date
+            && self.person_in_charge == other.person_in_charge
+            && self.origin == other.origin
+            && self.category == other.category
+            && self.target == other.target
+            && self.other == other.other
+            && self.attachments.len() == other.attachments.len()
+            && self
+                .attachments
+                .iter()
+                .zip(&other.attachments)
+                .all(|(a, b)| a.name == b.name && a.url == b.url)
+    }
+
+    /// the entries of 対象. entries that can not be parsed are left out.
+    pub fn targets(&self) -> Vec<Target> {
+        parse_targets(&self.target)
+    }
+
+    /// whether the post is aimed at the students selected by `query`
+    pub fn is_for(&self, query: &Target) -> bool {
+        self.targets().iter().any(|target| target.includes(query))
+    }
 }
 
 /// Compatibility layer for Post
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct PostCompatv1 {
     #[serde(rename = "id")]
     post_id: u32,
@@ -83,9 +332,9 @@ impl From<Post> for PostCompatv1 {
         post_compat.data.insert("本文".to_string(), post.contents);
         post_compat
             .data
-            .insert("最終更新日".to_string(), post.updated_date);
+            .insert("最終更新日".to_string(), post.updated_date.text);
         post_compat.data.insert("公開期間".to_string(), {
-            format!("{} 〜 {}", post.show_date.0, post.show_date.1)
+            format!("{} 〜 {}", post.show_date.0.text, post.show_date.1.text)
         });
         post_compat
             .data
@@ -95,9 +344,9 @@ impl From<Post> for PostCompatv1 {
             .data
             .insert("カテゴリー".to_string(), post.category);
         let attachment_string = post
-            .attachment
+            .attachments
             .iter()
-            .map(|(name, url)| format!("[{}]({})", name, url))
+            .map(|attachment| format!("[{}]({})", attachment.name, attachment.url))
             .collect::<Vec<String>>()
             .join("\n");
         if !attachment_string.is_empty() {
@@ -112,3 +361,294 @@ impl From<Post> for PostCompatv1 {
         post_compat
     }
 }
+
+/// an attachment of a post for the v3 api
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AttachmentV3 {
+    /// file name
+    pub name: String,
+    /// where the board serves the file
+    pub url: String,
+    /// mime type, e.g. `application/pdf`
+    pub file_type: String,
+    /// size in bytes, if known
+    pub size: Option<u64>,
+    /// text extracted from the file, e.g. the schedule inside a pdf
+    pub text: Option<String>,
+}
+
+/// Post for the v3 api.
+/// keys are in english and dates are ISO 8601 (`2021-10-07`).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PostV3 {
+    /// the id of the information. found in the tuat feed.
+    pub id: u32,
+    /// タイトル
+    pub title: String,
+    /// 本文
+    pub contents: String,
+    /// 本文 as sanitized html, with absolute links
+    pub contents_html: String,
+    /// 本文 as markdown, with absolute links
+    pub contents_markdown: String,
+    /// 最終更新日
+    pub updated_date: Option<NaiveDate>,
+    /// start of 公開期間
+    pub show_start: Option<NaiveDate>,
+    /// end of 公開期間
+    pub show_end: Option<NaiveDate>,
+    /// 担当者
+    pub person_in_charge: String,
+    /// 発信元
+    pub origin: String,
+    /// カテゴリー
+    pub category: String,
+    /// 対象, split into entries like `CM(C1)[All]`
+    pub target: Vec<String>,
+    /// the entries of 対象 that could be parsed
+    pub targets: Vec<Target>,
+    /// 添付ファイル in the order shown on the board.
+    /// the file at index `i` is also served by `/v2/attachments/{id}/{i}`.
+    pub attachments: Vec<AttachmentV3>,
+    /// その他のフィールド
+    pub other: HashMap<String, String>,
+}
+
+impl From<Post> for PostV3 {
+    fn from(post: Post) -> Self {
+        let targets = post.targets();
+        let target = post
+            .target
+            .split(" / ")
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::to_string)
+            .collect();
+        let attachments = post
+            .attachments
+            .into_iter()
+            .map(|attachment| AttachmentV3 {
+                name: attachment.name,
+                url: attachment.url,
+                file_type: attachment.file_type,
+                size: attachment.size,
+                text: attachment.text,
+            })
+            .collect();
+        PostV3 {
+            id: post.post_id,
+            title: post.title,
+            contents: post.contents,
+            contents_html: post.contents_html,
+            contents_markdown: post.contents_markdown,
+            updated_date: post.updated_date.date,
+            show_start: post.show_date.0.date,
+            show_end: post.show_date.1.date,
+            person_in_charge: post.person_in_charge,
+            origin: post.origin,
+            category: post.category,
+            target,
+            targets,
+            attachments,
+            other: post.other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Attachment, BoardDate, Post, PostCompatv1, PostV3, Target};
+    use chrono::NaiveDate;
+
+    fn dated_post() -> Post {
+        let mut post = Post::new(1);
+        post.updated_date = BoardDate::parse("2021/10/07(Thu)");
+        post.show_date = (
+            BoardDate::parse("2021/10/07(Thu)"),
+            BoardDate::parse("2022/03/31(Thu)"),
+        );
+        post
+    }
+
+    #[test]
+    fn v2_keeps_board_dates() {
+        let json = serde_json::to_value(dated_post()).unwrap();
+        assert_eq!(json["最終更新日"], "2021/10/07(Thu)");
+        assert_eq!(
+            json["公開期間"],
+            serde_json::json!(["2021/10/07(Thu)", "2022/03/31(Thu)"])
+        );
+
+        let post: Post = serde_json::from_value(json).unwrap();
+        assert_eq!(post, dated_post());
+
+        let json = serde_json::to_value(Post::new(1)).unwrap();
+        assert_eq!(json["最終更新日"], "");
+        assert_eq!(json["公開期間"], serde_json::json!(["", ""]));
+        let post: Post = serde_json::from_value(json).unwrap();
+        assert_eq!(post, Post::new(1));
+    }
+
+    #[test]
+    fn v2_lists_attachments() {
+        let mut post = Post::new(1);
+        for name in ["b.pdf", "a.pdf", "a.pdf"] {
+            post.attachments.push(Attachment::new(
+                name.to_string(),
+                format!("http://example.com/{}", post.attachments.len()),
+            ));
+        }
+        post.attachments[0].size = Some(1024);
+        post.attachments[1].text = Some("日程".to_string());
+
+        let json = serde_json::to_value(&post).unwrap();
+        assert_eq!(
+            json["添付ファイル"],
+            serde_json::json!({"a.pdf": "http://example.com/2", "b.pdf": "http://example.com/0"})
+        );
+        assert_eq!(json["添付ファイル一覧"].as_array().unwrap().len(), 3);
+        assert_eq!(json["添付ファイル一覧"][0]["size"], 1024);
+        assert!(json["添付ファイル一覧"][0].get("text").is_none());
+        assert_eq!(json["添付ファイル一覧"][1]["text"], "日程");
+        let parsed: Post = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, post);
+
+        // posts stored before the list only have the map
+        let mut json = serde_json::to_value(Post::new(1)).unwrap();
+        assert!(json.get("添付ファイル").is_none());
+        json["添付ファイル"] =
+            serde_json::json!({"b.pdf": "http://example.com/b", "a.txt": "http://example.com/a"});
+        let parsed: Post = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            parsed.attachments,
+            vec![
+                Attachment::new("a.txt".to_string(), "http://example.com/a".to_string()),
+                Attachment::new("b.pdf".to_string(), "http://example.com/b".to_string()),
+            ]
+        );
+        assert_eq!(parsed.attachments[0].file_type, "text/plain");
+    }
+
+    #[test]
+    fn v1_keeps_board_dates() {
+        let json = serde_json::to_value(PostCompatv1::from(dated_post())).unwrap();
+        assert_eq!(json["data"]["最終更新日"], "2021/10/07(Thu)");
+        assert_eq!(
+            json["data"]["公開期間"],
+            "2021/10/07(Thu) 〜 2022/03/31(Thu)"
+        );
+    }
+
+    #[test]
+    fn board_dates_are_kept_as_shown() {
+        let mut post = Post::new(1);
+        post.updated_date = BoardDate::parse("2021年10月7日（木）");
+        post.show_date = (BoardDate::parse("2021/10/7"), BoardDate::parse("未定"));
+        assert_eq!(post.updated_date.date, NaiveDate::from_ymd_opt(2021, 10, 7));
+        assert_eq!(post.show_date.1.date, None);
+
+        let json = serde_json::to_value(&post).unwrap();
+        assert_eq!(json["最終更新日"], "2021年10月7日（木）");
+        assert_eq!(json["公開期間"], serde_json::json!(["2021/10/7", "未定"]));
+        assert_eq!(serde_json::from_value::<Post>(json).unwrap(), post);
+
+        let json = serde_json::to_value(PostCompatv1::from(post.clone())).unwrap();
+        assert_eq!(json["data"]["公開期間"], "2021/10/7 〜 未定");
+
+        let json = serde_json::to_value(PostV3::from(post)).unwrap();
+        assert_eq!(json["updated_date"], "2021-10-07");
+        assert_eq!(json["show_start"], "2021-10-07");
+        assert!(json["show_end"].is_null());
+    }
+
+    #[test]
+    fn v3_uses_iso_dates() {
+        let json = serde_json::to_value(PostV3::from(dated_post())).unwrap();
+        assert_eq!(json["id"], 1);
+        assert_eq!(json["updated_date"], "2021-10-07");
+        assert_eq!(json["show_start"], "2021-10-07");
+        assert_eq!(json["show_end"], "2022-03-31");
+        assert!(
+            serde_json::to_value(PostV3::from(Post::new(1))).unwrap()["updated_date"].is_null()
+        );
+    }
+
+    #[test]
+    fn v3_structures_target_and_attachments() {
+        let mut post = Post::new(1);
+        post.target = "LM[All] / CM(C1)[All] / SM(CS)[All]".to_string();
+        post.attachments.push(Attachment::new(
+            "b.pdf".to_string(),
+            "http://example.com/b".to_string(),
+        ));
+        post.attachments.push(Attachment::new(
+            "a.png".to_string(),
+            "http://example.com/a".to_string(),
+        ));
+
+        let json = serde_json::to_value(PostV3::from(post)).unwrap();
+        assert_eq!(
+            json["target"],
+            serde_json::json!(["LM[All]", "CM(C1)[All]", "SM(CS)[All]"])
+        );
+        assert_eq!(
+            json["attachments"],
+            serde_json::json!([
+                {"name": "b.pdf", "url": "http://example.com/b", "file_type": "application/pdf", "size": null, "text": null},
+                {"name": "a.png", "url": "http://example.com/a", "file_type": "image/png", "size": null, "text": null},
+            ])
+        );
+        assert_eq!(
+            json["targets"][1],
+            serde_json::json!({"program": "CM", "course": "C1", "years": null})
+        );
+        assert_eq!(
+            serde_json::to_value(PostV3::from(Post::new(1))).unwrap()["target"],
+            serde_json::json!([])
+        );
+    }
+
+    #[test]
+    fn targeting() {
+        let mut post = Post::new(1);
+        post.target = "LM[All] / CM(C1)[All] / B(L)[1,2]".to_string();
+        let is_for = |query: &str| post.is_for(&Target::parse(query).unwrap());
+
+        assert!(is_for("LM"));
+        assert!(is_for("lm[3]"));
+        assert!(is_for("CM"));
+        assert!(is_for("CM(C1)[2]"));
+        assert!(!is_for("CM(C2)"));
+        assert!(is_for("B(L)[2]"));
+        assert!(!is_for("B(L)[3]"));
+        assert!(is_for("B[1,3]"));
+        assert!(!is_for("SM"));
+        assert!(!Post::new(2).is_for(&Target::parse("LM").unwrap()));
+    }
+
+    #[test]
+    fn same_content() {
+        let mut post = dated_post();
+        post.attachments = vec![Attachment::new(
+            "schedule.pdf".to_string(),
+            "https://example.com/schedule.pdf".to_string(),
+        )];
+        let mut described = post.clone();
+        described.attachments[0].size = Some(1024);
+        described.attachments[0].text = Some("schedule".to_string());
+        described.contents_html = "<p>schedule</p>".to_string();
+        assert!(post.same_content(&described));
+
+        let mut edited = described.clone();
+        edited.title = "schedule (updated)".to_string();
+        assert!(!post.same_content(&edited));
+        let mut edited = described.clone();
+        edited.attachments[0].url = "https://example.com/schedule2.pdf".to_string();
+        assert!(!post.same_content(&edited));
+        let mut edited = described;
+        edited.attachments.clear();
+        assert!(!post.same_content(&edited));
+    }
+}
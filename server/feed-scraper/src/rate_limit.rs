@@ -0,0 +1,174 @@
+//! keeps the requests to the board within a rate
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use thiserror::Error;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+/// default number of requests started each second, to go easy on the board
+pub const DEFAULT_REQUESTS_PER_SECOND: f64 = 2.0;
+
+/// default number of requests running at once
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 4;
+
+/// the error that happens when a rate limit can not be kept
+#[derive(Error, Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitError {
+    /// the rate is not a positive number, or so small that the interval does not fit a [`Duration`]
+    #[error("invalid number of requests per second: {0:?}")]
+    InvalidRate(f64),
+    /// no request could ever run
+    #[error("at least one request must be allowed in flight")]
+    NoneInFlight,
+}
+
+/// limits how often requests start and how many are in flight at once.
+/// clones share the limit, so one limiter can be shared by all the feeds of a t-board.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    /// time between the starts of two requests
+    interval: Duration,
+    in_flight: Arc<Semaphore>,
+    /// when the next request may start
+    next: Arc<Mutex<Instant>>,
+}
+
+impl Default for RateLimiter {
+    /// [`DEFAULT_REQUESTS_PER_SECOND`] with at most [`DEFAULT_MAX_IN_FLIGHT`] at once
+    fn default() -> Self {
+        Self::new(DEFAULT_REQUESTS_PER_SECOND, DEFAULT_MAX_IN_FLIGHT)
+            .expect("the defaults are valid")
+    }
+}
+
+impl RateLimiter {
+    /// at most `requests_per_second` requests start each second,
+    /// and at most `max_in_flight` requests run at once.
+    /// fails when `requests_per_second` is not a positive finite number small enough to
+    /// wait for, or when `max_in_flight` is 0.
+    pub fn new(requests_per_second: f64, max_in_flight: usize) -> Result<Self, RateLimitError> {
+        if !requests_per_second.is_finite() || requests_per_second <= 0.0 {
+            return Err(RateLimitError::InvalidRate(requests_per_second));
+        }
+        let interval = Duration::try_from_secs_f64(1.0 / requests_per_second)
+            .map_err(|_| RateLimitError::InvalidRate(requests_per_second))?;
+        if max_in_flight == 0 {
+            return Err(RateLimitError::NoneInFlight);
+        }
+        Ok(Self::with_interval(
+            interval,
+            max_in_flight.min(Semaphore::MAX_PERMITS),
+        ))
+    }
+
+    /// no limit at all, e.g. for a mock board
+    pub fn unlimited() -> Self {
+        Self::with_interval(Duration::ZERO, Semaphore::MAX_PERMITS)
+    }
+
+    fn with_interval(interval: Duration, max_in_flight: usize) -> Self {
+        Self {
+            interval,
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+            next: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// waits until a request may start.
+    /// the request counts as in flight until the permit is dropped.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        let permit = self
+            .in_flight
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let start = (*next).max(Instant::now());
+            *next = start + self.interval;
+            start
+        };
+        tokio::time::sleep_until(start).await;
+        permit
+    }
+
+    /// runs `request` once the limit allows
+    pub async fn run<T>(&self, request: impl Future<Output = T>) -> T {
+        let _permit = self.acquire().await;
+        request.await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RateLimitError, RateLimiter};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::time::Instant;
+
+    #[tokio::test]
+    async fn spaces_requests() {
+        let limiter = RateLimiter::new(20.0, 10).unwrap();
+        let start = Instant::now();
+        let ends = futures_util::future::join_all(
+            (0..5).map(|_| limiter.run(async { Instant::now() - start })),
+        )
+        .await;
+        // the first request starts at once, the others 50ms apart
+        assert!(ends[0] < Duration::from_millis(40));
+        assert!(ends.iter().max().unwrap() >= &Duration::from_millis(200));
+
+        let unlimited = RateLimiter::unlimited();
+        let start = Instant::now();
+        futures_util::future::join_all((0..5).map(|_| unlimited.run(async {}))).await;
+        assert!(start.elapsed() < Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn limits_in_flight() {
+        let limiter = RateLimiter::new(1000.0, 2).unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        futures_util::future::join_all((0..6).map(|_| {
+            limiter.run(async {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        }))
+        .await;
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn rejects_bad_limits() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e-300] {
+            assert!(
+                matches!(
+                    RateLimiter::new(rate, 1),
+                    Err(RateLimitError::InvalidRate(_))
+                ),
+                "{}",
+                rate
+            );
+        }
+        assert_eq!(
+            RateLimiter::new(1.0, 0).unwrap_err(),
+            RateLimitError::NoneInFlight
+        );
+    }
+}
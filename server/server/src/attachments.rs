@@ -0,0 +1,464 @@
+use axum::body::Bytes;
+use futures_util::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tracing::debug;
+use tuat_feed_scraper::{post::Attachment, FeedConfig, GetError, RateLimiter};
+use url::Url;
+
+/// largest file passed through by default
+const DEFAULT_MAX_FILE_SIZE: usize = 32 * 1024 * 1024;
+/// total size of the files kept in memory
+const MAX_CACHE_SIZE: usize = 128 * 1024 * 1024;
+/// how long a downloaded file is served without asking the board again
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// downloads one client may wait for at once by default
+const DEFAULT_MAX_PER_CLIENT: usize = 2;
+
+/// the error that happens when downloading an attachment from the board
+#[derive(Error, Debug, Clone)]
+pub enum AttachmentError {
+    /// the board could not be reached or did not answer in time
+    #[error("request to the board failed")]
+    RequestError(#[source] Arc<GetError>),
+    /// the board answered with an error
+    #[error("the board answered with status {0}")]
+    HttpStatus(u16),
+    /// the file is larger than what is passed through
+    #[error("the file is larger than {0} bytes")]
+    TooLarge(usize),
+    /// the url is not on the host of the board of the post
+    #[error("{0} is not on the board")]
+    NotOnBoard(String),
+    /// the client already waits for as many downloads as it may
+    #[error("too many downloads at once")]
+    TooManyDownloads,
+}
+
+impl From<GetError> for AttachmentError {
+    fn from(e: GetError) -> Self {
+        match e {
+            GetError::HttpStatus(status) => Self::HttpStatus(status.as_u16()),
+            GetError::TooLarge(max_file_size) => Self::TooLarge(max_file_size),
+            e => Self::RequestError(Arc::new(e)),
+        }
+    }
+}
+
+/// a downloaded attachment
+#[derive(Debug, Clone)]
+pub struct AttachmentFile {
+    /// mime type of the file
+    pub content_type: String,
+    /// contents of the file
+    pub data: Bytes,
+    fetched_at: Instant,
+}
+
+/// recently downloaded files by url, dropping the oldest first
+#[derive(Default)]
+struct Cache {
+    files: HashMap<String, AttachmentFile>,
+    order: VecDeque<String>,
+    size: usize,
+}
+
+impl Cache {
+    fn get(&self, url: &str) -> Option<AttachmentFile> {
+        self.files
+            .get(url)
+            .filter(|file| file.fetched_at.elapsed() < CACHE_TTL)
+            .cloned()
+    }
+
+    fn remove(&mut self, url: &str) {
+        if let Some(file) = self.files.remove(url) {
+            self.size -= file.data.len();
+            self.order.retain(|cached| cached != url);
+        }
+    }
+
+    fn insert(&mut self, url: String, file: AttachmentFile) {
+        self.remove(&url);
+        while self.size + file.data.len() > MAX_CACHE_SIZE {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(old) = self.files.remove(&oldest) {
+                self.size -= old.data.len();
+            }
+        }
+        self.size += file.data.len();
+        self.order.push_back(url.clone());
+        self.files.insert(url, file);
+    }
+}
+
+/// a download in progress, awaited by every request for the same file
+type Download = Shared<BoxFuture<'static, Result<AttachmentFile, AttachmentError>>>;
+
+/// downloads attachments from the board and keeps recent ones in memory,
+/// so clients never have to talk to the board themselves
+pub struct AttachmentProxy {
+    /// client to download with
+    config: FeedConfig,
+    /// limit of the downloads, apart from the one of the feeds
+    /// so that downloads never hold up the updates
+    rate_limiter: RateLimiter,
+    cache: Arc<Mutex<Cache>>,
+    /// downloads in progress by url
+    in_flight: Arc<Mutex<HashMap<String, Download>>>,
+    /// fetches each client waits for
+    per_client: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    max_per_client: usize,
+    max_file_size: usize,
+}
+
+/// a fetch counted against the limit of a client until dropped
+struct ClientSlot<'a> {
+    per_client: &'a Mutex<HashMap<IpAddr, usize>>,
+    client: IpAddr,
+}
+
+impl Drop for ClientSlot<'_> {
+    fn drop(&mut self) {
+        let mut per_client = self.per_client.lock().unwrap();
+        if let Some(count) = per_client.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                per_client.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// whether `url` is on the same host and port as `board_url`
+fn on_board(url: &str, board_url: &str) -> bool {
+    let (Ok(url), Ok(board_url)) = (Url::parse(url), Url::parse(board_url)) else {
+        return false;
+    };
+    url.host_str().is_some()
+        && url.host_str() == board_url.host_str()
+        && url.port_or_known_default() == board_url.port_or_known_default()
+}
+
+impl fmt::Debug for AttachmentProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AttachmentProxy")
+            .field("max_per_client", &self.max_per_client)
+            .field("max_file_size", &self.max_file_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for AttachmentProxy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// downloads `url` through the client of `config` within `rate_limiter`,
+/// refusing files larger than `max_file_size` bytes.
+/// `file_type` is used when the board does not tell a specific type.
+async fn download(
+    config: &FeedConfig,
+    rate_limiter: &RateLimiter,
+    url: &str,
+    file_type: &str,
+    max_file_size: usize,
+) -> Result<AttachmentFile, AttachmentError> {
+    debug!("downloading attachment {}", url);
+    let file = rate_limiter
+        .run(config.client().download(url, max_file_size))
+        .await?;
+    let content_type = file
+        .file_type
+        .filter(|content_type| content_type != "application/octet-stream")
+        .unwrap_or_else(|| file_type.to_string());
+    Ok(AttachmentFile {
+        content_type,
+        data: file.data.into(),
+        fetched_at: Instant::now(),
+    })
+}
+
+impl AttachmentProxy {
+    /// creates a proxy with an empty cache
+    pub fn new() -> Self {
+        Self {
+            config: FeedConfig::default(),
+            rate_limiter: RateLimiter::default(),
+            cache: Arc::default(),
+            in_flight: Arc::default(),
+            per_client: Arc::default(),
+            max_per_client: DEFAULT_MAX_PER_CLIENT,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+
+    /// refuse files larger than `max_file_size` bytes
+    pub fn with_max_file_size(mut self, max_file_size: usize) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// download through the client of `config`, e.g. the one of the feeds.
+    /// the rate limit of `config` is not used; see [`Self::with_rate_limiter`].
+    pub fn with_config(mut self, config: FeedConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// limit the downloads with `rate_limiter`.
+    /// defaults to a limit of its own, so downloads do not hold up the updates of the feeds.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// let each client wait for at most `max_per_client` files at once
+    pub fn with_max_per_client(mut self, max_per_client: usize) -> Self {
+        self.max_per_client = max_per_client;
+        self
+    }
+
+    /// [`Self::fetch`] for `client`, refused when it already waits for as many files as it may
+    pub async fn fetch_for(
+        &self,
+        client: IpAddr,
+        attachment: &Attachment,
+        board_url: &str,
+    ) -> Result<AttachmentFile, AttachmentError> {
+        let _slot = {
+            let mut per_client = self.per_client.lock().unwrap();
+            let count = per_client.entry(client).or_default();
+            if *count >= self.max_per_client {
+                return Err(AttachmentError::TooManyDownloads);
+            }
+            *count += 1;
+            ClientSlot {
+                per_client: &self.per_client,
+                client,
+            }
+        };
+        self.fetch(attachment, board_url).await
+    }
+
+    /// the file of `attachment` on the board at `board_url`, from the cache or downloaded.
+    /// files on other hosts than the board are refused.
+    /// concurrent requests for a file that is not cached share one download.
+    pub async fn fetch(
+        &self,
+        attachment: &Attachment,
+        board_url: &str,
+    ) -> Result<AttachmentFile, AttachmentError> {
+        if !on_board(&attachment.url, board_url) {
+            return Err(AttachmentError::NotOnBoard(attachment.url.clone()));
+        }
+        let download = {
+            let cache = self.cache.lock().unwrap();
+            if let Some(file) = cache.get(&attachment.url) {
+                return Ok(file);
+            }
+            self.in_flight
+                .lock()
+                .unwrap()
+                .entry(attachment.url.clone())
+                .or_insert_with(|| self.download(attachment))
+                .clone()
+        };
+        download.await
+    }
+
+    /// starts downloading `attachment`.
+    /// the file is cached and the download forgotten once it is done.
+    fn download(&self, attachment: &Attachment) -> Download {
+        let config = self.config.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let cache = self.cache.clone();
+        let in_flight = self.in_flight.clone();
+        let url = attachment.url.clone();
+        let file_type = attachment.file_type.clone();
+        let max_file_size = self.max_file_size;
+        async move {
+            let result = download(&config, &rate_limiter, &url, &file_type, max_file_size).await;
+            if let Ok(file) = &result {
+                cache.lock().unwrap().insert(url.clone(), file.clone());
+            }
+            in_flight.lock().unwrap().remove(&url);
+            result
+        }
+        .boxed()
+        .shared()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AttachmentError, AttachmentProxy};
+    use axum::{extract::State, routing::get, Router};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tuat_feed_scraper::{post::Attachment, FeedConfig, RateLimiter, ReqwestClient};
+
+    /// serves `/file.pdf` as octet-stream, counting the requests.
+    /// `/slow.pdf` takes a moment and `/stalled.pdf` never answers.
+    async fn board() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/file.pdf",
+                get(|State(requests): State<Arc<AtomicUsize>>| async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    (
+                        [("content-type", "application/octet-stream")],
+                        "%PDF-1.4 dummy",
+                    )
+                }),
+            )
+            .route(
+                "/slow.pdf",
+                get(|State(requests): State<Arc<AtomicUsize>>| async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    "%PDF-1.4 slow"
+                }),
+            )
+            .route(
+                "/stalled.pdf",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    "%PDF-1.4 stalled"
+                }),
+            )
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}", address), requests)
+    }
+
+    #[tokio::test]
+    async fn downloads_and_caches() {
+        let (base, requests) = board().await;
+        let proxy = AttachmentProxy::new();
+        let attachment = Attachment::new("講義.pdf".to_string(), format!("{}/file.pdf", base));
+
+        let file = proxy.fetch(&attachment, &base).await.unwrap();
+        assert_eq!(file.content_type, "application/pdf");
+        assert_eq!(&file.data[..], b"%PDF-1.4 dummy");
+        proxy.fetch(&attachment, &base).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let missing = Attachment::new("a.pdf".to_string(), format!("{}/missing.pdf", base));
+        assert!(matches!(
+            proxy.fetch(&missing, &base).await,
+            Err(AttachmentError::HttpStatus(404))
+        ));
+
+        let small = AttachmentProxy::new().with_max_file_size(4);
+        assert!(matches!(
+            small.fetch(&attachment, &base).await,
+            Err(AttachmentError::TooLarge(4))
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_share_a_download() {
+        let (base, requests) = board().await;
+        let proxy = AttachmentProxy::new();
+        let attachment = Attachment::new("講義.pdf".to_string(), format!("{}/slow.pdf", base));
+
+        let files =
+            futures_util::future::join_all((0..5).map(|_| proxy.fetch(&attachment, &base))).await;
+        assert!(files.iter().all(|file| file
+            .as_ref()
+            .is_ok_and(|file| &file.data[..] == b"%PDF-1.4 slow")));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(proxy.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stalled_downloads_time_out() {
+        let (base, _) = board().await;
+        let config = FeedConfig::default()
+            .with_client(Arc::new(ReqwestClient::new(Duration::from_millis(50))));
+        let proxy = AttachmentProxy::new().with_config(config);
+        let attachment = Attachment::new("講義.pdf".to_string(), format!("{}/stalled.pdf", base));
+
+        let result = tokio::time::timeout(Duration::from_secs(5), proxy.fetch(&attachment, &base))
+            .await
+            .expect("the download should time out");
+        assert!(matches!(result, Err(AttachmentError::RequestError(_))));
+        assert!(proxy.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_files_on_the_board() {
+        let (base, requests) = board().await;
+        let proxy = AttachmentProxy::new();
+        let attachment = Attachment::new("講義.pdf".to_string(), format!("{}/file.pdf", base));
+
+        for board_url in [
+            "http://t-board.office.tuat.ac.jp/T/boar/",
+            "http://127.0.0.1:1/",
+        ] {
+            assert!(matches!(
+                proxy.fetch(&attachment, board_url).await,
+                Err(AttachmentError::NotOnBoard(_))
+            ));
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn limits_downloads_per_client() {
+        let (base, _) = board().await;
+        let proxy = AttachmentProxy::new().with_max_per_client(1);
+        let attachment = Attachment::new("講義.pdf".to_string(), format!("{}/slow.pdf", base));
+        let client = "192.0.2.1".parse().unwrap();
+        let other = "192.0.2.2".parse().unwrap();
+
+        let (first, second, third) = tokio::join!(
+            proxy.fetch_for(client, &attachment, &base),
+            proxy.fetch_for(client, &attachment, &base),
+            proxy.fetch_for(other, &attachment, &base),
+        );
+        assert!(first.is_ok());
+        assert!(matches!(second, Err(AttachmentError::TooManyDownloads)));
+        assert!(third.is_ok());
+        // the slot is given back once the download is done
+        assert!(proxy.fetch_for(client, &attachment, &base).await.is_ok());
+        assert!(proxy.per_client.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn downloads_do_not_wait_for_the_feeds() {
+        let (base, _) = board().await;
+        let feeds = RateLimiter::new(1000.0, 1).unwrap();
+        let config = FeedConfig::default().with_rate_limiter(feeds.clone());
+        let proxy = AttachmentProxy::new().with_config(config);
+        let attachment = Attachment::new("講義.pdf".to_string(), format!("{}/file.pdf", base));
+
+        // an update of the feeds takes the only request it may make
+        let _update = feeds.acquire().await;
+        let result = tokio::time::timeout(Duration::from_secs(5), proxy.fetch(&attachment, &base))
+            .await
+            .expect("the download should not wait for the feeds");
+        assert!(result.is_ok());
+    }
+}
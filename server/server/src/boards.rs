@@ -0,0 +1,208 @@
+use crate::state::{FeedState, ServerState};
+use serde::{Deserialize as _, Deserializer};
+use std::{collections::HashSet, path::Path};
+use thiserror::Error;
+use tuat_feed_scraper::board::Board;
+
+/// the error that happens when loading the board registry
+#[derive(Error, Debug)]
+pub enum BoardConfigError {
+    /// the config file could not be read
+    #[error("could not read the board config")]
+    IoError(#[from] std::io::Error),
+    /// the config file is not a json list of boards
+    #[error("invalid board config")]
+    JsonError(#[from] serde_json::Error),
+    /// no board is configured
+    #[error("no board is configured")]
+    Empty,
+    /// the id can not be used as a key in storage and urls
+    #[error("invalid board id {0:?}")]
+    InvalidId(String),
+    /// two boards share an id
+    #[error("board id {0:?} is used more than once")]
+    DuplicateId(String),
+}
+
+/// the error that happens when a query names a board, group or category that is not registered
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SelectionError {
+    /// no board has this id
+    #[error("unknown board {0:?}")]
+    UnknownBoard(String),
+    /// no board is in this group
+    #[error("unknown group {0:?}")]
+    UnknownGroup(String),
+    /// no board is of this category
+    #[error("unknown category {0:?}")]
+    UnknownCategory(String),
+}
+
+/// checks that there is a board and that every id is unique and made of `[A-Za-z0-9_-]`
+pub fn validate(boards: &[Board]) -> Result<(), BoardConfigError> {
+    if boards.is_empty() {
+        return Err(BoardConfigError::Empty);
+    }
+    let mut ids = HashSet::new();
+    for board in boards {
+        let valid = !board.id.is_empty()
+            && board
+                .id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(BoardConfigError::InvalidId(board.id.clone()));
+        }
+        if !ids.insert(board.id.as_str()) {
+            return Err(BoardConfigError::DuplicateId(board.id.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// whether the `group` or `category` of a board is `selected`, ignoring ascii case.
+/// `None` selects every board.
+pub fn selects(selected: Option<&str>, value: &str) -> bool {
+    selected.is_none_or(|selected| selected.trim().eq_ignore_ascii_case(value))
+}
+
+/// the feeds of the board `board`, of `group` and of `category`, in the order of the registry.
+/// `None` selects every board. fails when a given one matches no board at all,
+/// so that a typo is not mistaken for a board without posts.
+pub fn select_feeds<'a>(
+    state: &'a ServerState,
+    board: Option<&str>,
+    group: Option<&'a str>,
+    category: Option<&'a str>,
+) -> Result<Vec<&'a FeedState>, SelectionError> {
+    let feeds = state.feeds();
+    if let Some(board) = board {
+        if !feeds.iter().any(|feed| feed.id() == board) {
+            return Err(SelectionError::UnknownBoard(board.to_string()));
+        }
+    }
+    if let Some(group) = group {
+        if !feeds.iter().any(|feed| selects(Some(group), feed.group())) {
+            return Err(SelectionError::UnknownGroup(group.to_string()));
+        }
+    }
+    if let Some(category) = category {
+        if !feeds
+            .iter()
+            .any(|feed| selects(Some(category), feed.category()))
+        {
+            return Err(SelectionError::UnknownCategory(category.to_string()));
+        }
+    }
+    Ok(state
+        .feeds_of(group, category)
+        .filter(|feed| board.is_none_or(|board| feed.id() == board))
+        .collect())
+}
+
+/// parses a `category` query. `all` (ignoring case) or an empty query selects every category.
+pub fn deserialize_category<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let category = Option::<String>::deserialize(deserializer)?;
+    Ok(category.filter(|category| {
+        let category = category.trim();
+        !category.is_empty() && !category.eq_ignore_ascii_case("all")
+    }))
+}
+
+/// parses a json list of boards, like
+/// `[{"id": "technology_academic", "name": "工学部 教務情報", "group": "technology",
+/// "category": "academic", "feed_url": "...", "info_url": "...", "board_url": "..."}]`
+pub fn parse_boards(json: &str) -> Result<Vec<Board>, BoardConfigError> {
+    let boards: Vec<Board> = serde_json::from_str(json)?;
+    validate(&boards)?;
+    Ok(boards)
+}
+
+/// reads the board registry from the json file at `path`
+pub fn load_boards(path: impl AsRef<Path>) -> Result<Vec<Board>, BoardConfigError> {
+    parse_boards(&std::fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_boards, select_feeds, selects, validate, BoardConfigError, SelectionError};
+    use crate::state::ServerState;
+    use tuat_feed_scraper::board::Board;
+
+    #[test]
+    fn defaults_are_valid() {
+        validate(&Board::defaults()).unwrap();
+        let json = serde_json::to_string(&Board::defaults()).unwrap();
+        assert_eq!(parse_boards(&json).unwrap(), Board::defaults());
+    }
+
+    #[test]
+    fn selection_ignores_case() {
+        assert!(selects(None, "technology"));
+        assert!(selects(Some("technology"), "technology"));
+        assert!(selects(Some("Technology"), "technology"));
+        assert!(!selects(Some("agriculture"), "technology"));
+        assert!(!selects(Some(""), "technology"));
+    }
+
+    #[test]
+    fn selection() {
+        let state = ServerState::init();
+        let ids = |board, group, category| {
+            select_feeds(&state, board, group, category).map(|feeds| {
+                feeds
+                    .iter()
+                    .map(|feed| feed.id().to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(ids(None, None, None).unwrap().len(), 4);
+        assert_eq!(
+            ids(None, Some("Agriculture"), None).unwrap(),
+            vec!["agriculture_academic", "agriculture_campus"]
+        );
+        assert_eq!(
+            ids(Some("technology_campus"), None, Some("campus")).unwrap(),
+            vec!["technology_campus"]
+        );
+        // known, but not together
+        assert!(ids(Some("technology_campus"), Some("agriculture"), None)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            ids(Some("technology"), None, None),
+            Err(SelectionError::UnknownBoard("technology".to_string()))
+        );
+        assert_eq!(
+            ids(None, Some("graduate"), None),
+            Err(SelectionError::UnknownGroup("graduate".to_string()))
+        );
+        assert_eq!(
+            ids(None, None, Some("all")),
+            Err(SelectionError::UnknownCategory("all".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_ids() {
+        let mut boards = Board::defaults();
+        boards[1].id = boards[0].id.clone();
+        assert!(matches!(
+            validate(&boards),
+            Err(BoardConfigError::DuplicateId(id)) if id == "technology_academic"
+        ));
+        boards[1].id = "工学府".to_string();
+        assert!(matches!(
+            validate(&boards),
+            Err(BoardConfigError::InvalidId(_))
+        ));
+        assert!(matches!(validate(&[]), Err(BoardConfigError::Empty)));
+        assert!(matches!(
+            parse_boards(r#"[{"id": "a"}]"#),
+            Err(BoardConfigError::JsonError(_))
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{
+    search::normalize_char,
+    syndication::{description, FeedItem},
+};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use regex::Regex;
+use std::{collections::BTreeSet, sync::OnceLock};
+use tuat_feed_scraper::post::Post;
+
+/// maximum length of a line in octets, not counting the line break
+const MAX_LINE_LENGTH: usize = 75;
+
+/// dates like `2021年10月7日`, `2021/10/07`, `10月7日` and `10/7(木)`
+fn date_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"(?:(\d{4})\s*[年/\-.]\s*)?(\d{1,2})\s*([月/\-.])\s*(\d{1,2})\s*(日|\()?")
+            .unwrap()
+    })
+}
+
+/// the date on `month`/`day` closest to `reference`
+fn closest_date(month: u32, day: u32, reference: NaiveDate) -> Option<NaiveDate> {
+    [reference.year() - 1, reference.year(), reference.year() + 1]
+        .into_iter()
+        .filter_map(|year| NaiveDate::from_ymd_opt(year, month, day))
+        .min_by_key(|date| (*date - reference).num_days().abs())
+}
+
+/// dates mentioned in `text`.
+///
+/// a date without a year is taken to be the one closest to `reference`, and is only
+/// detected when it is written like `10月7日` or followed by the day of the week like `10/7(木)`.
+pub fn find_dates(text: &str, reference: Option<NaiveDate>) -> BTreeSet<NaiveDate> {
+    let text = text.chars().map(normalize_char).collect::<String>();
+    let mut dates = BTreeSet::new();
+    for captures in date_pattern().captures_iter(&text) {
+        let number = |i: usize| captures.get(i).and_then(|m| m.as_str().parse::<u32>().ok());
+        let (Some(month), Some(day)) = (number(2), number(4)) else {
+            continue;
+        };
+        let date = match number(1) {
+            Some(year) => NaiveDate::from_ymd_opt(year as i32, month, day),
+            None => {
+                let separator = &captures[3];
+                let suffix = captures.get(5).map(|m| m.as_str());
+                let written_as_date =
+                    matches!((separator, suffix), ("月", Some("日")) | ("/", Some("(")));
+                match reference {
+                    Some(reference) if written_as_date => closest_date(month, day, reference),
+                    _ => None,
+                }
+            }
+        };
+        dates.extend(date);
+    }
+    dates
+}
+
+/// escapes a TEXT value
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// appends a content line, folded so that no line is longer than 75 octets
+fn push_line(calendar: &mut String, line: &str) {
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            calendar.push_str("\r\n ");
+            // the leading space counts
+            length = 1;
+        }
+        calendar.push(c);
+        length += c.len_utf8();
+    }
+    calendar.push_str("\r\n");
+}
+
+fn format_date(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+/// appends an all day event from `start` to `end` (inclusive)
+fn push_event(
+    calendar: &mut String,
+    uid: &str,
+    stamp: &str,
+    (start, end): (NaiveDate, NaiveDate),
+    summary: &str,
+    item @ FeedItem { link, post, .. }: &FeedItem,
+) {
+    let end = end.succ_opt().unwrap_or(end);
+    push_line(calendar, "BEGIN:VEVENT");
+    push_line(calendar, &format!("UID:{}", uid));
+    push_line(calendar, &format!("DTSTAMP:{}", stamp));
+    push_line(
+        calendar,
+        &format!("DTSTART;VALUE=DATE:{}", format_date(start)),
+    );
+    push_line(calendar, &format!("DTEND;VALUE=DATE:{}", format_date(end)));
+    push_line(calendar, &format!("SUMMARY:{}", escape(summary)));
+    push_line(
+        calendar,
+        &format!("DESCRIPTION:{}", escape(&description(item))),
+    );
+    if !post.category.is_empty() {
+        push_line(calendar, &format!("CATEGORIES:{}", escape(&post.category)));
+    }
+    push_line(calendar, &format!("URL:{}", link));
+    push_line(calendar, "END:VEVENT");
+}
+
+/// the day used to guess the year of dates written without one
+fn reference_date(post: &Post) -> Option<NaiveDate> {
+    post.updated_date.date.or(post.show_date.0.date)
+}
+
+/// renders the posts as an iCalendar.
+///
+/// every post with a 公開期間 becomes an all day event over it, and every date mentioned in
+/// the title or the body becomes an all day event on that day.
+pub fn calendar(name: &str, items: &[FeedItem], now: DateTime<Utc>) -> String {
+    let stamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let mut calendar = String::new();
+    push_line(&mut calendar, "BEGIN:VCALENDAR");
+    push_line(&mut calendar, "VERSION:2.0");
+    push_line(&mut calendar, "PRODID:-//tuat-feed-api//calendar//JA");
+    push_line(&mut calendar, "CALSCALE:GREGORIAN");
+    push_line(&mut calendar, &format!("X-WR-CALNAME:{}", escape(name)));
+    for item in items {
+        let post = &item.post;
+        if let (Some(start), end) = (post.show_date.0.date, post.show_date.1.date) {
+            push_event(
+                &mut calendar,
+                &format!("{}#show", item.link),
+                &stamp,
+                (start, end.unwrap_or(start).max(start)),
+                &format!("【掲載】{}", post.title),
+                item,
+            );
+        }
+        let text = format!("{}\n{}", post.title, post.contents);
+        for date in find_dates(&text, reference_date(post)) {
+            push_event(
+                &mut calendar,
+                &format!("{}#{}", item.link, format_date(date)),
+                &stamp,
+                (date, date),
+                &post.title,
+                item,
+            );
+        }
+    }
+    push_line(&mut calendar, "END:VCALENDAR");
+    calendar
+}
+
+#[cfg(test)]
+mod test {
+    use super::{calendar, find_dates};
+    use crate::syndication::FeedItem;
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use tuat_feed_scraper::post::{Attachment, BoardDate, Post};
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn dates_in_text() {
+        let reference = Some(date(2021, 12, 20));
+        let dates = find_dates(
+            "試験は2022年1月14日、追試は１月２１日（金）です。締切 2021/12/24",
+            reference,
+        );
+        assert_eq!(
+            dates.into_iter().collect::<Vec<_>>(),
+            vec![date(2021, 12, 24), date(2022, 1, 14), date(2022, 1, 21)]
+        );
+
+        assert_eq!(
+            find_dates("12/27(月)まで", reference)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![date(2021, 12, 27)]
+        );
+        // not dates
+        assert!(find_dates("1/2の学生 3-4限", reference).is_empty());
+        // no year to guess from
+        assert!(find_dates("1月14日", None).is_empty());
+        assert_eq!(find_dates("2021年2月30日", reference).len(), 0);
+    }
+
+    #[test]
+    fn events() {
+        let mut post = Post::new(10641);
+        post.title = "集中講義; 変更".to_string();
+        post.contents = "講義は10月20日に行います".to_string();
+        post.updated_date = date(2021, 10, 7).into();
+        post.show_date = (date(2021, 10, 7).into(), date(2021, 10, 31).into());
+        post.attachments.push(Attachment::new(
+            "日程.pdf".to_string(),
+            "http://example.com/a".to_string(),
+        ));
+        let items = vec![
+            FeedItem {
+                link: "http://example.com/post?i=10641".to_string(),
+                attachments: vec!["http://api.example.com/v2/attachments/10641/0".to_string()],
+                post,
+            },
+            FeedItem {
+                link: "http://example.com/post?i=1".to_string(),
+                attachments: vec![],
+                post: Post::new(1),
+            },
+        ];
+        let now = Utc.with_ymd_and_hms(2021, 10, 8, 1, 2, 3).unwrap();
+        let ics = calendar("工学部", &items, now);
+
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+        assert!(ics.contains(
+            "UID:http://example.com/post?i=10641#show\r\nDTSTAMP:20211008T010203Z\r\n\
+             DTSTART;VALUE=DATE:20211007\r\nDTEND;VALUE=DATE:20211101\r\n\
+             SUMMARY:【掲載】集中講義\\; 変更\r\n"
+        ));
+        assert!(ics.contains(
+            "UID:http://example.com/post?i=10641#20211020\r\nDTSTAMP:20211008T010203Z\r\n\
+             DTSTART;VALUE=DATE:20211020\r\nDTEND;VALUE=DATE:20211021\r\n"
+        ));
+        // attachments link to the api, not to the board
+        let unfolded = ics.replace("\r\n ", "");
+        assert!(unfolded.contains(
+            "DESCRIPTION:講義は10月20日に行います\\n日程.pdf: http://api.example.com/v2/attachments/10641/0\r\n"
+        ));
+        assert!(!unfolded.contains("http://example.com/a"));
+        for line in ics.split("\r\n") {
+            assert!(line.len() <= 75, "{:?} is too long", line);
+        }
+    }
+
+    #[test]
+    fn long_lines_are_folded() {
+        let mut post = Post::new(1);
+        post.contents = "あ".repeat(100);
+        post.show_date = (date(2021, 10, 7).into(), BoardDate::default());
+        let items = vec![FeedItem {
+            link: "http://example.com/post?i=1".to_string(),
+            attachments: vec![],
+            post,
+        }];
+        let ics = calendar("工学部", &items, Utc::now());
+        let unfolded = ics.replace("\r\n ", "");
+        assert!(unfolded.contains(&format!("DESCRIPTION:{}\r\n", "あ".repeat(100))));
+        assert!(unfolded.contains("DTSTART;VALUE=DATE:20211007\r\nDTEND;VALUE=DATE:20211008\r\n"));
+    }
+}
@@ -0,0 +1,442 @@
+use crate::{
+    attachments::{AttachmentError, AttachmentProxy},
+    storage::{Storage, StorageError},
+};
+use axum::body::Bytes;
+use chrono::Utc;
+use quick_xml::{events::Event, Reader};
+use std::io::{Cursor, Read};
+use thiserror::Error;
+use tracing::{debug, warn};
+use tuat_feed_scraper::post::{guess_file_type, Post};
+use zip::ZipArchive;
+
+/// longest text kept for an attachment, in characters
+const MAX_TEXT_LENGTH: usize = 100_000;
+/// most text read from a pdf before tidying, in bytes.
+/// leaves room for multibyte characters and the blank lines dropped by [`tidy`].
+const MAX_RAW_TEXT_SIZE: usize = 8 * MAX_TEXT_LENGTH;
+/// most xml decompressed from an office document, over all of its parts
+const MAX_OFFICE_XML_SIZE: u64 = 64 * 1024 * 1024;
+
+/// the file types text is extracted from
+const SUPPORTED_TYPES: [&str; 5] = [
+    "application/pdf",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    "text/plain",
+];
+
+/// the error that happens when extracting text from an attachment
+#[derive(Error, Debug)]
+pub enum ExtractionError {
+    /// the pdf could not be read
+    #[error("invalid pdf")]
+    PdfError(#[from] pdf_extract::OutputError),
+    /// the office document is not a valid zip archive
+    #[error("invalid office document")]
+    ZipError(#[from] zip::result::ZipError),
+    /// the xml inside the office document could not be read
+    #[error("invalid xml in office document")]
+    XmlError(#[from] quick_xml::Error),
+    /// a part of the office document could not be read
+    #[error("could not read office document")]
+    IoError(#[from] std::io::Error),
+    /// the office document decompresses to more than is read
+    #[error("office document is too large when decompressed")]
+    TooLarge,
+    /// the extractor panicked, e.g. on a broken pdf
+    #[error("extraction failed")]
+    TaskError(#[from] tokio::task::JoinError),
+}
+
+/// text of the `<t>` elements in the xml `data`, with a line break after each `paragraph` element
+fn xml_text(data: &[u8], paragraph: &[u8]) -> Result<String, ExtractionError> {
+    let mut reader = Reader::from_reader(data);
+    let mut buffer = Vec::new();
+    let mut text = String::new();
+    let mut in_text = false;
+    loop {
+        match reader.read_event_into(&mut buffer)? {
+            Event::Start(start) if start.local_name().as_ref() == b"t" => in_text = true,
+            Event::End(end) if end.local_name().as_ref() == b"t" => in_text = false,
+            Event::End(end) if end.local_name().as_ref() == paragraph => text.push('\n'),
+            Event::Text(content) if in_text => text.push_str(&content.unescape()?),
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    Ok(text)
+}
+
+/// text of the parts of an office document whose names start with `prefix`, in order.
+/// fails when the parts decompress to more than `max_size` bytes in total.
+fn office_text(
+    data: &[u8],
+    prefix: &str,
+    paragraph: &[u8],
+    max_size: u64,
+) -> Result<String, ExtractionError> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let mut parts = archive
+        .file_names()
+        .filter(|name| name.starts_with(prefix) && name.ends_with(".xml"))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    // slide10.xml comes after slide9.xml
+    parts.sort_by_key(|name| (name.len(), name.clone()));
+    let mut text = String::new();
+    // the sizes in the archive may lie, so the reads are bounded as well
+    let mut remaining = max_size;
+    for part in parts {
+        let file = archive.by_name(&part)?;
+        if file.size() > remaining {
+            return Err(ExtractionError::TooLarge);
+        }
+        let mut xml = Vec::new();
+        let read = file.take(remaining + 1).read_to_end(&mut xml)? as u64;
+        if read > remaining {
+            return Err(ExtractionError::TooLarge);
+        }
+        remaining -= read;
+        text.push_str(&xml_text(&xml, paragraph)?);
+    }
+    Ok(text)
+}
+
+/// keeps what is written up to a number of bytes, then fails the writes
+struct LimitedWriter {
+    data: Vec<u8>,
+    max: usize,
+}
+
+impl std::io::Write for LimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let room = self.max - self.data.len();
+        if room == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        let written = buf.len().min(room);
+        self.data.extend_from_slice(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// text of a pdf, cut at `max_size` bytes
+fn pdf_text(data: &[u8], max_size: usize) -> Result<String, ExtractionError> {
+    let mut document =
+        pdf_extract::Document::load_mem(data).map_err(pdf_extract::OutputError::PdfError)?;
+    if document.is_encrypted() {
+        // many pdfs are encrypted with an empty password just to restrict printing
+        document
+            .decrypt("")
+            .map_err(pdf_extract::OutputError::PdfError)?;
+    }
+    let mut writer = LimitedWriter {
+        data: Vec::new(),
+        max: max_size,
+    };
+    let result = {
+        let mut output = pdf_extract::PlainTextOutput::new(&mut writer as &mut dyn std::io::Write);
+        pdf_extract::output_doc(&document, &mut output)
+    };
+    match result {
+        Ok(()) => {}
+        // stopped at the limit
+        Err(pdf_extract::OutputError::FormatError(_)) if writer.data.len() == writer.max => {}
+        Err(e) => return Err(e.into()),
+    }
+    let text = match String::from_utf8(writer.data) {
+        Ok(text) => text,
+        // a character cut at the limit
+        Err(e) => {
+            let valid = e.utf8_error().valid_up_to();
+            let mut data = e.into_bytes();
+            data.truncate(valid);
+            String::from_utf8(data).unwrap_or_default()
+        }
+    };
+    Ok(text)
+}
+
+/// trims every line, drops empty lines and cuts the text at [`MAX_TEXT_LENGTH`]
+fn tidy(text: &str) -> String {
+    let text = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    match text.char_indices().nth(MAX_TEXT_LENGTH) {
+        Some((end, _)) => text[..end].to_string(),
+        None => text,
+    }
+}
+
+/// mime type of a file, guessed from the name when `content_type` does not tell
+fn file_type<'a>(name: &str, content_type: &'a str) -> &'a str {
+    match content_type {
+        "" | "application/octet-stream" => guess_file_type(name),
+        content_type => content_type.split(';').next().unwrap_or_default().trim(),
+    }
+}
+
+/// whether text can be extracted from a file named `name` of `content_type`
+pub fn is_supported(name: &str, content_type: &str) -> bool {
+    SUPPORTED_TYPES.contains(&file_type(name, content_type))
+}
+
+/// extracts the text of a file.
+/// pdf, word, excel and powerpoint (the xml formats) and plain text are supported.
+/// `None` when the format is not supported or the file has no text.
+pub fn extract_text(
+    name: &str,
+    content_type: &str,
+    data: &[u8],
+) -> Result<Option<String>, ExtractionError> {
+    let text = match file_type(name, content_type) {
+        "application/pdf" => pdf_text(data, MAX_RAW_TEXT_SIZE)?,
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+            office_text(data, "word/document", b"p", MAX_OFFICE_XML_SIZE)?
+        }
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => {
+            office_text(data, "xl/sharedStrings", b"si", MAX_OFFICE_XML_SIZE)?
+        }
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation" => {
+            office_text(data, "ppt/slides/slide", b"p", MAX_OFFICE_XML_SIZE)?
+        }
+        "text/plain" => String::from_utf8_lossy(data).into_owned(),
+        _ => return Ok(None),
+    };
+    let text = tidy(&text);
+    Ok((!text.is_empty()).then_some(text))
+}
+
+/// [`extract_text`] on the blocking thread pool
+pub async fn extract(
+    name: String,
+    content_type: String,
+    data: Bytes,
+) -> Result<Option<String>, ExtractionError> {
+    tokio::task::spawn_blocking(move || extract_text(&name, &content_type, &data)).await?
+}
+
+/// fills in the text of the supported attachments of `posts`.
+///
+/// every attachment is downloaded once and its text (or that it has none) is kept in `storage`,
+/// so later calls only read the storage.
+/// attachments that could not be downloaded are tried again on the next call.
+/// only files on the host of `board_url` are downloaded.
+pub async fn fill_texts(
+    storage: &Storage,
+    proxy: &AttachmentProxy,
+    board_url: &str,
+    posts: &mut [Post],
+) -> Result<(), StorageError> {
+    let urls = posts
+        .iter()
+        .flat_map(|post| &post.attachments)
+        .filter(|attachment| is_supported(&attachment.name, &attachment.file_type))
+        .map(|attachment| attachment.url.clone())
+        .collect();
+    let mut texts = storage.load_attachment_texts(urls).await?;
+    for attachment in posts.iter_mut().flat_map(|post| &mut post.attachments) {
+        if !is_supported(&attachment.name, &attachment.file_type) {
+            continue;
+        }
+        if let Some(text) = texts.get(&attachment.url) {
+            attachment.text = text.clone();
+            continue;
+        }
+        let file = match proxy.fetch(attachment, board_url).await {
+            Ok(file) => file,
+            Err(AttachmentError::TooLarge(_)) => {
+                debug!("{} is too large to extract", attachment.url);
+                storage
+                    .save_attachment_text(attachment.url.clone(), None, Utc::now())
+                    .await?;
+                texts.insert(attachment.url.clone(), None);
+                continue;
+            }
+            Err(e) => {
+                warn!("could not download {}: {:?}", attachment.url, e);
+                continue;
+            }
+        };
+        let text = extract(attachment.name.clone(), file.content_type, file.data)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("could not extract text of {}: {:?}", attachment.url, e);
+                None
+            });
+        storage
+            .save_attachment_text(attachment.url.clone(), text.clone(), Utc::now())
+            .await?;
+        texts.insert(attachment.url.clone(), text.clone());
+        attachment.text = text;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{extract_text, fill_texts, ExtractionError};
+    use crate::{attachments::AttachmentProxy, storage::Storage};
+    use axum::{extract::State, routing::get, Router};
+    use std::{
+        io::{Cursor, Write},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tuat_feed_scraper::post::{Attachment, Post};
+    use zip::{write::FileOptions, ZipWriter};
+
+    /// a one page pdf showing `text` in Helvetica
+    fn pdf(text: &str) -> Vec<u8> {
+        let contents = format!("BT /F1 12 Tf 72 720 Td ({}) Tj ET", text);
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 4 0 R \
+            /Resources << /Font << /F1 5 0 R >> >> >>"
+                .to_string(),
+            format!(
+                "<< /Length {} >>\nstream\n{}\nendstream",
+                contents.len(),
+                contents
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        ];
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).bytes());
+        }
+        let xref = pdf.len();
+        pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).bytes());
+        for offset in offsets {
+            pdf.extend(format!("{:010} 00000 n \n", offset).bytes());
+        }
+        pdf.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                xref
+            )
+            .bytes(),
+        );
+        pdf
+    }
+
+    fn docx(body: &str) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("word/document.xml", FileOptions::default())
+            .unwrap();
+        write!(
+            writer,
+            r#"<?xml version="1.0"?><w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>{}</w:body></w:document>"#,
+            body
+        )
+        .unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn pdf_text() {
+        let text = extract_text("a.pdf", "application/pdf", &pdf("Room 101")).unwrap();
+        assert_eq!(text.as_deref(), Some("Room 101"));
+        assert!(extract_text("a.pdf", "application/pdf", b"not a pdf").is_err());
+    }
+
+    #[test]
+    fn office_text() {
+        let document = docx(
+            r#"<w:p><w:r><w:t>日程</w:t></w:r><w:r><w:t xml:space="preserve"> 10:00</w:t></w:r></w:p><w:p/><w:p><w:r><w:t>講義室 &amp; 講堂</w:t></w:r></w:p>"#,
+        );
+        let text = extract_text("日程.docx", "application/octet-stream", &document).unwrap();
+        assert_eq!(text.as_deref(), Some("日程 10:00\n講義室 & 講堂"));
+    }
+
+    #[test]
+    fn bounded_output() {
+        // the page starts with blank lines
+        let text = super::pdf_text(&pdf("Room 101"), 6).unwrap();
+        assert_eq!(text, "\n\nRoom");
+
+        // a small archive that decompresses to far more than it is
+        let document = docx(&"<w:p/>".repeat(100_000));
+        assert!(document.len() < 10_000);
+        assert!(matches!(
+            super::office_text(&document, "word/document", b"p", 100_000),
+            Err(ExtractionError::TooLarge)
+        ));
+        assert!(super::office_text(&document, "word/document", b"p", 1_000_000).is_ok());
+    }
+
+    #[test]
+    fn other_formats() {
+        let text = extract_text(
+            "a.txt",
+            "text/plain; charset=utf-8",
+            " 教室\n\n 変更 ".as_bytes(),
+        );
+        assert_eq!(text.unwrap().as_deref(), Some("教室\n変更"));
+        assert_eq!(extract_text("a.png", "image/png", b"png").unwrap(), None);
+        assert_eq!(extract_text("a.txt", "text/plain", b"  ").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn fills_texts_once() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/schedule.txt",
+                get(|State(requests): State<Arc<AtomicUsize>>| async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    "1限 講義室"
+                }),
+            )
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut post = Post::new(1);
+        post.attachments = vec![
+            Attachment::new("日程.txt".to_string(), format!("{}/schedule.txt", base)),
+            Attachment::new("地図.png".to_string(), format!("{}/map.png", base)),
+            Attachment::new("名簿.pdf".to_string(), format!("{}/missing.pdf", base)),
+        ];
+        let storage = Storage::in_memory().unwrap();
+        let proxy = AttachmentProxy::new();
+
+        let mut posts = vec![post.clone()];
+        fill_texts(&storage, &proxy, &base, &mut posts)
+            .await
+            .unwrap();
+        let texts = posts[0]
+            .attachments
+            .iter()
+            .map(|attachment| attachment.text.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec![Some("1限 講義室"), None, None]);
+
+        // the text comes from the storage, even with an empty proxy cache
+        let mut posts = vec![post];
+        fill_texts(&storage, &AttachmentProxy::new(), &base, &mut posts)
+            .await
+            .unwrap();
+        assert_eq!(posts[0].attachments[0].text.as_deref(), Some("1限 講義室"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::{
+    handlers_v2::{api_error, storage_error, ApiError},
+    state::SharedState,
+    webhooks::{Webhook, WebhookDelivery, WebhookFilter},
+};
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    routing::{delete, get},
+    Json, Router,
+};
+use chrono::Utc;
+use serde_derive::Deserialize;
+use subtle::ConstantTimeEq;
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi, ToSchema,
+};
+
+/// checks the `Authorization: Bearer <token>` header against the admin token
+fn authorize(state: &SharedState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let Some(token) = &state.admin_token else {
+        return Err(api_error(
+            StatusCode::FORBIDDEN,
+            "the admin api is disabled",
+        ));
+    };
+    let given = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // compare in constant time, so the token can not be guessed from response times
+    let matches = given.is_some_and(|given| bool::from(given.as_bytes().ct_eq(token.as_bytes())));
+    if !matches {
+        return Err(api_error(StatusCode::UNAUTHORIZED, "invalid admin token"));
+    }
+    Ok(())
+}
+
+/// body to register a webhook
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewWebhook {
+    /// url the events are posted to
+    url: String,
+    /// key of the HMAC-SHA256 signature in the `X-Tuat-Feed-Signature` header
+    secret: String,
+    /// which posts are delivered. every post when not given.
+    #[serde(flatten)]
+    filter: WebhookFilter,
+}
+
+/// every registered webhook
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "registered webhooks", body = [Webhook]),
+        (status = 401, description = "invalid admin token", body = ErrorBody),
+        (status = 403, description = "the admin api is disabled", body = ErrorBody),
+    )
+)]
+pub async fn list_webhooks(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Webhook>>, ApiError> {
+    authorize(&state, &headers)?;
+    let webhooks = state.storage.load_webhooks().await.map_err(storage_error)?;
+    Ok(Json(webhooks))
+}
+
+/// registers a webhook
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = NewWebhook,
+    responses(
+        (status = 201, description = "the registered webhook", body = Webhook),
+        (status = 400, description = "the url is not http(s)", body = ErrorBody),
+        (status = 401, description = "invalid admin token", body = ErrorBody),
+        (status = 403, description = "the admin api is disabled", body = ErrorBody),
+    )
+)]
+pub async fn add_webhook(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(webhook): Json<NewWebhook>,
+) -> Result<(StatusCode, Json<Webhook>), ApiError> {
+    authorize(&state, &headers)?;
+    if !(webhook.url.starts_with("http://") || webhook.url.starts_with("https://")) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "url has to be http or https",
+        ));
+    }
+    let webhook = state
+        .storage
+        .add_webhook(webhook.url, webhook.secret, webhook.filter, Utc::now())
+        .await
+        .map_err(storage_error)?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+/// unregisters a webhook
+#[utoipa::path(
+    delete,
+    path = "/admin/webhooks/{id}",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("id" = i64, Path, description = "id of the webhook")),
+    responses(
+        (status = 204, description = "the webhook was unregistered"),
+        (status = 404, description = "no such webhook", body = ErrorBody),
+    )
+)]
+pub async fn remove_webhook(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    authorize(&state, &headers)?;
+    if state
+        .storage
+        .remove_webhook(id)
+        .await
+        .map_err(storage_error)?
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(api_error(
+            StatusCode::NOT_FOUND,
+            format!("webhook {} not found", id),
+        ))
+    }
+}
+
+/// delivery log of a webhook, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/{id}/deliveries",
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(("id" = i64, Path, description = "id of the webhook")),
+    responses(
+        (status = 200, description = "every delivery attempt", body = [WebhookDelivery]),
+    )
+)]
+pub async fn deliveries(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<WebhookDelivery>>, ApiError> {
+    authorize(&state, &headers)?;
+    let deliveries = state
+        .storage
+        .load_deliveries(id)
+        .await
+        .map_err(storage_error)?;
+    Ok(Json(deliveries))
+}
+
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// openapi document of the admin api
+#[derive(OpenApi)]
+#[openapi(
+    paths(list_webhooks, add_webhook, remove_webhook, deliveries),
+    components(schemas(NewWebhook, Webhook, WebhookFilter, WebhookDelivery)),
+    modifiers(&AdminToken),
+    tags((name = "admin", description = "管理用API。`TUAT_FEED_API_ADMIN_TOKEN`が必要"))
+)]
+pub struct ApiDoc;
+
+/// routes for the admin api
+pub fn app_admin(initial_state: SharedState) -> Router {
+    Router::new()
+        .route("/webhooks", get(list_webhooks).post(add_webhook))
+        .route("/webhooks/:id", delete(remove_webhook))
+        .route("/webhooks/:id/deliveries", get(deliveries))
+        .with_state(initial_state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::ServerState;
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header("content-type", "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let response = app
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn requires_token() {
+        let app = app_admin(Arc::new(ServerState::init()));
+        let (status, _) = send(&app, "GET", "/webhooks", Some("token"), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let state = ServerState::init().with_admin_token(Some("token".to_string()));
+        let app = app_admin(Arc::new(state));
+        let (status, _) = send(&app, "GET", "/webhooks", None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(&app, "GET", "/webhooks", Some("wrong"), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(&app, "GET", "/webhooks", Some("token2"), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(&app, "GET", "/webhooks", Some("token"), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn manage_webhooks() {
+        let state = ServerState::init().with_admin_token(Some("token".to_string()));
+        let app = app_admin(Arc::new(state));
+
+        let (status, webhook) = send(
+            &app,
+            "POST",
+            "/webhooks",
+            Some("token"),
+            Some(serde_json::json!({
+                "url": "http://example.com/hook",
+                "secret": "secret",
+                "group": "technology",
+                "keyword": "試験",
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(webhook["url"], "http://example.com/hook");
+        assert_eq!(webhook["filter"]["group"], "technology");
+        assert!(webhook["filter"]["category"].is_null());
+        assert!(webhook.get("secret").is_none());
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/webhooks",
+            Some("token"),
+            Some(serde_json::json!({"url": "ftp://example.com", "secret": "secret"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, webhooks) = send(&app, "GET", "/webhooks", Some("token"), None).await;
+        assert_eq!(webhooks.as_array().unwrap().len(), 1);
+
+        let uri = format!("/webhooks/{}", webhook["id"]);
+        let (status, deliveries) = send(
+            &app,
+            "GET",
+            &format!("{}/deliveries", uri),
+            Some("token"),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(deliveries, serde_json::json!([]));
+
+        let (status, _) = send(&app, "DELETE", &uri, Some("token"), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, "DELETE", &uri, Some("token"), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, webhooks) = send(&app, "GET", "/webhooks", Some("token"), None).await;
+        assert_eq!(webhooks, serde_json::json!([]));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::{
+    handlers_v2::{api_error, storage_error, ApiError},
+    push::{validate_endpoint, PushFilter, PushKeys, PushMessage, PushSubscription},
+    state::SharedState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::Utc;
+use serde_derive::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+fn disabled() -> ApiError {
+    api_error(StatusCode::SERVICE_UNAVAILABLE, "web push is disabled")
+}
+
+/// the public key of the server
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VapidPublicKey {
+    /// base64url encoded key, passed to `pushManager.subscribe` as `applicationServerKey`
+    public_key: String,
+}
+
+/// body to subscribe to notifications.
+/// the json of the browser's `PushSubscription` with the filter fields added.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewPushSubscription {
+    /// url of the push service
+    endpoint: String,
+    /// keys of the subscription
+    keys: PushKeys,
+    /// which posts are notified. every new post when not given.
+    #[serde(flatten)]
+    filter: PushFilter,
+}
+
+/// body to unsubscribe
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Unsubscribe {
+    /// url of the push service, as given when subscribing
+    endpoint: String,
+}
+
+/// the VAPID public key of the server
+#[utoipa::path(
+    get,
+    path = "/v2/push/key",
+    tag = "push",
+    responses(
+        (status = 200, description = "the public key", body = VapidPublicKey),
+        (status = 503, description = "web push is disabled", body = ErrorBody),
+    )
+)]
+pub async fn public_key(
+    State(state): State<SharedState>,
+) -> Result<Json<VapidPublicKey>, ApiError> {
+    let public_key = state.push.public_key().ok_or_else(disabled)?;
+    Ok(Json(VapidPublicKey { public_key }))
+}
+
+/// subscribes a browser to notifications of new posts.
+/// subscribing again with the same endpoint replaces the filter.
+/// the endpoint has to be a https url of a host on the internet.
+/// every notification is a [`PushMessage`] as json.
+#[utoipa::path(
+    post,
+    path = "/v2/push/subscriptions",
+    tag = "push",
+    request_body = NewPushSubscription,
+    responses(
+        (status = 201, description = "the subscription", body = PushSubscription),
+        (status = 400, description = "the endpoint or the keys are invalid", body = ErrorBody),
+        (status = 503, description = "web push is disabled, or there are too many subscriptions", body = ErrorBody),
+    )
+)]
+pub async fn subscribe(
+    State(state): State<SharedState>,
+    Json(subscription): Json<NewPushSubscription>,
+) -> Result<(StatusCode, Json<PushSubscription>), ApiError> {
+    state.push.public_key().ok_or_else(disabled)?;
+    if let Err(e) = validate_endpoint(&subscription.endpoint) {
+        return Err(api_error(StatusCode::BAD_REQUEST, e.to_string()));
+    }
+    if subscription.keys.validate().is_err() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "invalid keys"));
+    }
+    let subscription = PushSubscription {
+        endpoint: subscription.endpoint,
+        keys: subscription.keys,
+        filter: subscription.filter,
+        created_at: Utc::now(),
+    };
+    let saved = state
+        .storage
+        .save_push_subscription(subscription.clone(), state.push.max_subscriptions())
+        .await
+        .map_err(storage_error)?;
+    if !saved {
+        return Err(api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "too many subscriptions",
+        ));
+    }
+    Ok((StatusCode::CREATED, Json(subscription)))
+}
+
+/// unsubscribes a browser
+#[utoipa::path(
+    delete,
+    path = "/v2/push/subscriptions",
+    tag = "push",
+    request_body = Unsubscribe,
+    responses(
+        (status = 204, description = "the subscription was removed"),
+        (status = 404, description = "no such subscription", body = ErrorBody),
+    )
+)]
+pub async fn unsubscribe(
+    State(state): State<SharedState>,
+    Json(Unsubscribe { endpoint }): Json<Unsubscribe>,
+) -> Result<StatusCode, ApiError> {
+    if state
+        .storage
+        .remove_push_subscription(endpoint)
+        .await
+        .map_err(storage_error)?
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(api_error(StatusCode::NOT_FOUND, "subscription not found"))
+    }
+}
+
+/// openapi document of the web push api
+#[derive(OpenApi)]
+#[openapi(
+    paths(public_key, subscribe, unsubscribe),
+    components(schemas(
+        VapidPublicKey,
+        NewPushSubscription,
+        Unsubscribe,
+        PushKeys,
+        PushFilter,
+        PushSubscription,
+        PushMessage
+    )),
+    tags((name = "push", description = "新しい投稿のWeb Push通知"))
+)]
+pub struct ApiDoc;
+
+/// routes for the web push api
+pub fn app_push(initial_state: SharedState) -> Router {
+    Router::new()
+        .route("/key", get(public_key))
+        .route("/subscriptions", post(subscribe).delete(unsubscribe))
+        .with_state(initial_state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{push::VapidKeys, state::ServerState};
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        let body = match body {
+            Some(body) => {
+                request = request.header("content-type", "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let response = app
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    // keys of the user agent in RFC 8291 appendix A
+    const P256DH: &str =
+        "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4";
+    const AUTH: &str = "BTBZMqHH6r4Tts7J_aSIgg";
+
+    #[tokio::test]
+    async fn disabled_without_keys() {
+        let app = app_push(Arc::new(ServerState::init()));
+        let (status, _) = send(&app, "GET", "/key", None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn manage_subscriptions() {
+        let vapid = VapidKeys::generate("mailto:admin@example.com");
+        let state = Arc::new(ServerState::init().with_vapid(Some(vapid.clone())));
+        let app = app_push(state.clone());
+
+        let (status, key) = send(&app, "GET", "/key", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(key["public_key"], vapid.public_key());
+
+        let subscription = serde_json::json!({
+            "endpoint": "https://push.example.com/send/1",
+            "expirationTime": null,
+            "keys": {"p256dh": P256DH, "auth": AUTH},
+            "category": "academic",
+            "keyword": "試験",
+        });
+        let (status, body) = send(&app, "POST", "/subscriptions", Some(subscription)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["filter"]["category"], "academic");
+        assert!(body["filter"]["group"].is_null());
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/subscriptions",
+            Some(serde_json::json!({
+                "endpoint": "https://push.example.com/send/2",
+                "keys": {"p256dh": "not a key", "auth": AUTH},
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            state.storage.load_push_subscriptions().await.unwrap().len(),
+            1
+        );
+
+        let endpoint = serde_json::json!({"endpoint": "https://push.example.com/send/1"});
+        let (status, _) = send(&app, "DELETE", "/subscriptions", Some(endpoint.clone())).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, "DELETE", "/subscriptions", Some(endpoint)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn rejects_local_endpoints() {
+        let vapid = VapidKeys::generate("mailto:admin@example.com");
+        let app = app_push(Arc::new(ServerState::init().with_vapid(Some(vapid))));
+
+        for endpoint in [
+            "http://push.example.com/send/1",
+            "https://localhost/send",
+            "https://push.localhost/send",
+            "https://intranet/send",
+            "https://127.0.0.1/send",
+            "https://10.0.0.1/send",
+            "https://192.168.1.1/send",
+            "https://169.254.169.254/latest/meta-data",
+            "https://[::1]/send",
+            "https://[fe80::1]/send",
+            "https://[fd00::1]/send",
+            "https://[::ffff:127.0.0.1]/send",
+            "file:///etc/passwd",
+        ] {
+            let subscription = serde_json::json!({
+                "endpoint": endpoint,
+                "keys": {"p256dh": P256DH, "auth": AUTH},
+            });
+            let (status, _) = send(&app, "POST", "/subscriptions", Some(subscription)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", endpoint);
+        }
+    }
+
+    #[tokio::test]
+    async fn limits_subscriptions() {
+        let vapid = VapidKeys::generate("mailto:admin@example.com");
+        let mut state = ServerState::init().with_vapid(Some(vapid));
+        state.push = state.push.with_max_subscriptions(1);
+        let state = Arc::new(state);
+        let app = app_push(state.clone());
+        let subscription = |id: u32| {
+            Some(serde_json::json!({
+                "endpoint": format!("https://push.example.com/send/{}", id),
+                "keys": {"p256dh": P256DH, "auth": AUTH},
+            }))
+        };
+
+        let (status, _) = send(&app, "POST", "/subscriptions", subscription(1)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send(&app, "POST", "/subscriptions", subscription(2)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        // an existing subscription can still be updated
+        let (status, _) = send(&app, "POST", "/subscriptions", subscription(1)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            state.storage.load_push_subscriptions().await.unwrap().len(),
+            1
+        );
+    }
+}
@@ -1,86 +1,112 @@
-use axum::{routing::get, Router};
-
-use crate::{redirect_path, state::SharedState};
-
-/// routes for technology
-pub mod technology {
-    use crate::state::SharedState;
-    use axum::extract::State;
-    use axum::response::Json;
-    use tuat_feed_scraper::post::PostCompatv1;
-
-    /// all data
-    pub async fn all(State(state): State<SharedState>) -> Json<Vec<PostCompatv1>> {
-        let info_academic = state.technology_academic.information.read().await.clone();
-        let info_campus = state.technology_campus.information.read().await.clone();
-
-        Json(
-            info_academic
-                .post
-                .into_iter()
-                .chain(info_campus.post)
-                .map(Into::into)
-                .collect(),
-        )
-    }
+use axum::{
+    extract::{Path, State},
+    response::{Json, Redirect},
+    routing::get,
+    Router,
+};
+use tuat_feed_scraper::{post::PostCompatv1, Gakubu};
+use utoipa::OpenApi;
 
-    /// academic
-    pub async fn academic(State(state): State<SharedState>) -> Json<Vec<PostCompatv1>> {
-        let info = state.technology_academic.information.read().await.clone();
-        Json(info.post.into_iter().map(Into::into).collect())
-    }
+use crate::{boards::select_feeds, redirect_path, state::SharedState};
 
-    /// campus
-    pub async fn campus(State(state): State<SharedState>) -> Json<Vec<PostCompatv1>> {
-        let info = state.technology_campus.information.read().await.clone();
-        Json(info.post.into_iter().map(Into::into).collect())
+/// the group of the boards of a gakubu in v1 paths, like `T` in `/v1/T/academic`.
+/// v1 only knows the two gakubu, every other group is only served by the later versions.
+fn group_of(gakubu: &str) -> Option<&'static str> {
+    match gakubu {
+        "T" => Some(Gakubu::Technology.id()),
+        "A" => Some(Gakubu::Agriculture.id()),
+        _ => None,
     }
 }
 
-/// routes for agriculture
-pub mod agriculture {
-    use crate::state::SharedState;
-    use axum::extract::State;
-    use axum::response::Json;
-    use tuat_feed_scraper::post::PostCompatv1;
-
-    /// all data
-    pub async fn all(State(state): State<SharedState>) -> Json<Vec<PostCompatv1>> {
-        let info_academic = state.agriculture_academic.information.read().await.clone();
-
-        let info_campus = state.agriculture_campus.information.read().await.clone();
-        Json(
-            info_academic
-                .post
-                .into_iter()
-                .chain(info_campus.post)
-                .map(Into::into)
-                .collect(),
-        )
+/// posts on the boards of `gakubu` and `category`, in the order of the registry.
+/// `None` selects every category. `None` when no such board is registered.
+async fn posts(
+    state: &SharedState,
+    gakubu: &str,
+    category: Option<&str>,
+) -> Option<Json<Vec<PostCompatv1>>> {
+    let feeds = select_feeds(state, None, Some(group_of(gakubu)?), category).ok()?;
+    let mut posts = Vec::new();
+    for feed in feeds {
+        let info = feed.information.read().await;
+        posts.extend(info.post.iter().cloned().map(Into::into));
     }
+    Some(Json(posts))
+}
 
-    /// academic
-    pub async fn academic(State(state): State<SharedState>) -> Json<Vec<PostCompatv1>> {
-        let info = state.agriculture_academic.information.read().await.clone();
-        Json(info.post.into_iter().map(Into::into).collect())
-    }
+/// all data of a gakubu
+#[utoipa::path(
+    get,
+    path = "/v1/{gakubu}",
+    tag = "v1",
+    params(("gakubu" = String, Path, description = "`T` (工学部) or `A` (農学部)")),
+    responses(
+        (status = 200, description = "教務情報と学生生活情報", body = [PostCompatv1]),
+        (status = 307, description = "no such gakubu, redirected to `/v1/T`"),
+    )
+)]
+pub async fn all(state: &SharedState, gakubu: &str) -> Option<Json<Vec<PostCompatv1>>> {
+    posts(state, gakubu, None).await
+}
 
-    /// campus
-    pub async fn campus(State(state): State<SharedState>) -> Json<Vec<PostCompatv1>> {
-        let info = state.agriculture_campus.information.read().await.clone();
-        Json(info.post.into_iter().map(Into::into).collect())
-    }
+/// a category of a gakubu
+#[utoipa::path(
+    get,
+    path = "/v1/{gakubu}/{category}",
+    tag = "v1",
+    params(
+        ("gakubu" = String, Path, description = "`T` (工学部) or `A` (農学部)"),
+        ("category" = String, Path, description = "`academic` (教務情報) or `campus` (学生生活情報)"),
+    ),
+    responses(
+        (status = 200, description = "教務情報または学生生活情報", body = [PostCompatv1]),
+        (status = 307, description = "no such board, redirected to `/v1/T`"),
+    )
+)]
+pub async fn category(
+    state: &SharedState,
+    gakubu: &str,
+    category: &str,
+) -> Option<Json<Vec<PostCompatv1>>> {
+    posts(state, gakubu, Some(category)).await
 }
 
-/// routes for app v1
+/// openapi document of app v1
+#[derive(OpenApi)]
+#[openapi(
+    paths(all, category),
+    components(schemas(PostCompatv1)),
+    tags((name = "v1", description = "古いAPI"))
+)]
+pub struct ApiDoc;
+
+/// routes for app v1.
+/// the boards are looked up in the registry; paths that match no board redirect to `/v1/T`.
 pub fn app_v1(base_path: String, initial_state: SharedState) -> Router {
+    let fallback = format!("{}/v1/T", base_path);
+    let redirect = move |posts: Option<Json<Vec<PostCompatv1>>>| {
+        posts.ok_or_else(|| Redirect::temporary(&fallback))
+    };
     Router::new()
-        .route("/T", get(technology::all))
-        .route("/T/academic", get(technology::academic))
-        .route("/T/campus", get(technology::campus))
-        .route("/A", get(agriculture::all))
-        .route("/A/academic", get(agriculture::academic))
-        .route("/A/campus", get(agriculture::campus))
+        .route(
+            "/:gakubu",
+            get({
+                let redirect = redirect.clone();
+                |State(state): State<SharedState>, Path(gakubu): Path<String>| async move {
+                    redirect(all(&state, &gakubu).await)
+                }
+            }),
+        )
+        .route(
+            "/:gakubu/:category",
+            get(
+                |State(state): State<SharedState>,
+                 Path((gakubu, name)): Path<(String, String)>| async move {
+                    redirect(category(&state, &gakubu, &name).await)
+                },
+            ),
+        )
         .route(
             "/academic",
             get(redirect_path!(v1 base_path, "/T/academic")),
@@ -95,21 +121,35 @@ mod test {
     use super::*;
     use crate::info_bundle::InfoBundle;
     use crate::state::ServerState;
+    use crate::storage::Storage;
     use axum::http::Request;
     use axum::{body::Body, http::StatusCode};
     use http_body_util::BodyExt;
     use std::sync::Arc;
     use std::time::Instant;
     use tower::ServiceExt;
-    use tuat_feed_scraper::post::{Post, PostCompatv1};
+    use tuat_feed_scraper::{
+        board::Board,
+        post::{Post, PostCompatv1},
+    };
 
     async fn dummy_state() -> SharedState {
         let academic = InfoBundle::new(vec![Post::new(0), Post::new(1)], Instant::now());
         let campus = InfoBundle::new(vec![Post::new(10), Post::new(11)], Instant::now());
         let state = ServerState::init();
 
-        *state.technology_academic.information.write().await = academic;
-        *state.technology_campus.information.write().await = campus;
+        *state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await = academic;
+        *state
+            .feed("technology_campus")
+            .unwrap()
+            .information
+            .write()
+            .await = campus;
 
         Arc::new(state)
     }
@@ -179,6 +219,41 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn routes_follow_the_registry() {
+        let request = |app: Router, uri: &'static str| async move {
+            app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+        };
+        let app = app_v1("/base".to_string(), dummy_state().await);
+        assert_eq!(
+            request(app.clone(), "/A/campus").await.status(),
+            StatusCode::OK
+        );
+        for uri in ["/X", "/T/events", "/graduate/academic", "/"] {
+            let response = request(app.clone(), uri).await;
+            assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT, "{}", uri);
+            assert_eq!(response.headers()["location"], "/base/v1/T", "{}", uri);
+        }
+
+        // a registry without the boards of 農学部
+        let boards = Board::defaults()
+            .into_iter()
+            .filter(|board| board.group == "technology")
+            .collect();
+        let state = Arc::new(ServerState::with_boards(
+            Storage::in_memory().unwrap(),
+            boards,
+        ));
+        let app = app_v1("/base".to_string(), state);
+        assert_eq!(request(app.clone(), "/T").await.status(), StatusCode::OK);
+        assert_eq!(
+            request(app, "/A").await.status(),
+            StatusCode::TEMPORARY_REDIRECT
+        );
+    }
+
     #[should_panic]
     #[tokio::test]
     async fn check_json_formatting_index_panic() {
@@ -1,91 +1,991 @@
-use crate::{redirect_path, state::SharedState};
+use crate::{
+    attachments::AttachmentError,
+    boards::{deserialize_category, select_feeds},
+    calendar,
+    handlers_push::app_push,
+    redirect_path,
+    state::{FeedState, ServerState, SharedState},
+    storage::{ArchivedPost, PostRevision, StorageError},
+    stream::StreamMessage,
+    syndication::{self, FeedItem},
+};
 use axum::{
-    extract::{Query, State},
+    extract::{ConnectInfo, OriginalUri, Path, Query, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, HOST},
+        HeaderMap, HeaderValue, StatusCode, Uri,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
     routing::get,
     Json, Router,
 };
-use serde_derive::Deserialize;
-use tuat_feed_scraper::post::Post;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+use tokio_stream::{Stream, StreamExt};
+use tracing::warn;
+use tuat_feed_scraper::{
+    jst,
+    post::{Attachment, Post, Target},
+};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
-#[derive(Debug, Deserialize)]
-enum Gakubu {
-    Technology,
-    Agriculture,
+/// key to sort the posts by
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum SortKey {
+    /// 最終更新日
+    Updated,
+    /// 投稿ID
+    Id,
+    /// start of 公開期間
+    ShowStart,
 }
 
-impl Default for Gakubu {
-    fn default() -> Self {
-        Self::Technology
-    }
+/// order of the sort
+#[derive(Debug, Default, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
 }
 
-#[derive(Debug, Deserialize)]
-enum Category {
-    All,
-    Academic,
-    Campus,
+/// http querys
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryType {
+    /// only the board with this id
+    board: Option<String>,
+    /// only the boards of this group, like `technology`, ignoring case.
+    /// also accepted as `gakubu`. `technology` when neither `board` nor `group` is given.
+    #[serde(alias = "gakubu")]
+    #[param(example = "technology")]
+    group: Option<String>,
+    /// only the boards of this category, like `academic`, ignoring case.
+    /// every category when not given or `All`.
+    #[serde(default, deserialize_with = "deserialize_category")]
+    #[param(value_type = Option<String>, example = "academic")]
+    category: Option<String>,
+    /// only posts updated on or after this day
+    since: Option<NaiveDate>,
+    /// only posts updated on or before this day
+    until: Option<NaiveDate>,
+    /// only posts whose 公開期間 includes this day
+    shown_on: Option<NaiveDate>,
+    /// only posts from this 発信元
+    origin: Option<String>,
+    /// only posts in this カテゴリー
+    post_category: Option<String>,
+    /// only posts whose 対象 includes this program, course and year,
+    /// like `CM(C1)[2]`, `CM[2]` or `CM`
+    #[serde(default, deserialize_with = "deserialize_target")]
+    #[param(value_type = Option<String>, example = "CM(C1)[2]")]
+    target: Option<Target>,
+    /// sort by this key. the board order is kept when not given.
+    sort: Option<SortKey>,
+    /// order of the sort
+    #[serde(default = "SortOrder::default")]
+    order: SortOrder,
+    /// maximum number of posts returned
+    limit: Option<usize>,
+    /// number of posts skipped
+    #[serde(default)]
+    offset: usize,
 }
 
-impl Default for Category {
-    fn default() -> Self {
-        Self::All
+impl QueryType {
+    fn matches(&self, post: &Post) -> bool {
+        if self.since.is_some() || self.until.is_some() {
+            let Some(updated) = post.updated_date.date else {
+                return false;
+            };
+            if self.since.is_some_and(|since| updated < since)
+                || self.until.is_some_and(|until| updated > until)
+            {
+                return false;
+            }
+        }
+        if let Some(day) = self.shown_on {
+            let (start, end) = (post.show_date.0.date, post.show_date.1.date);
+            if start.is_none_or(|start| day < start) || end.is_some_and(|end| day > end) {
+                return false;
+            }
+        }
+        if let Some(origin) = &self.origin {
+            if post.origin.trim() != origin.trim() {
+                return false;
+            }
+        }
+        if let Some(category) = &self.post_category {
+            if post.category.trim() != category.trim() {
+                return false;
+            }
+        }
+        if let Some(target) = &self.target {
+            if !post.is_for(target) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn sort(&self, posts: &mut [(&FeedState, Post)]) {
+        let Some(key) = self.sort else {
+            return;
+        };
+        posts.sort_by(|(_, a), (_, b)| {
+            let ordering = match key {
+                SortKey::Updated => a.updated_date.date.cmp(&b.updated_date.date),
+                SortKey::Id => std::cmp::Ordering::Equal,
+                SortKey::ShowStart => a.show_date.0.date.cmp(&b.show_date.0.date),
+            }
+            .then(a.post_id.cmp(&b.post_id));
+            match self.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
     }
 }
 
-/// http querys
-#[derive(Debug, Deserialize)]
-pub struct QueryType {
-    #[serde(default = "Gakubu::default")]
-    gakubu: Gakubu,
-    #[serde(default = "Category::default")]
-    category: Category,
+/// parses a `target` query like `CM(C1)[2]`. an empty query selects every post.
+pub(crate) fn deserialize_target<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Target>, D::Error> {
+    let Some(text) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    Target::parse(&text)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid target {:?}", text)))
 }
 
-/// all data
-pub async fn index(
+/// error body returned by the v2 api
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    /// what went wrong
+    pub error: String,
+}
+
+/// error response of the v2 api
+pub type ApiError = (StatusCode, Json<ErrorBody>);
+
+pub(crate) fn api_error(status: StatusCode, error: impl Into<String>) -> ApiError {
+    (
+        status,
+        Json(ErrorBody {
+            error: error.into(),
+        }),
+    )
+}
+
+/// `500` for a failure of the storage. the cause is only logged.
+pub(crate) fn storage_error(e: StorageError) -> ApiError {
+    warn!("storage error: {:?}", e);
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, "storage error")
+}
+
+/// group selected when a query names neither a board nor a group, like `gakubu` did before
+const DEFAULT_GROUP: &str = "technology";
+
+/// feeds of the board `board`, of `group` and of `category`.
+/// the boards of [`DEFAULT_GROUP`] when neither a board nor a group is given,
+/// or every board when there is no such group.
+/// `400` when one of them matches no board.
+fn feeds<'a>(
+    state: &'a ServerState,
+    board: &'a Option<String>,
+    group: &'a Option<String>,
+    category: &'a Option<String>,
+) -> Result<Vec<&'a FeedState>, ApiError> {
+    let group = match (board, group) {
+        (None, None) => state
+            .feeds()
+            .iter()
+            .any(|feed| feed.group() == DEFAULT_GROUP)
+            .then_some(DEFAULT_GROUP),
+        _ => group.as_deref(),
+    };
+    select_feeds(state, board.as_deref(), group, category.as_deref())
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+/// a post and the board it is on
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PostWithBoard {
+    /// id of the board
+    board: String,
+    /// group of the board, e.g. `technology`
+    group: String,
+    /// category of the board, e.g. `academic`
+    category: String,
+    post: Post,
+}
+
+/// a single post
+#[utoipa::path(
+    get,
+    path = "/v2/posts/{id}",
+    tag = "v2",
+    params(("id" = u32, Path, description = "投稿ID")),
+    responses(
+        (status = 200, description = "the post and the board it is on", body = PostWithBoard),
+        (status = 404, description = "no such post on any board", body = ErrorBody),
+    )
+)]
+pub async fn post(
     State(state): State<SharedState>,
-    Query(query): Query<QueryType>,
-) -> Json<Vec<Post>> {
-    match query.gakubu {
-        Gakubu::Technology => {
-            let info_academic = state.technology_academic.information.read().await.clone();
-            let info_campus = state.technology_campus.information.read().await.clone();
-            match query.category {
-                Category::All => Json(
-                    info_academic
-                        .post
-                        .into_iter()
-                        .chain(info_campus.post)
-                        .collect::<Vec<Post>>(),
-                ),
-                Category::Campus => Json(info_campus.post),
-                Category::Academic => Json(info_academic.post),
-            }
+    Path(id): Path<u32>,
+) -> Result<Json<PostWithBoard>, ApiError> {
+    find_post(&state, id).await.map(Json)
+}
+
+/// the post with `id` on any board
+async fn find_post(state: &ServerState, id: u32) -> Result<PostWithBoard, ApiError> {
+    for feed in state.feeds() {
+        let information = feed.information.read().await;
+        if let Some(post) = information.post.iter().find(|post| post.post_id == id) {
+            return Ok(PostWithBoard {
+                board: feed.id().to_string(),
+                group: feed.group().to_string(),
+                category: feed.category().to_string(),
+                post: post.clone(),
+            });
         }
-        Gakubu::Agriculture => {
-            let info_academic = state.agriculture_academic.information.read().await.clone();
-            let info_campus = state.agriculture_campus.information.read().await.clone();
-            match query.category {
-                Category::All => Json(
-                    info_academic
-                        .post
-                        .into_iter()
-                        .chain(info_campus.post)
-                        .collect::<Vec<Post>>(),
-                ),
-                Category::Campus => Json(info_campus.post),
-                Category::Academic => Json(info_academic.post),
+    }
+    Err(api_error(
+        StatusCode::NOT_FOUND,
+        format!("post {} not found", id),
+    ))
+}
+
+/// `Content-Disposition` for a download named `name` (RFC 6266)
+fn content_disposition(name: &str) -> String {
+    let encoded = name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect::<String>();
+    format!("attachment; filename*=UTF-8''{}", encoded)
+}
+
+/// a 添付ファイル of a post, downloaded from the board by this server
+#[utoipa::path(
+    get,
+    path = "/v2/attachments/{id}/{index}",
+    tag = "v2",
+    params(
+        ("id" = u32, Path, description = "投稿ID"),
+        ("index" = usize, Path, description = "position in 添付ファイル一覧, starting at 0"),
+    ),
+    responses(
+        (status = 200, description = "the file", body = String, content_type = "application/octet-stream"),
+        (status = 404, description = "no such post or attachment", body = ErrorBody),
+        (status = 403, description = "the file is not on the board of the post", body = ErrorBody),
+        (status = 429, description = "too many downloads from this client at once", body = ErrorBody),
+        (status = 502, description = "the board did not serve the file", body = ErrorBody),
+    )
+)]
+pub async fn attachment(
+    State(state): State<SharedState>,
+    Path((id, index)): Path<(u32, usize)>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let PostWithBoard { board, post, .. } = find_post(&state, id).await?;
+    let board_url = state
+        .feed(&board)
+        .map(|feed| feed.board_url().to_string())
+        .unwrap_or_default();
+    let client = connect_info.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(address)| {
+        address.ip()
+    });
+    let attachment = post.attachments.get(index).ok_or_else(|| {
+        api_error(
+            StatusCode::NOT_FOUND,
+            format!("post {} has no attachment {}", id, index),
+        )
+    })?;
+    let file = state
+        .attachments
+        .fetch_for(client, attachment, &board_url)
+        .await
+        .map_err(|e| {
+            warn!("could not download {}: {:?}", attachment.url, e);
+            match e {
+                AttachmentError::TooLarge(_) => {
+                    api_error(StatusCode::BAD_GATEWAY, "the file is too large")
+                }
+                AttachmentError::NotOnBoard(_) => {
+                    api_error(StatusCode::FORBIDDEN, "the file is not on the board")
+                }
+                AttachmentError::TooManyDownloads => {
+                    api_error(StatusCode::TOO_MANY_REQUESTS, "too many downloads at once")
+                }
+                _ => api_error(StatusCode::BAD_GATEWAY, "the board did not serve the file"),
             }
+        })?;
+    let content_type = HeaderValue::from_str(&file.content_type)
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    let disposition = HeaderValue::from_str(&content_disposition(&attachment.name))
+        .unwrap_or(HeaderValue::from_static("attachment"));
+    Ok((
+        [
+            (CONTENT_TYPE, content_type),
+            (CONTENT_DISPOSITION, disposition),
+            (
+                CACHE_CONTROL,
+                HeaderValue::from_static("public, max-age=3600"),
+            ),
+        ],
+        file.data,
+    ))
+}
+
+/// default number of search results
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+fn default_search_limit() -> usize {
+    DEFAULT_SEARCH_LIMIT
+}
+
+/// http querys for search
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// search terms, separated by spaces
+    q: String,
+    /// only the board with this id
+    board: Option<String>,
+    /// only the boards of this group, like `technology`, ignoring case.
+    /// also accepted as `gakubu`. `technology` when neither `board` nor `group` is given.
+    #[serde(alias = "gakubu")]
+    #[param(example = "technology")]
+    group: Option<String>,
+    /// only the boards of this category, like `academic`, ignoring case.
+    /// every category when not given or `All`.
+    #[serde(default, deserialize_with = "deserialize_category")]
+    #[param(value_type = Option<String>, example = "academic")]
+    category: Option<String>,
+    /// maximum number of results
+    #[serde(default = "default_search_limit")]
+    #[param(default = 20)]
+    limit: usize,
+}
+
+/// a search result
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchResult {
+    /// id of the board
+    board: String,
+    /// group of the board, e.g. `technology`
+    group: String,
+    /// category of the board, e.g. `academic`
+    category: String,
+    /// relevance, higher is better
+    score: f64,
+    /// html-escaped text around the first match, with matches wrapped in `<mark>`
+    snippet: String,
+    post: Post,
+}
+
+/// full-text search over titles, bodies, categories, origins and targets
+#[utoipa::path(
+    get,
+    path = "/v2/search",
+    tag = "v2",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "matching posts, best match first", body = [SearchResult]),
+        (status = 400, description = "the query is empty, or a board, group or category matches no board", body = ErrorBody),
+    )
+)]
+pub async fn search(
+    State(state): State<SharedState>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<SearchResult>>, ApiError> {
+    if query.q.trim().is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "query `q` is empty"));
+    }
+    let names = feeds(&state, &query.board, &query.group, &query.category)?
+        .iter()
+        .map(|feed| feed.id())
+        .collect::<Vec<_>>();
+    let hits = state
+        .search
+        .read()
+        .await
+        .search(&query.q, &names, query.limit);
+
+    let results = hits
+        .into_iter()
+        .filter_map(|hit| {
+            let feed = state.feed(&hit.feed)?;
+            Some(SearchResult {
+                board: hit.feed.clone(),
+                group: feed.group().to_string(),
+                category: feed.category().to_string(),
+                score: hit.score,
+                snippet: hit.snippet,
+                post: hit.post,
+            })
+        })
+        .collect();
+    Ok(Json(results))
+}
+
+/// http querys for the archive
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ArchiveQuery {
+    /// only the board with this id
+    board: Option<String>,
+    /// only the boards of this group, like `technology`, ignoring case.
+    /// also accepted as `gakubu`. `technology` when neither `board` nor `group` is given.
+    #[serde(alias = "gakubu")]
+    #[param(example = "technology")]
+    group: Option<String>,
+    /// only the boards of this category, like `academic`, ignoring case.
+    /// every category when not given or `All`.
+    #[serde(default, deserialize_with = "deserialize_category")]
+    #[param(value_type = Option<String>, example = "academic")]
+    category: Option<String>,
+    /// only posts still on the board on or after this day (JST)
+    from: Option<NaiveDate>,
+    /// only posts already on the board on or before this day (JST)
+    to: Option<NaiveDate>,
+}
+
+/// `date` at `time` in JST
+fn jst_datetime(date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    date.and_time(time)
+        .and_local_timezone(jst())
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+/// archived posts, including the ones that are no longer on the board
+#[utoipa::path(
+    get,
+    path = "/v2/archive",
+    tag = "v2",
+    params(ArchiveQuery),
+    responses(
+        (status = 200, description = "archived posts, most recently seen first", body = [ArchivedPost]),
+        (status = 400, description = "a board, group or category matches no board", body = ErrorBody),
+        (status = 500, description = "the archive could not be read", body = ErrorBody),
+    )
+)]
+pub async fn archive(
+    State(state): State<SharedState>,
+    Query(query): Query<ArchiveQuery>,
+) -> Result<Json<Vec<ArchivedPost>>, ApiError> {
+    let from = query.from.map(|from| jst_datetime(from, NaiveTime::MIN));
+    let to = query
+        .to
+        .and_then(|to| to.succ_opt())
+        .map(|to| jst_datetime(to, NaiveTime::MIN));
+
+    let mut posts = Vec::new();
+    for feed in feeds(&state, &query.board, &query.group, &query.category)? {
+        let archived = state
+            .storage
+            .load_archive(feed.id(), from, to)
+            .await
+            .map_err(|e| {
+                warn!("could not load archive of {}: {:?}", feed.id(), e);
+                api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not load archive")
+            })?;
+        posts.extend(archived);
+    }
+    posts.sort_by(|a, b| {
+        b.last_seen
+            .cmp(&a.last_seen)
+            .then(b.post.post_id.cmp(&a.post.post_id))
+    });
+    Ok(Json(posts))
+}
+
+/// a field that differs between two revisions
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldChange {
+    /// name of the field, as in the json of the post
+    pub field: String,
+    /// value in the previous revision
+    pub old: serde_json::Value,
+    /// value in this revision
+    pub new: serde_json::Value,
+}
+
+/// a revision and how it differs from the one before
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RevisionDiff {
+    #[serde(flatten)]
+    revision: PostRevision,
+    /// empty for the first revision
+    changes: Vec<FieldChange>,
+}
+
+/// fields of `new` that differ from `old`
+fn diff_posts(old: &Post, new: &Post) -> Vec<FieldChange> {
+    let old = serde_json::to_value(old).unwrap_or_default();
+    let new = serde_json::to_value(new).unwrap_or_default();
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return Vec::new();
+    };
+    let mut fields = old.keys().chain(new.keys()).collect::<Vec<_>>();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let old_value = old.get(field).cloned().unwrap_or_default();
+            let new_value = new.get(field).cloned().unwrap_or_default();
+            (old_value != new_value).then(|| FieldChange {
+                field: field.clone(),
+                old: old_value,
+                new: new_value,
+            })
+        })
+        .collect()
+}
+
+/// every recorded revision of a post, oldest first
+#[utoipa::path(
+    get,
+    path = "/v2/posts/{id}/revisions",
+    tag = "v2",
+    params(("id" = u32, Path, description = "投稿ID")),
+    responses(
+        (status = 200, description = "revisions of the post, oldest first", body = [RevisionDiff]),
+        (status = 404, description = "no revision of the post is recorded", body = ErrorBody),
+        (status = 500, description = "the revisions could not be read", body = ErrorBody),
+    )
+)]
+pub async fn revisions(
+    State(state): State<SharedState>,
+    Path(id): Path<u32>,
+) -> Result<Json<Vec<RevisionDiff>>, ApiError> {
+    let mut diffs = Vec::new();
+    for feed in state.feeds() {
+        let revisions = state
+            .storage
+            .load_revisions(feed.id(), id)
+            .await
+            .map_err(|e| {
+                warn!("could not load revisions of {}: {:?}", feed.id(), e);
+                api_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "could not load revisions",
+                )
+            })?;
+        let mut previous: Option<Post> = None;
+        for revision in revisions {
+            let changes = previous
+                .as_ref()
+                .map(|previous| diff_posts(previous, &revision.post))
+                .unwrap_or_default();
+            previous = Some(revision.post.clone());
+            diffs.push(RevisionDiff { revision, changes });
         }
     }
+    if diffs.is_empty() {
+        return Err(api_error(
+            StatusCode::NOT_FOUND,
+            format!("post {} not found", id),
+        ));
+    }
+    Ok(Json(diffs))
+}
+
+/// all data.
+/// the total number of matching posts before `limit`/`offset` is in the `X-Total-Count` header.
+#[utoipa::path(
+    get,
+    path = "/v2/",
+    tag = "v2",
+    params(QueryType),
+    responses(
+        (
+            status = 200,
+            description = "posts on the selected boards",
+            body = [Post],
+            headers(("X-Total-Count" = usize, description = "number of matching posts before `limit` and `offset`"))
+        ),
+        (status = 400, description = "a board, group or category matches no board", body = ErrorBody),
+    )
+)]
+pub async fn index(
+    State(state): State<SharedState>,
+    Query(query): Query<QueryType>,
+) -> Result<(HeaderMap, Json<Vec<Post>>), ApiError> {
+    let posts = select_posts(
+        feeds(&state, &query.board, &query.group, &query.category)?,
+        &query,
+    )
+    .await;
+    Ok(paginate(posts, &query))
+}
+
+/// the page of `posts` selected by `limit` and `offset`, with the total in `X-Total-Count`
+fn paginate(posts: Vec<(&FeedState, Post)>, query: &QueryType) -> (HeaderMap, Json<Vec<Post>>) {
+    let mut headers = HeaderMap::new();
+    headers.insert("X-Total-Count", HeaderValue::from(posts.len()));
+    let posts = posts
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .map(|(_, post)| post)
+        .collect();
+    (headers, Json(posts))
+}
+
+/// a board served by this server
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BoardInfo {
+    /// id of the board, used in `/v2/boards/{board}`
+    id: String,
+    /// name of the board
+    name: String,
+    /// group of the board, e.g. `technology`
+    group: String,
+    /// category of the board, e.g. `academic`
+    category: String,
+    /// top page of the board
+    url: String,
+}
+
+/// every board in the registry
+#[utoipa::path(
+    get,
+    path = "/v2/boards",
+    tag = "v2",
+    responses((status = 200, description = "the boards served, in the order of the registry", body = [BoardInfo]))
+)]
+pub async fn boards(State(state): State<SharedState>) -> Json<Vec<BoardInfo>> {
+    Json(
+        state
+            .feeds()
+            .iter()
+            .map(|feed| BoardInfo {
+                id: feed.id().to_string(),
+                name: feed.board().name.clone(),
+                group: feed.group().to_string(),
+                category: feed.category().to_string(),
+                url: feed.board_url().to_string(),
+            })
+            .collect(),
+    )
+}
+
+/// posts on a single board.
+/// takes the same querys as `/v2/`, except that `board`, `group` and `category` are ignored.
+#[utoipa::path(
+    get,
+    path = "/v2/boards/{board}",
+    tag = "v2",
+    params(("board" = String, Path, description = "id of the board"), QueryType),
+    responses(
+        (
+            status = 200,
+            description = "posts on the board",
+            body = [Post],
+            headers(("X-Total-Count" = usize, description = "number of matching posts before `limit` and `offset`"))
+        ),
+        (status = 404, description = "no such board", body = ErrorBody),
+    )
+)]
+pub async fn board_posts(
+    State(state): State<SharedState>,
+    Path(board): Path<String>,
+    Query(query): Query<QueryType>,
+) -> Result<(HeaderMap, Json<Vec<Post>>), ApiError> {
+    let feed = state
+        .feed(&board)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("board {} not found", board)))?;
+    let posts = select_posts(vec![feed], &query).await;
+    Ok(paginate(posts, &query))
+}
+
+/// posts on `feeds` matching `query`, sorted, with the feed each one is on.
+/// `limit` and `offset` are not applied.
+async fn select_posts<'a>(
+    feeds: Vec<&'a FeedState>,
+    query: &QueryType,
+) -> Vec<(&'a FeedState, Post)> {
+    let mut posts = Vec::new();
+    for feed in feeds {
+        let information = feed.information.read().await;
+        posts.extend(
+            information
+                .post
+                .iter()
+                .filter(|post| query.matches(post))
+                .map(|post| (feed, post.clone())),
+        );
+    }
+    query.sort(&mut posts);
+    posts
+}
+
+/// url of this api, like `https://example.com/tuat`, for links in feeds.
+/// guessed from the request when no public url is configured.
+fn api_root(state: &ServerState, headers: &HeaderMap, uri: &Uri) -> String {
+    if let Some(url) = &state.public_url {
+        return url.clone();
+    }
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let scheme = header("X-Forwarded-Proto").unwrap_or("http");
+    let host = header(HOST.as_str()).unwrap_or("localhost");
+    let base_path = uri
+        .path()
+        .rsplit_once("/v2/")
+        .map_or("", |(base_path, _)| base_path);
+    format!("{}://{}{}", scheme, host, base_path)
+}
+
+/// title and link of the feed of the boards selected by `query`, and its items.
+/// attachments link to `/v2/attachments/` under `api_root`.
+async fn feed_items(
+    state: &ServerState,
+    query: &QueryType,
+    api_root: &str,
+) -> Result<(String, String, Vec<FeedItem>), ApiError> {
+    let feeds = feeds(state, &query.board, &query.group, &query.category)?;
+    let title = if feeds.is_empty() || (feeds.len() > 1 && feeds.len() == state.feeds().len()) {
+        "農工大WEB掲示板".to_string()
+    } else {
+        let names = feeds
+            .iter()
+            .map(|feed| feed.board().name.as_str())
+            .collect::<Vec<_>>();
+        format!("農工大WEB掲示板 {}", names.join("・"))
+    };
+    let link = feeds
+        .first()
+        .map(|feed| feed.board_url().to_string())
+        .unwrap_or_default();
+    let items = select_posts(feeds, query)
+        .await
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .map(|(feed, post)| FeedItem {
+            link: feed.post_url(post.post_id),
+            attachments: (0..post.attachments.len())
+                .map(|index| format!("{}/v2/attachments/{}/{}", api_root, post.post_id, index))
+                .collect(),
+            post,
+        })
+        .collect();
+    Ok((title, link, items))
+}
+
+/// the selected boards as an RSS 2.0 feed.
+/// takes the same querys as `/v2/`.
+#[utoipa::path(
+    get,
+    path = "/v2/feed.rss",
+    tag = "v2",
+    params(QueryType),
+    responses(
+        (status = 200, description = "RSS 2.0 feed", body = String, content_type = "application/rss+xml"),
+        (status = 400, description = "a board, group or category matches no board", body = ErrorBody),
+    )
+)]
+pub async fn feed_rss(
+    State(state): State<SharedState>,
+    Query(query): Query<QueryType>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let (title, link, items) =
+        feed_items(&state, &query, &api_root(&state, &headers, &uri)).await?;
+    Ok((
+        [(CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        syndication::rss(&title, &link, &items),
+    ))
+}
+
+/// the selected boards as an Atom feed.
+/// takes the same querys as `/v2/`.
+#[utoipa::path(
+    get,
+    path = "/v2/feed.atom",
+    tag = "v2",
+    params(QueryType),
+    responses(
+        (status = 200, description = "Atom feed", body = String, content_type = "application/atom+xml"),
+        (status = 400, description = "a board, group or category matches no board", body = ErrorBody),
+    )
+)]
+pub async fn feed_atom(
+    State(state): State<SharedState>,
+    Query(query): Query<QueryType>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let (title, link, items) =
+        feed_items(&state, &query, &api_root(&state, &headers, &uri)).await?;
+    Ok((
+        [(CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        syndication::atom(&title, &link, &items),
+    ))
+}
+
+/// the selected boards as an iCalendar.
+/// takes the same querys as `/v2/`.
+/// every 公開期間 and every date mentioned in a post is an all day event.
+#[utoipa::path(
+    get,
+    path = "/v2/calendar.ics",
+    tag = "v2",
+    params(QueryType),
+    responses(
+        (status = 200, description = "iCalendar", body = String, content_type = "text/calendar"),
+        (status = 400, description = "a board, group or category matches no board", body = ErrorBody),
+    )
+)]
+pub async fn calendar_ics(
+    State(state): State<SharedState>,
+    Query(query): Query<QueryType>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let (title, _, items) = feed_items(&state, &query, &api_root(&state, &headers, &uri)).await?;
+    Ok((
+        [(CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        calendar::calendar(&title, &items, Utc::now()),
+    ))
+}
+
+/// changes to the selected boards as server-sent events.
+///
+/// every update that finds new, updated or removed posts sends a `changes` event
+/// with the changes of one board as json. `board`, `group` and `category` select the boards,
+/// and the other filters of `/v2/` apply to the new and updated posts.
+/// sending the `Last-Event-ID` header resumes after that event.
+/// a `resync` event means that some events were missed and the posts should be fetched again.
+#[utoipa::path(
+    get,
+    path = "/v2/stream",
+    tag = "v2",
+    params(
+        QueryType,
+        ("Last-Event-ID" = Option<u64>, Header, description = "id of the last received event"),
+    ),
+    responses(
+        (status = 200, description = "server-sent events", body = String, content_type = "text/event-stream"),
+        (status = 400, description = "a board, group or category matches no board", body = ErrorBody),
+    )
+)]
+pub async fn stream(
+    State(state): State<SharedState>,
+    Query(query): Query<QueryType>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    let names = feeds(&state, &query.board, &query.group, &query.category)?
+        .iter()
+        .map(|feed| feed.id().to_string())
+        .collect::<Vec<_>>();
+
+    let events = state
+        .stream
+        .subscribe(last_event_id)
+        .filter_map(move |message| match message {
+            StreamMessage::Changes(event) => {
+                let mut changes = event.changes;
+                if !names.contains(&changes.feed) {
+                    return None;
+                }
+                changes.new.retain(|post| query.matches(post));
+                changes.updated.retain(|post| query.matches(post));
+                if changes.is_empty() {
+                    return None;
+                }
+                Event::default()
+                    .id(event.id.to_string())
+                    .event("changes")
+                    .json_data(changes)
+                    .ok()
+            }
+            StreamMessage::Resync => Some(Event::default().event("resync").data("")),
+        })
+        .map(Ok);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
 }
 
+/// openapi document of app v2
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        index,
+        boards,
+        board_posts,
+        archive,
+        search,
+        post,
+        attachment,
+        revisions,
+        feed_rss,
+        feed_atom,
+        calendar_ics,
+        stream
+    ),
+    components(schemas(
+        Post,
+        Attachment,
+        SortKey,
+        SortOrder,
+        ErrorBody,
+        PostWithBoard,
+        BoardInfo,
+        SearchResult,
+        ArchivedPost,
+        FieldChange,
+        RevisionDiff,
+        PostRevision,
+    )),
+    tags((name = "v2", description = "クエリで絞り込めるAPI"))
+)]
+pub struct ApiDoc;
+
 /// routes for app v2
 pub fn app_v2(base_path: String, initial_state: SharedState) -> Router {
     Router::new()
         .route("/", get(index))
+        .route("/boards", get(boards))
+        .route("/boards/:board", get(board_posts))
+        .route("/archive", get(archive))
+        .route("/feed.rss", get(feed_rss))
+        .route("/feed.atom", get(feed_atom))
+        .route("/calendar.ics", get(calendar_ics))
+        .route("/stream", get(stream))
+        .route("/search", get(search))
+        .route("/posts/:id", get(post))
+        .route("/posts/:id/revisions", get(revisions))
+        .route("/attachments/:id/:index", get(attachment))
         .fallback(redirect_path!(v2 base_path))
-        .with_state(initial_state)
+        .with_state(initial_state.clone())
+        .nest("/push", app_push(initial_state))
 }
 
 #[cfg(test)]
@@ -93,20 +993,32 @@ mod test {
     use super::*;
     use crate::info_bundle::InfoBundle;
     use crate::state::ServerState;
+    use crate::storage::Storage;
     use axum::body::Body;
     use http_body_util::BodyExt;
     use hyper::{Request, StatusCode};
     use std::sync::Arc;
     use std::time::Instant;
     use tower::ServiceExt;
+    use tuat_feed_scraper::{board::Board, post::BoardDate};
 
     async fn dummy_state() -> Arc<ServerState> {
         let academic = InfoBundle::new(vec![Post::new(0), Post::new(1)], Instant::now());
         let campus = InfoBundle::new(vec![Post::new(10), Post::new(11)], Instant::now());
         let state = ServerState::init();
 
-        *state.technology_academic.information.write().await = academic;
-        *state.technology_campus.information.write().await = campus;
+        *state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await = academic;
+        *state
+            .feed("technology_campus")
+            .unwrap()
+            .information
+            .write()
+            .await = campus;
 
         Arc::new(state)
     }
@@ -170,6 +1082,627 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn check_archive() {
+        let state = dummy_state().await;
+        let now = Utc::now();
+        state
+            .storage
+            .record_seen(
+                "technology_campus",
+                vec![Post::new(10)],
+                Some([10].into()),
+                now - chrono::Duration::minutes(15),
+            )
+            .await
+            .unwrap();
+        state
+            .storage
+            .record_seen(
+                "technology_campus",
+                vec![Post::new(11)],
+                Some([11].into()),
+                now,
+            )
+            .await
+            .unwrap();
+        let app = app_v2("/".to_string(), state);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/archive?category=Campus&from=2021-01-01")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let output: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let output = output.as_array().unwrap();
+
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0]["feed"], "technology_campus");
+        assert_eq!(output[0]["post"]["投稿ID"], 11);
+        assert!(output[0]["removed_at"].is_null());
+        assert_eq!(output[1]["post"]["投稿ID"], 10);
+        assert!(output[1]["removed_at"].is_string());
+    }
+
+    fn dated_post(id: u32, updated: &str, show: (&str, &str)) -> Post {
+        let mut post = Post::new(id);
+        post.updated_date = BoardDate::parse(updated);
+        post.show_date = (BoardDate::parse(show.0), BoardDate::parse(show.1));
+        post.origin = if id.is_multiple_of(2) {
+            "教務係"
+        } else {
+            "学生支援係"
+        }
+        .to_string();
+        post.target = if id <= 2 {
+            "LM[All] / CM(C1)[1,2]"
+        } else {
+            "SM(CS)[All]"
+        }
+        .to_string();
+        post
+    }
+
+    async fn dated_state() -> Arc<ServerState> {
+        let state = ServerState::init();
+        state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await
+            .post = vec![
+            dated_post(1, "2021/10/07(Thu)", ("2021/10/01(Fri)", "2021/10/31(Sun)")),
+            dated_post(2, "2021/10/09(Sat)", ("2021/10/09(Sat)", "2022/03/31(Thu)")),
+        ];
+        state
+            .feed("technology_campus")
+            .unwrap()
+            .information
+            .write()
+            .await
+            .post = vec![
+            dated_post(3, "2021/10/08(Fri)", ("2021/09/01(Wed)", "2021/10/08(Fri)")),
+            dated_post(4, "2021/10/06(Wed)", ("2021/10/06(Wed)", "2021/11/01(Mon)")),
+        ];
+        Arc::new(state)
+    }
+
+    async fn query_ids(app: &Router, uri: &str) -> (Vec<u32>, String) {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        let total = response.headers()["X-Total-Count"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let output: Vec<Post> = serde_json::from_slice(&body).unwrap();
+        (output.iter().map(|post| post.post_id).collect(), total)
+    }
+
+    #[tokio::test]
+    async fn check_boards() {
+        let state = dated_state().await;
+        state
+            .feed("agriculture_academic")
+            .unwrap()
+            .information
+            .write()
+            .await
+            .post = vec![dated_post(
+            5,
+            "2021/10/05(Tue)",
+            ("2021/10/05(Tue)", "2021/10/31(Sun)"),
+        )];
+        let app = app_v2("/".to_string(), state);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/boards")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let boards: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(boards.as_array().unwrap().len(), 4);
+        assert_eq!(boards[1]["id"], "technology_campus");
+        assert_eq!(boards[1]["name"], "工学部 学生生活情報");
+        assert_eq!(boards[1]["group"], "technology");
+        assert_eq!(boards[1]["category"], "campus");
+        assert_eq!(
+            boards[1]["url"],
+            "http://t-board.office.tuat.ac.jp/T/boar/index.php"
+        );
+
+        assert_eq!(
+            query_ids(&app, "/boards/technology_campus?sort=id").await,
+            (vec![4, 3], "2".to_string())
+        );
+
+        // the boards of technology are selected unless a board or group is given
+        assert_eq!(
+            query_ids(&app, "/?sort=id").await,
+            (vec![4, 3, 2, 1], "4".to_string())
+        );
+        assert_eq!(
+            query_ids(&app, "/?gakubu=Technology&sort=id").await,
+            (vec![4, 3, 2, 1], "4".to_string())
+        );
+        assert_eq!(
+            query_ids(&app, "/?group=agriculture").await,
+            (vec![5], "1".to_string())
+        );
+        assert_eq!(
+            query_ids(&app, "/?board=technology_campus&sort=id").await,
+            (vec![4, 3], "2".to_string())
+        );
+        assert_eq!(
+            query_ids(&app, "/?category=academic&sort=id").await,
+            (vec![2, 1], "2".to_string())
+        );
+        assert_eq!(
+            query_ids(&app, "/?group=agriculture&category=All").await,
+            (vec![5], "1".to_string())
+        );
+
+        // a typo is an error, not a board without posts
+        for uri in [
+            "/?gakubu=Science",
+            "/?board=graduate",
+            "/?category=events",
+            "/feed.rss?group=graduate",
+            "/search?q=test&group=graduate",
+        ] {
+            let response = app
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        }
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/boards/graduate")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// url of the enclosure of the first item of the rss feed at `uri`
+    async fn enclosure_url(app: Router, uri: &str) -> String {
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .header("Host", "example.com")
+                    .header("X-Forwarded-Proto", "https")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let channel = rss::Channel::read_from(&body[..]).unwrap();
+        channel.items()[0].enclosure().unwrap().url().to_string()
+    }
+
+    #[tokio::test]
+    async fn feed_attachments_link_to_the_api() {
+        let mut post = Post::new(2);
+        post.attachments.push(Attachment::new(
+            "a.pdf".to_string(),
+            "http://t-board.office.tuat.ac.jp/T/boar/resAjax.php?bAnno=2&rNo=1".to_string(),
+        ));
+        let state = ServerState::init();
+        state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await
+            .post = vec![post];
+        let state = Arc::new(state);
+
+        let app = crate::app("/tuat".to_string(), state.clone());
+        assert_eq!(
+            enclosure_url(app, "/tuat/v2/feed.rss").await,
+            "https://example.com/tuat/v2/attachments/2/0"
+        );
+
+        let state = Arc::new(
+            Arc::try_unwrap(state)
+                .ok()
+                .unwrap()
+                .with_public_url(Some("https://api.example.com/tuat/".to_string())),
+        );
+        let app = crate::app("/tuat".to_string(), state);
+        assert_eq!(
+            enclosure_url(app, "/tuat/v2/feed.rss").await,
+            "https://api.example.com/tuat/v2/attachments/2/0"
+        );
+    }
+
+    #[tokio::test]
+    async fn check_feeds() {
+        let app = app_v2("/".to_string(), dated_state().await);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/feed.rss?gakubu=Technology&category=Academic&sort=updated")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/rss+xml; charset=utf-8"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let channel = rss::Channel::read_from(&body[..]).unwrap();
+        assert_eq!(channel.title(), "農工大WEB掲示板 工学部 教務情報");
+        let guids = channel
+            .items()
+            .iter()
+            .map(|item| item.guid().unwrap().value())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            guids,
+            vec![
+                "http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=2",
+                "http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=1",
+            ]
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/feed.atom?limit=1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/atom+xml; charset=utf-8"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let feed = atom_syndication::Feed::read_from(&body[..]).unwrap();
+        assert_eq!(feed.entries().len(), 1);
+        assert_eq!(
+            feed.entries()[0].id(),
+            "http://t-board.office.tuat.ac.jp/T/boar/vewAjax.php?i=1"
+        );
+    }
+
+    #[tokio::test]
+    async fn check_filter_sort_paginate() {
+        let app = app_v2("/".to_string(), dated_state().await);
+
+        // board order, academic first
+        assert_eq!(query_ids(&app, "/").await.0, vec![1, 2, 3, 4]);
+
+        assert_eq!(query_ids(&app, "/?sort=updated").await.0, vec![2, 3, 1, 4]);
+        assert_eq!(
+            query_ids(&app, "/?sort=updated&order=asc").await.0,
+            vec![4, 1, 3, 2]
+        );
+        assert_eq!(query_ids(&app, "/?sort=id").await.0, vec![4, 3, 2, 1]);
+        assert_eq!(
+            query_ids(&app, "/?sort=show_start&order=asc").await.0,
+            vec![3, 1, 4, 2]
+        );
+
+        assert_eq!(
+            query_ids(
+                &app,
+                "/?since=2021-10-07&until=2021-10-08&sort=id&order=asc"
+            )
+            .await
+            .0,
+            vec![1, 3]
+        );
+        assert_eq!(
+            query_ids(&app, "/?shown_on=2021-10-08&sort=id&order=asc")
+                .await
+                .0,
+            vec![1, 3, 4]
+        );
+        assert_eq!(
+            query_ids(&app, "/?origin=%E6%95%99%E5%8B%99%E4%BF%82")
+                .await
+                .0,
+            vec![2, 4]
+        );
+
+        let (ids, total) = query_ids(&app, "/?sort=id&limit=2&offset=1").await;
+        assert_eq!(ids, vec![3, 2]);
+        assert_eq!(total, "4");
+        assert_eq!(query_ids(&app, "/?target=CM").await.0, vec![1, 2]);
+        assert_eq!(query_ids(&app, "/?target=cm(c1)[2]").await.0, vec![1, 2]);
+        assert!(query_ids(&app, "/?target=CM(C1)[3]").await.0.is_empty());
+        assert_eq!(query_ids(&app, "/?target=SM[4]").await.0, vec![3, 4]);
+        assert_eq!(query_ids(&app, "/?target=").await.0, vec![1, 2, 3, 4]);
+        for target in ["%5BAll%5D", "B%5B1-4000000000%5D", "D%5BM1%5D"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(format!("/?target={}", target))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", target);
+        }
+    }
+
+    #[tokio::test]
+    async fn check_search() {
+        let state = dummy_state().await;
+        let mut post = Post::new(12);
+        post.title = "定期試験の日程".to_string();
+        post.contents = "期末試験は2月に行います。".to_string();
+        state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await
+            .post
+            .push(post.clone());
+        state.rebuild_search_index().await;
+        let app = app_v2("/".to_string(), state);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/search?q=%E8%A9%A6%E9%A8%93")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let output: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let output = output.as_array().unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0]["group"], "technology");
+        assert_eq!(output[0]["category"], "academic");
+        assert_eq!(
+            output[0]["snippet"],
+            "期末<mark>試験</mark>は2月に行います。"
+        );
+        let found: Post = serde_json::from_value(output[0]["post"].clone()).unwrap();
+        assert_eq!(found, post);
+
+        // filtered out by category
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/search?q=%E8%A9%A6%E9%A8%93&category=Campus")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let output: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert!(output.is_empty());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/search?q=")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn check_post() {
+        let app = app_v2("/".to_string(), dummy_state().await);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/posts/11")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let output: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(output["group"], "technology");
+        assert_eq!(output["category"], "campus");
+        let post: Post = serde_json::from_value(output["post"].clone()).unwrap();
+        assert_eq!(post, Post::new(11));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/posts/999")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let output: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(output.error, "post 999 not found");
+    }
+
+    #[tokio::test]
+    async fn check_attachment() {
+        let board = Router::new().route(
+            "/file",
+            get(|| async { ([("content-type", "application/pdf")], "%PDF-1.4") }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, board).await.unwrap() });
+
+        let state = Arc::new(ServerState::with_boards(
+            Storage::in_memory().unwrap(),
+            Board::defaults_at(&format!("http://{}", address)),
+        ));
+        let mut post = Post::new(2);
+        for url in [
+            format!("http://{}/file", address),
+            format!("http://{}/missing", address),
+            "http://example.com/file".to_string(),
+        ] {
+            post.attachments
+                .push(Attachment::new("講義 一覧.pdf".to_string(), url));
+        }
+        state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await
+            .post
+            .push(post);
+        let app = app_v2("/".to_string(), state);
+        let request = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let response = request("/attachments/2/0").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/pdf");
+        assert_eq!(
+            response.headers()[CONTENT_DISPOSITION],
+            "attachment; filename*=UTF-8''%E8%AC%9B%E7%BE%A9%20%E4%B8%80%E8%A6%A7.pdf"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"%PDF-1.4");
+
+        assert_eq!(
+            request("/attachments/2/1").await.status(),
+            StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            request("/attachments/2/2").await.status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            request("/attachments/2/3").await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            request("/attachments/999/0").await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn check_revisions() {
+        let state = dummy_state().await;
+        let now = Utc::now();
+        let mut post = Post::new(10);
+        state
+            .storage
+            .record_revisions("technology_campus", vec![post.clone()], now)
+            .await
+            .unwrap();
+        post.title = "10/7更新".to_string();
+        state
+            .storage
+            .record_revisions(
+                "technology_campus",
+                vec![post],
+                now + chrono::Duration::minutes(15),
+            )
+            .await
+            .unwrap();
+        let app = app_v2("/".to_string(), state);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/posts/10/revisions")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let output: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let output = output.as_array().unwrap();
+
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0]["revision"], 1);
+        assert_eq!(output[0]["changes"], serde_json::json!([]));
+        assert_eq!(output[1]["revision"], 2);
+        assert_eq!(
+            output[1]["changes"],
+            serde_json::json!([{"field": "タイトル", "old": "", "new": "10/7更新"}])
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/posts/999/revisions")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let output: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(output.error, "post 999 not found");
+    }
+
     #[should_panic]
     #[tokio::test]
     async fn check_json_formatting_index_panic() {
@@ -198,4 +1731,67 @@ mod test {
             assert!(!flg, "output {:?} did not match any correct outputs", out);
         }
     }
+
+    #[tokio::test]
+    async fn check_stream() {
+        use crate::state::FeedChanges;
+
+        let state = dummy_state().await;
+        let changes = |feed: &str, group: &str, category: &str, id| FeedChanges {
+            feed: feed.to_string(),
+            group: group.to_string(),
+            category: category.to_string(),
+            info_url: "http://example.com/post?i=".to_string(),
+            new: vec![Post::new(id)],
+            updated: Vec::new(),
+            removed: Vec::new(),
+        };
+        let seen = state
+            .stream
+            .publish(changes("agriculture_campus", "agriculture", "campus", 1));
+        state
+            .stream
+            .publish(changes("technology_academic", "technology", "academic", 2));
+        let missed =
+            state
+                .stream
+                .publish(changes("agriculture_campus", "agriculture", "campus", 3));
+        let app = app_v2("/".to_string(), state.clone());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/stream?gakubu=Agriculture&category=Campus")
+                    .header("Last-Event-ID", seen.to_string())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        let live = state
+            .stream
+            .publish(changes("agriculture_campus", "agriculture", "campus", 4));
+        let mut body = response.into_body();
+        let mut received = String::new();
+        while !received.contains(&format!("id: {}", live)) {
+            let frame = tokio::time::timeout(std::time::Duration::from_secs(5), body.frame())
+                .await
+                .expect("no event was sent")
+                .unwrap()
+                .unwrap();
+            received.push_str(std::str::from_utf8(frame.data_ref().unwrap()).unwrap());
+        }
+        let events = received
+            .split("\n\n")
+            .filter(|event| !event.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 2, "{:?}", events);
+        assert!(events[0].contains("event: changes\n"));
+        assert!(events[0].contains(&format!("id: {}", missed)));
+        assert!(events[0].contains(r#""投稿ID":3"#));
+        assert!(events[1].contains(r#""投稿ID":4"#));
+    }
 }
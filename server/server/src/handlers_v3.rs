@@ -0,0 +1,367 @@
+use crate::{
+    boards::{deserialize_category, select_feeds},
+    handlers_v2::deserialize_target,
+    redirect_path,
+    state::{FeedState, SharedState},
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use serde_derive::{Deserialize, Serialize};
+use tuat_feed_scraper::post::{AttachmentV3, PostV3, Target};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+/// version of the v3 response schema.
+/// bumped whenever a field is added to or changed in the responses.
+pub const SCHEMA_VERSION: u32 = 5;
+
+pub use response::Response;
+use response::{PostResponse, PostsResponse};
+
+/// kept in its own module because the schema aliases of `Response` are not documented
+mod response {
+    use super::{BoardPost, SCHEMA_VERSION};
+    use axum::Json;
+    use serde_derive::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    /// every v3 response is wrapped in this
+    #[derive(Debug, Serialize, Deserialize, ToSchema)]
+    #[aliases(PostsResponse = Response<Vec<BoardPost>>, PostResponse = Response<BoardPost>)]
+    pub struct Response<T> {
+        /// see [`SCHEMA_VERSION`]
+        pub schema_version: u32,
+        /// the actual response
+        pub data: T,
+    }
+
+    impl<T> Response<T> {
+        pub(super) fn new(data: T) -> Json<Self> {
+            Json(Self {
+                schema_version: SCHEMA_VERSION,
+                data,
+            })
+        }
+    }
+}
+
+/// error body returned by the v3 api
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[schema(as = v3::ErrorBody)]
+pub struct ErrorBody {
+    /// see [`SCHEMA_VERSION`]
+    pub schema_version: u32,
+    /// what went wrong
+    pub error: String,
+}
+
+/// error response of the v3 api
+pub type ApiError = (StatusCode, Json<ErrorBody>);
+
+fn api_error(status: StatusCode, error: impl Into<String>) -> ApiError {
+    (
+        status,
+        Json(ErrorBody {
+            schema_version: SCHEMA_VERSION,
+            error: error.into(),
+        }),
+    )
+}
+
+/// identifies a board
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Board {
+    /// id of the board in the registry
+    id: String,
+    /// group of the board, e.g. `technology`
+    group: String,
+    /// category of the board, e.g. `academic`
+    category: String,
+}
+
+impl Board {
+    fn of(feed: &FeedState) -> Self {
+        Self {
+            id: feed.id().to_string(),
+            group: feed.group().to_string(),
+            category: feed.category().to_string(),
+        }
+    }
+}
+
+/// a post and the board it is on
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BoardPost {
+    board: Board,
+    #[serde(flatten)]
+    post: PostV3,
+}
+
+/// http querys
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryType {
+    /// only the board with this id
+    board: Option<String>,
+    /// only the boards of this group, like `technology`, ignoring case.
+    /// also accepted as `gakubu`. every group when not given.
+    #[serde(alias = "gakubu")]
+    #[param(example = "technology")]
+    group: Option<String>,
+    /// only the boards of this category, like `academic`, ignoring case.
+    /// every category when not given or `all`.
+    #[serde(default, deserialize_with = "deserialize_category")]
+    #[param(value_type = Option<String>, example = "academic")]
+    category: Option<String>,
+    /// only posts whose 対象 includes this program, course and year,
+    /// like `CM(C1)[2]`, `CM[2]` or `CM`
+    #[serde(default, deserialize_with = "deserialize_target")]
+    #[param(value_type = Option<String>, example = "CM(C1)[2]")]
+    target: Option<Target>,
+    /// maximum number of posts returned
+    limit: Option<usize>,
+    /// number of posts skipped
+    #[serde(default)]
+    offset: usize,
+}
+
+/// list of posts
+#[utoipa::path(
+    get,
+    path = "/v3/posts",
+    tag = "v3",
+    params(QueryType),
+    responses(
+        (status = 200, description = "posts on the selected boards", body = PostsResponse),
+        (status = 400, description = "a board, group or category matches no board", body = ErrorBody),
+    )
+)]
+pub async fn posts(
+    State(state): State<SharedState>,
+    Query(query): Query<QueryType>,
+) -> Result<Json<Response<Vec<BoardPost>>>, ApiError> {
+    let feeds = select_feeds(
+        &state,
+        query.board.as_deref(),
+        query.group.as_deref(),
+        query.category.as_deref(),
+    )
+    .map_err(|e| api_error(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let mut posts = Vec::new();
+    for feed in feeds {
+        let board = Board::of(feed);
+        let information = feed.information.read().await;
+        posts.extend(
+            information
+                .post
+                .iter()
+                .filter(|post| {
+                    query
+                        .target
+                        .as_ref()
+                        .is_none_or(|target| post.is_for(target))
+                })
+                .map(|post| BoardPost {
+                    board: board.clone(),
+                    post: post.clone().into(),
+                }),
+        );
+    }
+    let posts = posts
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect();
+    Ok(Response::new(posts))
+}
+
+/// a single post
+#[utoipa::path(
+    get,
+    path = "/v3/posts/{id}",
+    tag = "v3",
+    params(("id" = u32, Path, description = "post id")),
+    responses(
+        (status = 200, description = "the post and the board it is on", body = PostResponse),
+        (status = 404, description = "no such post on any board", body = ErrorBody),
+    )
+)]
+pub async fn post(
+    State(state): State<SharedState>,
+    Path(id): Path<u32>,
+) -> Result<Json<Response<BoardPost>>, ApiError> {
+    for feed in state.feeds() {
+        let information = feed.information.read().await;
+        if let Some(post) = information.post.iter().find(|post| post.post_id == id) {
+            return Ok(Response::new(BoardPost {
+                board: Board::of(feed),
+                post: post.clone().into(),
+            }));
+        }
+    }
+    Err(api_error(
+        StatusCode::NOT_FOUND,
+        format!("post {} not found", id),
+    ))
+}
+
+/// openapi document of app v3
+#[derive(OpenApi)]
+#[openapi(
+    paths(posts, post),
+    components(schemas(
+        PostsResponse,
+        PostResponse,
+        ErrorBody,
+        Board,
+        BoardPost,
+        PostV3,
+        AttachmentV3,
+        Target,
+    )),
+    tags((name = "v3", description = "English keys and ISO 8601 dates"))
+)]
+pub struct ApiDoc;
+
+/// routes for app v3
+pub fn app_v3(base_path: String, initial_state: SharedState) -> Router {
+    Router::new()
+        .route("/posts", get(posts))
+        .route("/posts/:id", get(post))
+        .fallback(redirect_path!(v3 base_path, "/posts"))
+        .with_state(initial_state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::info_bundle::InfoBundle;
+    use crate::state::ServerState;
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use std::sync::Arc;
+    use std::time::Instant;
+    use tower::ServiceExt;
+    use tuat_feed_scraper::post::Post;
+
+    async fn dummy_state() -> Arc<ServerState> {
+        let academic = InfoBundle::new(vec![Post::new(0), Post::new(1)], Instant::now());
+        let campus = InfoBundle::new(vec![Post::new(10), Post::new(11)], Instant::now());
+        let state = ServerState::init();
+
+        *state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await = academic;
+        *state
+            .feed("agriculture_campus")
+            .unwrap()
+            .information
+            .write()
+            .await = campus;
+
+        Arc::new(state)
+    }
+
+    async fn get_json(app: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn check_posts() {
+        let app = app_v3("/".to_string(), dummy_state().await);
+
+        let (status, output) = get_json(&app, "/posts").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(output["schema_version"], SCHEMA_VERSION);
+        let ids = output["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|post| post["id"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![0, 1, 10, 11]);
+        assert_eq!(output["data"][2]["board"]["group"], "agriculture");
+        assert_eq!(output["data"][2]["board"]["category"], "campus");
+        assert_eq!(output["data"][2]["title"], "");
+
+        let (_, output) = get_json(&app, "/posts?gakubu=technology&limit=1&offset=1").await;
+        let posts = output["data"].as_array().unwrap();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0]["id"], 1);
+        assert_eq!(posts[0]["board"]["category"], "academic");
+
+        let (_, output) = get_json(&app, "/posts?group=agriculture").await;
+        assert_eq!(output["data"].as_array().unwrap().len(), 2);
+        let (_, output) = get_json(&app, "/posts?group=agriculture&category=all").await;
+        assert_eq!(output["data"].as_array().unwrap().len(), 2);
+        for uri in [
+            "/posts?group=graduate",
+            "/posts?board=technology",
+            "/posts?category=events",
+        ] {
+            let (status, output) = get_json(&app, uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+            assert_eq!(output["schema_version"], SCHEMA_VERSION);
+        }
+        let (_, output) = get_json(&app, "/posts?board=technology_academic").await;
+        assert_eq!(output["data"].as_array().unwrap().len(), 2);
+        let (_, output) = get_json(&app, "/posts?board=technology_campus").await;
+        assert_eq!(output["data"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn check_target_filter() {
+        let state = dummy_state().await;
+        let mut post = Post::new(2);
+        post.target = "LM[All] / CM(C1)[2]".to_string();
+        state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await
+            .post
+            .push(post);
+        let app = app_v3("/".to_string(), state);
+
+        let (status, output) = get_json(&app, "/posts?target=CM(C1)[2]").await;
+        assert_eq!(status, StatusCode::OK);
+        let posts = output["data"].as_array().unwrap();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0]["id"], 2);
+        assert_eq!(posts[0]["targets"][1]["course"], "C1");
+        assert_eq!(posts[0]["targets"][1]["years"], serde_json::json!([2]));
+
+        let (_, output) = get_json(&app, "/posts?target=CM[1]").await;
+        assert_eq!(output["data"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn check_post() {
+        let app = app_v3("/".to_string(), dummy_state().await);
+
+        let (status, output) = get_json(&app, "/posts/11").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(output["schema_version"], SCHEMA_VERSION);
+        assert_eq!(output["data"]["id"], 11);
+        assert_eq!(output["data"]["board"]["group"], "agriculture");
+
+        let (status, output) = get_json(&app, "/posts/999").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(output["error"], "post 999 not found");
+    }
+}
@@ -6,25 +6,59 @@
 //! This is the library part.
 
 use axum::Router;
+use handlers_admin::app_admin;
 use handlers_v1::app_v1;
 use handlers_v2::app_v2;
+use handlers_v3::app_v3;
+use openapi::app_openapi;
 use state::SharedState;
 
+/// download of attachments through the server
+pub mod attachments;
+/// registry of the boards served
+pub mod boards;
+/// iCalendar of posts
+pub mod calendar;
+/// text extracted from attachments
+pub mod extraction;
+/// handlers for the admin api
+pub mod handlers_admin;
+/// handlers for the web push api
+pub mod handlers_push;
 /// handlers for endpoints v1
 pub mod handlers_v1;
 /// handlers for endpoints v2
 pub mod handlers_v2;
+/// handlers for endpoints v3
+pub mod handlers_v3;
 /// a place to store data for a category
 pub mod info_bundle;
+/// openapi document of the api
+pub mod openapi;
 
+/// web push notifications of new posts
+pub mod push;
+/// full-text search over posts
+pub mod search;
 /// manages state
 pub mod state;
+/// persistent storage of posts
+pub mod storage;
+/// stream of changes to the feeds
+pub mod stream;
+/// RSS and Atom feeds of posts
+pub mod syndication;
+/// delivery of new posts to webhooks
+pub mod webhooks;
 
 /// router for the whole app
 pub fn app(base_path: String, initial_state: SharedState) -> Router {
     let inner_router = Router::new()
         .merge(Router::new().nest("/v1/", app_v1(base_path.clone(), initial_state.clone())))
-        .merge(Router::new().nest("/v2/", app_v2(base_path.clone(), initial_state)))
+        .merge(Router::new().nest("/v2/", app_v2(base_path.clone(), initial_state.clone())))
+        .merge(Router::new().nest("/v3/", app_v3(base_path.clone(), initial_state.clone())))
+        .merge(Router::new().nest("/admin/", app_admin(initial_state)))
+        .merge(app_openapi(&base_path))
         .fallback(redirect_path!(v2 base_path));
 
     if base_path.is_empty() || base_path == "/" {
@@ -77,6 +111,15 @@ macro_rules! redirect_path {
             axum::response::Redirect::temporary(&format!("{}/v2/", base_path))
         }
     }};
+    (v3 $base_path:ident, $sub_path:expr) => {{
+        let base_path_ = $base_path.clone();
+        let sub_path_ = $sub_path.to_string();
+        || async {
+            let base_path = base_path_;
+            let sub_path = sub_path_;
+            axum::response::Redirect::temporary(&format!("{}/v3{}", base_path, sub_path))
+        }
+    }};
 }
 
 #[cfg(test)]
@@ -92,8 +135,18 @@ mod test {
         let campus = InfoBundle::new(vec![Post::new(10), Post::new(11)], Instant::now());
         let state = ServerState::init();
 
-        *state.technology_academic.information.write().await = academic;
-        *state.technology_campus.information.write().await = campus;
+        *state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await = academic;
+        *state
+            .feed("technology_campus")
+            .unwrap()
+            .information
+            .write()
+            .await = campus;
 
         Arc::new(state)
     }
@@ -2,12 +2,26 @@
 //!
 //! This is code for a server that formatsthe TUAT feed to json
 
-use std::{env, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    env,
+    fmt::Display,
+    net::SocketAddr,
+    num::{NonZeroU64, NonZeroUsize},
+    process,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{net::TcpListener, time::sleep};
 use tower_http::trace::{self, TraceLayer};
-use tracing::{info, Level};
+use tracing::{error, info, Level};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use tuat_feed_server::{app as make_app, state::ServerState};
+use tuat_feed_scraper::{
+    FeedConfig, RateLimiter, DEFAULT_MAX_IN_FLIGHT, DEFAULT_REQUESTS_PER_SECOND,
+};
+use tuat_feed_server::{
+    app as make_app, boards::load_boards, push::VapidKeys, state::ServerState, storage::Storage,
+};
 
 /// Interval time (in minutes) for checking for new content.
 const INTERVAL_MINUTES: u64 = 15;
@@ -15,11 +29,28 @@ const INTERVAL_MINUTES: u64 = 15;
 /// Interval duration computed from `INTERVAL_MIN`.
 const INTERVAL: Duration = Duration::from_secs(INTERVAL_MINUTES * 60);
 
+/// the environment variable `name` parsed as `T`, or `None` when it is unset or empty.
+/// exits when it is set to something that does not parse.
+fn env_parse<T>(name: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = env::var(name).ok().filter(|value| !value.is_empty())?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(e) => exit_with(format_args!("invalid {} {:?}: {}", name, value, e)),
+    }
+}
+
+/// logs `message` and stops the server before it starts
+fn exit_with(message: impl Display) -> ! {
+    error!("{}", message);
+    process::exit(1)
+}
+
 #[tokio::main]
 async fn main() {
-    let state = Arc::new(ServerState::init());
-    let state_cloned = state.clone();
-
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
             std::env::var("RUST_LOG").unwrap_or_else(|_| {
@@ -31,6 +62,81 @@ async fn main() {
 
     let base_path = env::var("TUAT_FEED_API_BASEPATH").unwrap_or_else(|_| String::new());
     let addr = env::var("TUAT_FEED_API_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_owned());
+    let db_path = env::var("TUAT_FEED_API_DB")
+        .ok()
+        .filter(|path| !path.is_empty());
+    let admin_token = env::var("TUAT_FEED_API_ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+    let public_url = env::var("TUAT_FEED_API_PUBLIC_URL")
+        .ok()
+        .filter(|url| !url.is_empty());
+    let extract_texts = env::var("TUAT_FEED_API_EXTRACT_ATTACHMENTS")
+        .map(|value| !value.is_empty() && value != "0")
+        .unwrap_or(false);
+    let requests_per_second = env_parse::<f64>("TUAT_FEED_API_REQUESTS_PER_SECOND")
+        .unwrap_or(DEFAULT_REQUESTS_PER_SECOND);
+    let max_in_flight = env_parse::<NonZeroUsize>("TUAT_FEED_API_MAX_IN_FLIGHT")
+        .map_or(DEFAULT_MAX_IN_FLIGHT, NonZeroUsize::get);
+    let max_pages = env_parse::<NonZeroUsize>("TUAT_FEED_API_MAX_PAGES").map(NonZeroUsize::get);
+    let max_age = env_parse::<NonZeroU64>("TUAT_FEED_API_MAX_AGE_DAYS")
+        .map(|days| Duration::from_secs(days.get() * 24 * 60 * 60));
+    let rate_limiter = RateLimiter::new(requests_per_second, max_in_flight).unwrap_or_else(|e| {
+        exit_with(format_args!(
+            "invalid TUAT_FEED_API_REQUESTS_PER_SECOND: {}",
+            e
+        ))
+    });
+    let feed_config = FeedConfig::default()
+        .with_rate_limiter(rate_limiter)
+        .with_max_pages(max_pages)
+        .with_max_age(max_age);
+    let feed_config = match env::var("TUAT_FEED_API_T_BOARD_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+    {
+        Some(url) => {
+            info!("scraping t-board at {}", url);
+            feed_config.with_base_url(url)
+        }
+        None => feed_config,
+    };
+    let boards = match env::var("TUAT_FEED_API_BOARDS")
+        .ok()
+        .filter(|path| !path.is_empty())
+    {
+        Some(path) => {
+            info!("loading boards from {}", path);
+            load_boards(&path).expect("invalid TUAT_FEED_API_BOARDS")
+        }
+        None => feed_config.boards(),
+    };
+    let vapid = env::var("TUAT_FEED_API_VAPID_PRIVATE_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())
+        .map(|key| {
+            let subject = env::var("TUAT_FEED_API_VAPID_SUBJECT")
+                .expect("TUAT_FEED_API_VAPID_SUBJECT is required for web push");
+            VapidKeys::from_base64(&key, subject).expect("invalid TUAT_FEED_API_VAPID_PRIVATE_KEY")
+        });
+
+    let storage = match db_path {
+        Some(path) => {
+            info!("using database {}", path);
+            Storage::open(path).unwrap()
+        }
+        None => Storage::in_memory().unwrap(),
+    };
+    let state = Arc::new(
+        ServerState::with_boards(storage, boards)
+            .with_feed_config(feed_config)
+            .with_admin_token(admin_token)
+            .with_public_url(public_url)
+            .with_vapid(vapid)
+            .with_text_extraction(extract_texts),
+    );
+    state.load().await;
+    let state_cloned = state.clone();
 
     tokio::spawn(async move {
         loop {
@@ -48,5 +154,10 @@ async fn main() {
     );
 
     let listener = TcpListener::bind(&address).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
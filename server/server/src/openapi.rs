@@ -0,0 +1,169 @@
+use crate::{handlers_admin, handlers_push, handlers_v1, handlers_v2, handlers_v3};
+use axum::{response::Html, routing::get, Json, Router};
+use utoipa::{openapi::Server, OpenApi};
+
+/// page showing the openapi document with swagger ui
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="ja">
+<head>
+  <meta charset="utf-8">
+  <title>農工大WEB掲示板API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+#[derive(OpenApi)]
+#[openapi(info(
+    title = "農工大WEB掲示板API",
+    description = "農工大のWEB掲示板の情報をjsonで返すAPI"
+))]
+struct ApiDoc;
+
+/// the openapi document of every version of the api
+pub fn openapi(base_path: &str) -> utoipa::openapi::OpenApi {
+    let mut openapi = ApiDoc::openapi();
+    openapi.merge(handlers_v1::ApiDoc::openapi());
+    openapi.merge(handlers_v2::ApiDoc::openapi());
+    openapi.merge(handlers_push::ApiDoc::openapi());
+    openapi.merge(handlers_v3::ApiDoc::openapi());
+    openapi.merge(handlers_admin::ApiDoc::openapi());
+    if !(base_path.is_empty() || base_path == "/") {
+        openapi.servers = Some(vec![Server::new(base_path)]);
+    }
+    openapi
+}
+
+/// routes for the openapi document and its docs page
+pub fn app_openapi(base_path: &str) -> Router {
+    let openapi = openapi(base_path);
+    Router::new()
+        .route("/openapi.json", get(|| async move { Json(openapi) }))
+        .route("/docs", get(|| async { Html(DOCS_PAGE) }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::openapi;
+    use crate::{app, info_bundle::InfoBundle, state::ServerState};
+    use axum::{body::Body, http::StatusCode, Router};
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use std::{sync::Arc, time::Instant};
+    use tower::ServiceExt;
+    use tuat_feed_scraper::post::Post;
+
+    async fn dummy_app(base_path: &str) -> Router {
+        let state = ServerState::init();
+        *state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .write()
+            .await = InfoBundle::new(vec![Post::new(0)], Instant::now());
+        app(base_path.to_string(), Arc::new(state))
+    }
+
+    async fn get(app: &Router, uri: &str) -> (StatusCode, String) {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// every `$ref` in `value`
+    fn refs(value: &serde_json::Value, found: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    match value.as_str() {
+                        Some(reference) if key == "$ref" => found.push(reference.to_string()),
+                        _ => refs(value, found),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => values.iter().for_each(|v| refs(v, found)),
+            _ => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn documented_paths_are_routed() {
+        let app = dummy_app("").await;
+        let openapi = openapi("");
+        assert!(openapi.paths.paths.len() >= 18);
+        for path in openapi.paths.paths.keys() {
+            let mut uri = path
+                .replace("{id}", "0")
+                .replace("{index}", "0")
+                .replace("{gakubu}", "T")
+                .replace("{category}", "academic");
+            if uri.ends_with("/search") {
+                uri.push_str("?q=test");
+            }
+            // unknown paths fall back to a redirect.
+            // the body is not read since the stream never ends.
+            let status = app
+                .clone()
+                .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+                .status();
+            assert_ne!(
+                status,
+                StatusCode::TEMPORARY_REDIRECT,
+                "{} is not routed",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn referenced_schemas_exist() {
+        let json = serde_json::to_value(openapi("")).unwrap();
+        let mut found = Vec::new();
+        refs(&json, &mut found);
+        assert!(!found.is_empty());
+        for reference in found {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            assert!(
+                json["components"]["schemas"].get(name).is_some(),
+                "schema {} is not registered",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_document_and_docs() {
+        let app = dummy_app("").await;
+        let (status, body) = get(&app, "/openapi.json").await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(json["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(json["paths"]["/v2/"]["get"].is_object());
+        assert!(json["paths"]["/v1/{gakubu}"]["get"].is_object());
+        assert!(json["servers"].is_null());
+
+        let (status, body) = get(&app, "/docs").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("openapi.json"));
+
+        let app = dummy_app("/base").await;
+        let (status, body) = get(&app, "/base/openapi.json").await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["servers"][0]["url"], "/base");
+    }
+}
@@ -0,0 +1,760 @@
+use crate::{
+    boards::selects,
+    state::FeedChanges,
+    storage::{Storage, StorageError},
+};
+use aes_gcm::{aead::Aead, Aes128Gcm, KeyInit, Nonce};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use hkdf::Hkdf;
+use p256::{
+    ecdsa::{signature::Signer, Signature, SigningKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+    PublicKey, SecretKey,
+};
+use rand::{rngs::OsRng, RngCore};
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{net::IpAddr, sync::Arc};
+use thiserror::Error;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+use tuat_feed_scraper::post::Post;
+use url::Host;
+use utoipa::ToSchema;
+
+/// seconds the push service keeps a message for an offline device
+const TTL_SECONDS: u32 = 24 * 60 * 60;
+/// size of the single record of an encrypted message
+const RECORD_SIZE: u32 = 4096;
+/// maximum number of characters of the title and the body in a notification
+const MAX_TEXT_LENGTH: usize = 120;
+/// time a push service has to answer before the message is given up
+const SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// default maximum number of stored subscriptions
+pub const DEFAULT_MAX_SUBSCRIPTIONS: usize = 10_000;
+
+/// the error that happens when handling keys or sending a message
+#[derive(Error, Debug)]
+pub enum PushError {
+    /// a key is not valid base64url or not a valid key
+    #[error("invalid key")]
+    InvalidKey,
+    /// the message could not be encrypted
+    #[error("encryption failed")]
+    EncryptionError,
+    /// the message could not be serialized
+    #[error("serialization error")]
+    SerializationError(#[from] serde_json::Error),
+    /// the push service could not be reached
+    #[error("request failed")]
+    RequestError(#[from] reqwest::Error),
+    /// the endpoint is not a https url of a host on the internet
+    #[error("invalid endpoint: {0}")]
+    InvalidEndpoint(&'static str),
+}
+
+/// whether `ip` is an address on the internet,
+/// and not e.g. a loopback, private or link-local one
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // shared address space of carrier-grade nat
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // unique local
+                || (first & 0xfe00) == 0xfc00
+                // link-local
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// checks that `endpoint` is a https url of a host on the internet,
+/// so that subscribing can not make the server send requests into its own network
+pub fn validate_endpoint(endpoint: &str) -> Result<reqwest::Url, PushError> {
+    let url = reqwest::Url::parse(endpoint).map_err(|_| PushError::InvalidEndpoint("not a url"))?;
+    if url.scheme() != "https" {
+        return Err(PushError::InvalidEndpoint("has to be https"));
+    }
+    let public = match url.host() {
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain.contains('.') && !domain.ends_with(".localhost")
+        }
+        Some(Host::Ipv4(ip)) => is_public(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public(IpAddr::V6(ip)),
+        None => false,
+    };
+    if !public {
+        return Err(PushError::InvalidEndpoint("has to be a public host"));
+    }
+    Ok(url)
+}
+
+/// whether the host of `url` is not a name resolving into the local network.
+/// checked before each message, since the name may resolve elsewhere than when subscribing.
+async fn resolves_to_public(url: &reqwest::Url) -> bool {
+    let Some(Host::Domain(domain)) = url.host() else {
+        return true;
+    };
+    let port = url.port_or_known_default().unwrap_or(443);
+    match tokio::net::lookup_host((domain, port)).await {
+        Ok(addrs) => addrs.into_iter().all(|addr| is_public(addr.ip())),
+        Err(_) => false,
+    }
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>, PushError> {
+    URL_SAFE_NO_PAD
+        .decode(key.trim_end_matches('='))
+        .map_err(|_| PushError::InvalidKey)
+}
+
+/// the VAPID key pair identifying this server to push services
+#[derive(Clone)]
+pub struct VapidKeys {
+    signing_key: SigningKey,
+    subject: String,
+}
+
+impl std::fmt::Debug for VapidKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VapidKeys")
+            .field("subject", &self.subject)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VapidKeys {
+    /// keys from the base64url encoded private key.
+    /// `subject` is a `mailto:` or `https:` url push services can contact.
+    pub fn from_base64(private_key: &str, subject: impl Into<String>) -> Result<Self, PushError> {
+        let signing_key =
+            SigningKey::from_slice(&decode_key(private_key)?).map_err(|_| PushError::InvalidKey)?;
+        Ok(Self {
+            signing_key,
+            subject: subject.into(),
+        })
+    }
+
+    /// new random keys
+    pub fn generate(subject: impl Into<String>) -> Self {
+        Self {
+            signing_key: SigningKey::random(&mut OsRng),
+            subject: subject.into(),
+        }
+    }
+
+    /// base64url encoded private key, as read by [`VapidKeys::from_base64`]
+    pub fn private_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.signing_key.to_bytes())
+    }
+
+    /// base64url encoded public key, the `applicationServerKey` of the subscriptions
+    pub fn public_key(&self) -> String {
+        let point = self.signing_key.verifying_key().to_encoded_point(false);
+        URL_SAFE_NO_PAD.encode(point.as_bytes())
+    }
+
+    /// the `Authorization` header for a message to `endpoint` (RFC 8292)
+    fn authorization(&self, endpoint: &reqwest::Url, now: DateTime<Utc>) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = serde_json::json!({
+            "aud": endpoint.origin().ascii_serialization(),
+            "exp": (now + Duration::hours(12)).timestamp(),
+            "sub": self.subject,
+        });
+        let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let signing_input = format!("{}.{}", header, claims);
+        let signature: Signature = self.signing_key.sign(signing_input.as_bytes());
+        format!(
+            "vapid t={}.{}, k={}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+            self.public_key()
+        )
+    }
+}
+
+/// keys of a subscription, as in `PushSubscription.toJSON()` of the browser
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PushKeys {
+    /// base64url encoded P-256 public key of the browser
+    pub p256dh: String,
+    /// base64url encoded authentication secret
+    pub auth: String,
+}
+
+impl PushKeys {
+    /// checks that both keys can be used for encryption
+    pub fn validate(&self) -> Result<(), PushError> {
+        PublicKey::from_sec1_bytes(&decode_key(&self.p256dh)?)
+            .map_err(|_| PushError::InvalidKey)?;
+        if decode_key(&self.auth)?.len() != 16 {
+            return Err(PushError::InvalidKey);
+        }
+        Ok(())
+    }
+}
+
+/// which posts a subscription is notified of. an empty filter matches every post.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PushFilter {
+    /// only posts on the board with this id
+    #[schema(example = "technology_academic")]
+    pub board: Option<String>,
+    /// only posts on the boards of this group, ignoring case. also accepted as `gakubu`.
+    #[serde(alias = "gakubu")]
+    #[schema(example = "technology")]
+    pub group: Option<String>,
+    /// only posts on the boards of this category, ignoring case
+    #[schema(example = "academic")]
+    pub category: Option<String>,
+    /// only posts whose title or target contains this
+    pub keyword: Option<String>,
+}
+
+impl PushFilter {
+    /// whether posts on the board of `changes` can match
+    fn matches_board(&self, changes: &FeedChanges) -> bool {
+        self.board
+            .as_ref()
+            .is_none_or(|board| *board == changes.feed)
+            && selects(self.group.as_deref(), &changes.group)
+            && selects(self.category.as_deref(), &changes.category)
+    }
+
+    fn matches_post(&self, post: &Post) -> bool {
+        let Some(keyword) = &self.keyword else {
+            return true;
+        };
+        post.title.contains(keyword.as_str()) || post.target.contains(keyword.as_str())
+    }
+}
+
+/// a browser subscribed to notifications
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PushSubscription {
+    /// url of the push service the messages are sent to. identifies the subscription.
+    pub endpoint: String,
+    /// keys used to encrypt the messages
+    pub keys: PushKeys,
+    /// which posts are notified
+    pub filter: PushFilter,
+    /// when the subscription was registered
+    pub created_at: DateTime<Utc>,
+}
+
+/// the json sent in a push message, one per new post
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PushMessage {
+    /// title of the post
+    pub title: String,
+    /// beginning of the body of the post
+    pub body: String,
+    /// url of the post on the board
+    pub url: String,
+    /// 投稿ID
+    pub post_id: u32,
+    /// group of the board, e.g. `technology`
+    pub group: String,
+    /// category of the board, e.g. `academic`
+    pub category: String,
+}
+
+/// the first [`MAX_TEXT_LENGTH`] characters of `text`
+fn shorten(text: &str) -> String {
+    let text = text.trim();
+    match text.char_indices().nth(MAX_TEXT_LENGTH) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// encrypts `plaintext` for the browser with `keys` (RFC 8291, `aes128gcm`)
+pub fn encrypt(keys: &PushKeys, plaintext: &[u8]) -> Result<Vec<u8>, PushError> {
+    let mut salt = [0; 16];
+    OsRng.fill_bytes(&mut salt);
+    encrypt_with(keys, plaintext, &SecretKey::random(&mut OsRng), salt)
+}
+
+/// [`encrypt`] with the given ephemeral key and salt
+fn encrypt_with(
+    keys: &PushKeys,
+    plaintext: &[u8],
+    server_key: &SecretKey,
+    salt: [u8; 16],
+) -> Result<Vec<u8>, PushError> {
+    let browser_public = PublicKey::from_sec1_bytes(&decode_key(&keys.p256dh)?)
+        .map_err(|_| PushError::InvalidKey)?;
+    let auth = decode_key(&keys.auth)?;
+    let browser_public_bytes = browser_public.to_encoded_point(false);
+    let server_public_bytes = server_key.public_key().to_encoded_point(false);
+
+    let shared =
+        p256::ecdh::diffie_hellman(server_key.to_nonzero_scalar(), browser_public.as_affine());
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(browser_public_bytes.as_bytes());
+    key_info.extend_from_slice(server_public_bytes.as_bytes());
+    let mut ikm = [0; 32];
+    Hkdf::<Sha256>::new(Some(&auth), shared.raw_secret_bytes())
+        .expand(&key_info, &mut ikm)
+        .map_err(|_| PushError::EncryptionError)?;
+
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), &ikm);
+    let mut key = [0; 16];
+    let mut nonce = [0; 12];
+    hkdf.expand(b"Content-Encoding: aes128gcm\0", &mut key)
+        .and_then(|_| hkdf.expand(b"Content-Encoding: nonce\0", &mut nonce))
+        .map_err(|_| PushError::EncryptionError)?;
+
+    // a single record, ended by the padding delimiter 2
+    let mut record = plaintext.to_vec();
+    record.push(2);
+    if record.len() + 16 > RECORD_SIZE as usize {
+        return Err(PushError::EncryptionError);
+    }
+    let ciphertext = Aes128Gcm::new_from_slice(&key)
+        .map_err(|_| PushError::EncryptionError)?
+        .encrypt(Nonce::from_slice(&nonce), record.as_slice())
+        .map_err(|_| PushError::EncryptionError)?;
+
+    let mut message = salt.to_vec();
+    message.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    message.push(server_public_bytes.len() as u8);
+    message.extend_from_slice(server_public_bytes.as_bytes());
+    message.extend_from_slice(&ciphertext);
+    Ok(message)
+}
+
+/// sends a notification of each new post to the subscribed browsers
+#[derive(Debug, Clone)]
+pub struct WebPush {
+    storage: Storage,
+    client: reqwest::Client,
+    vapid: Option<Arc<VapidKeys>>,
+    max_subscriptions: usize,
+}
+
+impl WebPush {
+    /// subscriptions registered in `storage`. disabled until keys are given.
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            storage,
+            client: reqwest::Client::builder()
+                .timeout(SEND_TIMEOUT)
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("could not build the push client"),
+            vapid: None,
+            max_subscriptions: DEFAULT_MAX_SUBSCRIPTIONS,
+        }
+    }
+
+    /// store at most `max_subscriptions` subscriptions
+    pub fn with_max_subscriptions(mut self, max_subscriptions: usize) -> Self {
+        self.max_subscriptions = max_subscriptions;
+        self
+    }
+
+    /// maximum number of stored subscriptions
+    pub fn max_subscriptions(&self) -> usize {
+        self.max_subscriptions
+    }
+
+    /// use `vapid` to sign the messages. `None` disables web push.
+    pub fn with_vapid(mut self, vapid: Option<VapidKeys>) -> Self {
+        self.vapid = vapid.map(Arc::new);
+        self
+    }
+
+    /// the public key of the server, `None` when web push is disabled
+    pub fn public_key(&self) -> Option<String> {
+        self.vapid.as_ref().map(|vapid| vapid.public_key())
+    }
+
+    /// the messages for `changes` that `subscription` should receive
+    fn messages(subscription: &PushSubscription, changes: &FeedChanges) -> Vec<PushMessage> {
+        if !subscription.filter.matches_board(changes) {
+            return Vec::new();
+        }
+        changes
+            .new
+            .iter()
+            .filter(|post| subscription.filter.matches_post(post))
+            .map(|post| PushMessage {
+                title: shorten(&post.title),
+                body: shorten(&post.contents),
+                url: changes.post_url(post.post_id),
+                post_id: post.post_id,
+                group: changes.group.clone(),
+                category: changes.category.clone(),
+            })
+            .collect()
+    }
+
+    /// notifies every matching subscription of the new posts in `changes`.
+    /// subscriptions the push service reports as gone are removed.
+    pub async fn dispatch(&self, changes: &[FeedChanges]) -> Result<(), StorageError> {
+        let Some(vapid) = &self.vapid else {
+            return Ok(());
+        };
+        let subscriptions = self.storage.load_push_subscriptions().await?;
+        let mut deliveries = JoinSet::new();
+        for subscription in subscriptions {
+            let messages = changes
+                .iter()
+                .flat_map(|changes| Self::messages(&subscription, changes))
+                .collect::<Vec<_>>();
+            if messages.is_empty() {
+                continue;
+            }
+            let this = self.clone();
+            let vapid = vapid.clone();
+            deliveries.spawn(async move {
+                for message in messages {
+                    match this.send(&vapid, &subscription, &message).await {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(e) => {
+                            warn!("could not push to {}: {:?}", subscription.endpoint, e);
+                        }
+                    }
+                }
+            });
+        }
+        while deliveries.join_next().await.is_some() {}
+        Ok(())
+    }
+
+    /// sends `message` to `subscription`.
+    /// returns `false` when the subscription is gone and was removed.
+    async fn send(
+        &self,
+        vapid: &VapidKeys,
+        subscription: &PushSubscription,
+        message: &PushMessage,
+    ) -> Result<bool, PushError> {
+        let endpoint =
+            reqwest::Url::parse(&subscription.endpoint).map_err(|_| PushError::InvalidKey)?;
+        if !resolves_to_public(&endpoint).await {
+            return Err(PushError::InvalidEndpoint("has to be a public host"));
+        }
+        let body = encrypt(&subscription.keys, &serde_json::to_vec(message)?)?;
+        let response = self
+            .client
+            .post(endpoint.clone())
+            .header(
+                reqwest::header::AUTHORIZATION,
+                vapid.authorization(&endpoint, Utc::now()),
+            )
+            .header(reqwest::header::CONTENT_ENCODING, "aes128gcm")
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .header("TTL", TTL_SECONDS)
+            .body(body)
+            .send()
+            .await?;
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE {
+            info!("push subscription {} is gone", subscription.endpoint);
+            if let Err(e) = self
+                .storage
+                .remove_push_subscription(subscription.endpoint.clone())
+                .await
+            {
+                warn!("could not remove push subscription: {:?}", e);
+            }
+            return Ok(false);
+        }
+        if !status.is_success() {
+            warn!("push service {} returned {}", subscription.endpoint, status);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{
+        body::Bytes,
+        extract::{Path, State},
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use p256::ecdsa::{signature::Verifier, VerifyingKey};
+    use std::sync::Mutex;
+    use tokio::net::TcpListener;
+
+    // RFC 8291 appendix A
+    const UA_PRIVATE: &str = "q1dXpw3UpT5VOmu_cf_v6ih07Aems3njxI-JWgLcM94";
+    const UA_PUBLIC: &str =
+        "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4";
+    const AUTH: &str = "BTBZMqHH6r4Tts7J_aSIgg";
+
+    fn keys() -> PushKeys {
+        PushKeys {
+            p256dh: UA_PUBLIC.to_string(),
+            auth: AUTH.to_string(),
+        }
+    }
+
+    /// decrypts a message as the user agent of RFC 8291 appendix A
+    fn decrypt(message: &[u8]) -> Vec<u8> {
+        let ua_secret = SecretKey::from_slice(&decode_key(UA_PRIVATE).unwrap()).unwrap();
+        let (salt, rest) = message.split_at(16);
+        let (_record_size, rest) = rest.split_at(4);
+        let (id_length, rest) = rest.split_at(1);
+        let (server_public, ciphertext) = rest.split_at(id_length[0] as usize);
+
+        let shared = p256::ecdh::diffie_hellman(
+            ua_secret.to_nonzero_scalar(),
+            PublicKey::from_sec1_bytes(server_public)
+                .unwrap()
+                .as_affine(),
+        );
+        let mut key_info = b"WebPush: info\0".to_vec();
+        key_info.extend_from_slice(&decode_key(UA_PUBLIC).unwrap());
+        key_info.extend_from_slice(server_public);
+        let mut ikm = [0; 32];
+        Hkdf::<Sha256>::new(Some(&decode_key(AUTH).unwrap()), shared.raw_secret_bytes())
+            .expand(&key_info, &mut ikm)
+            .unwrap();
+        let hkdf = Hkdf::<Sha256>::new(Some(salt), &ikm);
+        let mut key = [0; 16];
+        let mut nonce = [0; 12];
+        hkdf.expand(b"Content-Encoding: aes128gcm\0", &mut key)
+            .unwrap();
+        hkdf.expand(b"Content-Encoding: nonce\0", &mut nonce)
+            .unwrap();
+        let mut record = Aes128Gcm::new_from_slice(&key)
+            .unwrap()
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .unwrap();
+        assert_eq!(record.pop(), Some(2));
+        record
+    }
+
+    fn titled(id: u32, title: &str) -> Post {
+        let mut post = Post::new(id);
+        post.title = title.to_string();
+        post
+    }
+
+    fn changes() -> FeedChanges {
+        FeedChanges {
+            feed: "technology_academic".to_string(),
+            group: "technology".to_string(),
+            category: "academic".to_string(),
+            info_url: "http://example.com/post?i=".to_string(),
+            new: vec![titled(1, "定期試験"), titled(2, "奨学金")],
+            updated: vec![titled(3, "試験の変更")],
+            removed: Vec::new(),
+        }
+    }
+
+    fn subscription(endpoint: &str, filter: PushFilter) -> PushSubscription {
+        PushSubscription {
+            endpoint: endpoint.to_string(),
+            keys: keys(),
+            filter,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn encryption() {
+        let server_key = SecretKey::from_slice(
+            &decode_key("yfWPiYE-n46HLnH0KqZOF1fJJU3MYrct3AELtAQ-oRw").unwrap(),
+        )
+        .unwrap();
+        let salt = decode_key("DGv6ra1nlYgDCS1FRnbzlw").unwrap();
+        let message = encrypt_with(
+            &keys(),
+            b"When I grow up, I want to be a watermelon",
+            &server_key,
+            salt.try_into().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            URL_SAFE_NO_PAD.encode(&message),
+            "DGv6ra1nlYgDCS1FRnbzlwAAEABBBP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocIn\
+             mYWAmS6TlzAC8wEqKK6PBru3jl7A_yl95bQpu6cVPTpK4Mqgkf1CXztLVBSt2Ks3oZwbuwXPXLWyouBWLVWGN\
+             WQexSgSxsj_Qulcy4a-fN"
+        );
+
+        let message = encrypt(&keys(), "新しい投稿".as_bytes()).unwrap();
+        assert_eq!(decrypt(&message), "新しい投稿".as_bytes());
+    }
+
+    #[test]
+    fn vapid_authorization() {
+        let vapid = VapidKeys::generate("mailto:admin@example.com");
+        let vapid =
+            VapidKeys::from_base64(&vapid.private_key(), "mailto:admin@example.com").unwrap();
+        let endpoint = reqwest::Url::parse("https://push.example.com:8443/send/1").unwrap();
+        let authorization = vapid.authorization(&endpoint, Utc::now());
+
+        let (token, key) = authorization
+            .strip_prefix("vapid t=")
+            .and_then(|rest| rest.split_once(", k="))
+            .unwrap();
+        assert_eq!(key, vapid.public_key());
+        let (signing_input, signature) = token.rsplit_once('.').unwrap();
+        let verifying_key = VerifyingKey::from_sec1_bytes(&decode_key(key).unwrap()).unwrap();
+        let signature = Signature::from_slice(&decode_key(signature).unwrap()).unwrap();
+        verifying_key
+            .verify(signing_input.as_bytes(), &signature)
+            .unwrap();
+
+        let claims = signing_input.split('.').nth(1).unwrap();
+        let claims: serde_json::Value =
+            serde_json::from_slice(&decode_key(claims).unwrap()).unwrap();
+        assert_eq!(claims["aud"], "https://push.example.com:8443");
+        assert_eq!(claims["sub"], "mailto:admin@example.com");
+    }
+
+    #[test]
+    fn filters() {
+        let titles = |filter| {
+            WebPush::messages(&subscription("", filter), &changes())
+                .into_iter()
+                .map(|message| message.title)
+                .collect::<Vec<_>>()
+        };
+        // only new posts are notified
+        assert_eq!(titles(PushFilter::default()), vec!["定期試験", "奨学金"]);
+        assert_eq!(
+            titles(PushFilter {
+                keyword: Some("試験".to_string()),
+                ..Default::default()
+            }),
+            vec!["定期試験"]
+        );
+        assert!(titles(PushFilter {
+            category: Some("campus".to_string()),
+            ..Default::default()
+        })
+        .is_empty());
+
+        let mut post = Post::new(4);
+        post.target = "学部3年".to_string();
+        post.contents = "あ".repeat(200);
+        let mut changes = changes();
+        changes.new = vec![post];
+        let filter = PushFilter {
+            keyword: Some("3年".to_string()),
+            ..Default::default()
+        };
+        let messages = WebPush::messages(&subscription("", filter), &changes);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].url, "http://example.com/post?i=4");
+        assert_eq!(messages[0].body.chars().count(), MAX_TEXT_LENGTH + 1);
+    }
+
+    type Received = Arc<Mutex<Vec<(String, HeaderMap, Bytes)>>>;
+
+    /// a push service that accepts `/send/ok` and reports every other subscription as gone
+    async fn push_service() -> (String, Received) {
+        let received: Received = Arc::default();
+        let app = Router::new()
+            .route(
+                "/send/:id",
+                post(
+                    |State(received): State<Received>,
+                     Path(id): Path<String>,
+                     headers: HeaderMap,
+                     body: Bytes| async move {
+                        let status = if id == "ok" {
+                            StatusCode::CREATED
+                        } else {
+                            StatusCode::GONE
+                        };
+                        received.lock().unwrap().push((id, headers, body));
+                        status
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/send", address), received)
+    }
+
+    #[tokio::test]
+    async fn delivers_encrypted_messages() {
+        let (url, received) = push_service().await;
+        let storage = Storage::in_memory().unwrap();
+        let keyword = PushFilter {
+            keyword: Some("奨学金".to_string()),
+            ..Default::default()
+        };
+        for (endpoint, filter) in [("ok", keyword), ("gone", PushFilter::default())] {
+            storage
+                .save_push_subscription(
+                    subscription(&format!("{}/{}", url, endpoint), filter),
+                    DEFAULT_MAX_SUBSCRIPTIONS,
+                )
+                .await
+                .unwrap();
+        }
+        let vapid = VapidKeys::generate("mailto:admin@example.com");
+
+        // nothing is sent while disabled
+        WebPush::new(storage.clone())
+            .dispatch(&[changes()])
+            .await
+            .unwrap();
+        assert!(received.lock().unwrap().is_empty());
+
+        WebPush::new(storage.clone())
+            .with_vapid(Some(vapid.clone()))
+            .dispatch(&[changes()])
+            .await
+            .unwrap();
+
+        let mut received = std::mem::take(&mut *received.lock().unwrap());
+        received.sort_by(|a, b| a.0.cmp(&b.0));
+        // the gone subscription stops after its first message
+        let ids = received
+            .iter()
+            .map(|(id, _, _)| id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["gone", "ok"]);
+
+        let (_, headers, body) = &received[1];
+        assert_eq!(headers["content-encoding"], "aes128gcm");
+        assert_eq!(headers["ttl"], TTL_SECONDS.to_string().as_str());
+        assert!(headers["authorization"]
+            .to_str()
+            .unwrap()
+            .ends_with(&format!("k={}", vapid.public_key())));
+        let message: PushMessage = serde_json::from_slice(&decrypt(body)).unwrap();
+        assert_eq!(message.title, "奨学金");
+        assert_eq!(message.url, "http://example.com/post?i=2");
+
+        let subscriptions = storage.load_push_subscriptions().await.unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert!(subscriptions[0].endpoint.ends_with("/ok"));
+    }
+}
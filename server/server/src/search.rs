@@ -0,0 +1,423 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+use tuat_feed_scraper::{escape_html, post::Post};
+
+/// BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// number of characters shown around the first match in a snippet
+const SNIPPET_CONTEXT: usize = 40;
+
+/// the fields of a post that are searched, with their weight in the score
+const FIELDS: [(Field, f64); 6] = [
+    (Field::Title, 3.0),
+    (Field::Category, 1.5),
+    (Field::Target, 1.0),
+    (Field::Origin, 1.0),
+    (Field::Contents, 1.0),
+    (Field::Attachments, 0.5),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Title,
+    Category,
+    Target,
+    Origin,
+    Contents,
+    /// text extracted from the attachments
+    Attachments,
+}
+
+impl Field {
+    fn text(self, post: &Post) -> Cow<'_, str> {
+        match self {
+            Field::Title => Cow::Borrowed(&post.title),
+            Field::Category => Cow::Borrowed(&post.category),
+            Field::Target => Cow::Borrowed(&post.target),
+            Field::Origin => Cow::Borrowed(&post.origin),
+            Field::Contents => Cow::Borrowed(&post.contents),
+            Field::Attachments => Cow::Owned(
+                post.attachments
+                    .iter()
+                    .filter_map(|attachment| attachment.text.as_deref())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        }
+    }
+}
+
+/// normalizes a character for matching.
+/// full-width ascii and spaces become half-width and letters are lowercased.
+/// always maps one char to one char, so positions in the normalized text match the original.
+pub(crate) fn normalize_char(c: char) -> char {
+    let c = match c {
+        '\u{3000}' => ' ',
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        c => c,
+    };
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn normalize(text: &str) -> Vec<char> {
+    text.chars().map(normalize_char).collect()
+}
+
+/// the n-grams used to look up `term`: bigrams, or the character itself for one character terms.
+fn term_grams(term: &[char]) -> Vec<String> {
+    if term.len() == 1 {
+        vec![term[0].to_string()]
+    } else {
+        term.windows(2).map(|w| w.iter().collect()).collect()
+    }
+}
+
+/// every unigram and bigram in `text`
+fn index_grams(text: &[char]) -> impl Iterator<Item = String> + '_ {
+    let unigrams = text.iter().map(|c| c.to_string());
+    let bigrams = text.windows(2).map(|w| w.iter().collect());
+    unigrams
+        .chain(bigrams)
+        .filter(|gram: &String| !gram.trim().is_empty())
+}
+
+/// positions (in chars) where `term` occurs in `text`
+fn find_all(text: &[char], term: &[char]) -> Vec<usize> {
+    if term.is_empty() || term.len() > text.len() {
+        return Vec::new();
+    }
+    (0..=text.len() - term.len())
+        .filter(|&i| text[i..i + term.len()] == *term)
+        .collect()
+}
+
+struct Document {
+    feed: String,
+    post: Post,
+    /// normalized text of each field, in the order of `FIELDS`
+    fields: Vec<Vec<char>>,
+}
+
+/// a post that matched a search
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    /// the feed the post is on
+    pub feed: String,
+    /// relevance, higher is better
+    pub score: f64,
+    /// html-escaped text around the first match, with matches wrapped in `<mark>`
+    pub snippet: String,
+    /// the post
+    pub post: Post,
+}
+
+/// n-gram index over the title, category, target, origin and contents of posts,
+/// and the text extracted from their attachments.
+///
+/// japanese text has no spaces between words, so posts are indexed by character bigrams.
+/// a query is split on whitespace and every term has to appear in the post.
+#[derive(Default)]
+pub struct SearchIndex {
+    documents: Vec<Document>,
+    postings: HashMap<String, HashSet<usize>>,
+    /// average length of each field, in the order of `FIELDS`
+    average_lengths: Vec<f64>,
+}
+
+impl std::fmt::Debug for SearchIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchIndex")
+            .field("documents", &self.documents.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SearchIndex {
+    /// builds an index over `posts`, each tagged with the feed it is on.
+    pub fn new<F: Into<String>>(posts: impl IntoIterator<Item = (F, Post)>) -> Self {
+        let mut documents = Vec::new();
+        let mut postings: HashMap<String, HashSet<usize>> = HashMap::new();
+        for (feed, post) in posts {
+            let fields = FIELDS
+                .iter()
+                .map(|(field, _)| normalize(&field.text(&post)))
+                .collect::<Vec<_>>();
+            let id = documents.len();
+            for text in fields.iter() {
+                for gram in index_grams(text) {
+                    postings.entry(gram).or_default().insert(id);
+                }
+            }
+            documents.push(Document {
+                feed: feed.into(),
+                post,
+                fields,
+            });
+        }
+        let average_lengths = (0..FIELDS.len())
+            .map(|i| {
+                let total: usize = documents.iter().map(|doc| doc.fields[i].len()).sum();
+                total as f64 / documents.len().max(1) as f64
+            })
+            .collect();
+        Self {
+            documents,
+            postings,
+            average_lengths,
+        }
+    }
+
+    /// number of indexed posts
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// whether the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// documents that contain every gram of `term`
+    fn candidates(&self, term: &[char]) -> HashSet<usize> {
+        let mut grams = term_grams(term).into_iter();
+        let Some(first) = grams.next() else {
+            return HashSet::new();
+        };
+        let mut candidates = self.postings.get(&first).cloned().unwrap_or_default();
+        for gram in grams {
+            match self.postings.get(&gram) {
+                Some(docs) => candidates.retain(|doc| docs.contains(doc)),
+                None => return HashSet::new(),
+            }
+        }
+        candidates
+    }
+
+    /// searches posts in `feeds` for `query`, best match first.
+    pub fn search(&self, query: &str, feeds: &[&str], limit: usize) -> Vec<SearchHit> {
+        let query = normalize(query);
+        let terms = query
+            .split(|c| c.is_whitespace())
+            .filter(|term| !term.is_empty())
+            .collect::<Vec<_>>();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let total = self.documents.len() as f64;
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for (i, term) in terms.iter().enumerate() {
+            // term frequency in each field of every document that really contains the term
+            let matches = self
+                .candidates(term)
+                .into_iter()
+                .filter_map(|doc| {
+                    let frequencies = self.documents[doc]
+                        .fields
+                        .iter()
+                        .map(|text| find_all(text, term).len())
+                        .collect::<Vec<_>>();
+                    frequencies
+                        .iter()
+                        .any(|&f| f > 0)
+                        .then_some((doc, frequencies))
+                })
+                .collect::<Vec<_>>();
+
+            let df = matches.len() as f64;
+            let idf = (1.0 + (total - df + 0.5) / (df + 0.5)).ln();
+            let mut term_scores = HashMap::new();
+            for (doc, frequencies) in matches {
+                let score: f64 = frequencies
+                    .iter()
+                    .zip(FIELDS.iter())
+                    .enumerate()
+                    .map(|(field, (&tf, (_, weight)))| {
+                        let tf = tf as f64;
+                        let length = self.documents[doc].fields[field].len() as f64;
+                        let average = self.average_lengths[field].max(1.0);
+                        weight * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * length / average))
+                    })
+                    .sum();
+                term_scores.insert(doc, idf * score);
+            }
+
+            // every term has to match
+            if i == 0 {
+                scores = term_scores;
+            } else {
+                scores.retain(|doc, _| term_scores.contains_key(doc));
+                for (doc, score) in scores.iter_mut() {
+                    *score += term_scores[doc];
+                }
+            }
+        }
+
+        let mut hits = scores
+            .into_iter()
+            .filter(|(doc, _)| feeds.contains(&self.documents[*doc].feed.as_str()))
+            .collect::<Vec<_>>();
+        hits.sort_by(|(a_doc, a), (b_doc, b)| {
+            b.total_cmp(a).then_with(|| {
+                self.documents[*b_doc]
+                    .post
+                    .post_id
+                    .cmp(&self.documents[*a_doc].post.post_id)
+            })
+        });
+        hits.into_iter()
+            .take(limit)
+            .map(|(doc, score)| {
+                let document = &self.documents[doc];
+                SearchHit {
+                    feed: document.feed.clone(),
+                    score,
+                    snippet: snippet(document, &terms),
+                    post: document.post.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// text around the first match, preferring the contents and then the title
+fn snippet(document: &Document, terms: &[&[char]]) -> String {
+    let order = [
+        Field::Contents,
+        Field::Title,
+        Field::Target,
+        Field::Category,
+        Field::Origin,
+        Field::Attachments,
+    ];
+    for field in order {
+        let index = FIELDS.iter().position(|(f, _)| *f == field).unwrap();
+        let normalized = &document.fields[index];
+        let original = field.text(&document.post).chars().collect::<Vec<_>>();
+
+        // mark every matched character
+        let mut marked = vec![false; normalized.len()];
+        for term in terms {
+            for start in find_all(normalized, term) {
+                marked[start..start + term.len()].fill(true);
+            }
+        }
+        let Some(first) = marked.iter().position(|&m| m) else {
+            continue;
+        };
+
+        let start = first.saturating_sub(SNIPPET_CONTEXT);
+        let end = (first + SNIPPET_CONTEXT).min(original.len());
+        let mut snippet = String::new();
+        if start > 0 {
+            snippet.push('…');
+        }
+        let mut in_mark = false;
+        for i in start..end {
+            if marked[i] != in_mark {
+                snippet.push_str(if marked[i] { "<mark>" } else { "</mark>" });
+                in_mark = marked[i];
+            }
+            snippet.push_str(&escape_html(&original[i].to_string()));
+        }
+        if in_mark {
+            snippet.push_str("</mark>");
+        }
+        if end < original.len() {
+            snippet.push('…');
+        }
+        return snippet;
+    }
+    String::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchIndex;
+    use tuat_feed_scraper::post::{Attachment, Post};
+
+    fn post(id: u32, title: &str, contents: &str) -> Post {
+        let mut post = Post::new(id);
+        post.title = title.to_string();
+        post.contents = contents.to_string();
+        post
+    }
+
+    fn index() -> SearchIndex {
+        SearchIndex::new([
+            (
+                "academic",
+                post(1, "定期試験の日程", "期末試験は2月に行います。"),
+            ),
+            ("academic", post(2, "集中講義", "試験はありません。")),
+            (
+                "campus",
+                post(3, "無線LAN停止", "ＴＵＡＴＮＥＴが停止します。"),
+            ),
+            ("campus", post(4, "奨学金", "<申請>は窓口で")),
+        ])
+    }
+
+    #[test]
+    fn title_matches_rank_first() {
+        let hits = index().search("試験", &["academic", "campus"], 10);
+        let ids = hits.iter().map(|hit| hit.post.post_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(hits[0].score > hits[1].score);
+    }
+
+    #[test]
+    fn all_terms_must_match() {
+        let hits = index().search("試験 日程", &["academic"], 10);
+        let ids = hits.iter().map(|hit| hit.post.post_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1]);
+    }
+
+    #[test]
+    fn filters_by_feed() {
+        assert!(index().search("試験", &["campus"], 10).is_empty());
+    }
+
+    #[test]
+    fn normalizes_full_width() {
+        let hits = index().search("tuatnet", &["campus"], 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "<mark>ＴＵＡＴＮＥＴ</mark>が停止します。");
+    }
+
+    #[test]
+    fn single_character() {
+        let hits = index().search("期", &["academic"], 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].post.post_id, 1);
+    }
+
+    #[test]
+    fn snippet_is_escaped() {
+        let hits = index().search("申請", &["campus"], 10);
+        assert_eq!(hits[0].snippet, "&lt;<mark>申請</mark>&gt;は窓口で");
+    }
+
+    #[test]
+    fn attachment_text() {
+        let mut with_pdf = post(5, "時間割", "添付ファイルを確認してください。");
+        let mut attachment = Attachment::new("時間割.pdf".to_string(), "http://a".to_string());
+        attachment.text = Some("月曜1限 L0011講義室".to_string());
+        with_pdf.attachments.push(attachment);
+        let index = SearchIndex::new([("academic", with_pdf)]);
+
+        let hits = index.search("l0011", &["academic"], 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "月曜1限 <mark>L0011</mark>講義室");
+    }
+
+    #[test]
+    fn limit_and_empty_query() {
+        assert_eq!(index().search("試験", &["academic"], 1).len(), 1);
+        assert!(index().search("  ", &["academic"], 10).is_empty());
+    }
+}
@@ -1,29 +1,89 @@
-use crate::info_bundle::InfoBundle;
-use std::{fmt, sync::Arc, time::Instant};
+use crate::{
+    attachments::AttachmentProxy,
+    boards::selects,
+    extraction::fill_texts,
+    info_bundle::InfoBundle,
+    push::{VapidKeys, WebPush},
+    search::SearchIndex,
+    storage::Storage,
+    stream::ChangeStream,
+    webhooks::Webhooks,
+};
+use chrono::Utc;
+use futures_util::future::join_all;
+use serde_derive::Serialize;
+use std::{collections::HashMap, fmt, sync::Arc, time::Instant};
 use tokio::sync::{Mutex, RwLock};
 use tracing::{info, info_span, warn, Instrument};
-use tuat_feed_scraper::{Feed, FeedCategory, Gakubu};
+use tuat_feed_scraper::{board::Board, post::Post, Feed, FeedConfig};
 
 /// State of the server.
-/// contains data for both academic and campus information.
+/// contains the state of every board in the registry.
 #[derive(Debug)]
 pub struct ServerState {
-    /// state for Technology Academic
-    pub technology_academic: FeedState,
-    /// state for Technology Campus
-    pub technology_campus: FeedState,
-    /// state for Agriculture Academic
-    pub agriculture_academic: FeedState,
-    /// state for Agriculture Campus
-    pub agriculture_campus: FeedState,
+    /// state of each board, in the order of the registry
+    feeds: Vec<FeedState>,
+    /// storage shared by all feeds
+    pub storage: Storage,
+    /// search index over the posts of all feeds
+    pub search: RwLock<SearchIndex>,
+    /// webhooks notified of new and updated posts
+    pub webhooks: Webhooks,
+    /// changes found by each update, streamed to clients
+    pub stream: ChangeStream,
+    /// web push notifications of new posts
+    pub push: WebPush,
+    /// attachments downloaded from the boards
+    pub attachments: AttachmentProxy,
+    /// whether the text of attachments is extracted and added to the posts
+    pub extract_texts: bool,
+    /// token required by the admin api. the admin api is disabled when `None`.
+    pub admin_token: Option<String>,
+    /// url the api is served at, like `https://example.com/tuat`, for links back to the api.
+    /// guessed from each request when `None`.
+    pub public_url: Option<String>,
 }
 
 /// Atomic shared state
 pub type SharedState = Arc<ServerState>;
 
+/// posts that changed on a feed in one update
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeedChanges {
+    /// id of the board of the feed
+    pub feed: String,
+    /// group of the board of the feed
+    pub group: String,
+    /// category of the board of the feed
+    pub category: String,
+    /// url of the page of a post without the id
+    #[serde(skip)]
+    pub info_url: String,
+    /// posts that appeared on the board
+    pub new: Vec<Post>,
+    /// posts that were edited
+    pub updated: Vec<Post>,
+    /// ids of the posts that are gone from the board
+    pub removed: Vec<u32>,
+}
+
+impl FeedChanges {
+    /// whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.new.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+
+    /// url of the page of a post on the board
+    pub fn post_url(&self, post_id: u32) -> String {
+        format!("{}{}", self.info_url, post_id)
+    }
+}
+
 /// State for each feed
 pub struct FeedState {
+    board: Board,
     feed: Mutex<Feed>,
+    storage: Storage,
     /// information from feed. rw lock for fast access.
     pub information: RwLock<InfoBundle>,
 }
@@ -35,69 +95,531 @@ impl fmt::Debug for FeedState {
 }
 
 impl FeedState {
-    fn new(gakubu: Gakubu, category: FeedCategory) -> Self {
+    fn new(board: Board, config: FeedConfig, storage: Storage) -> Self {
         Self {
-            feed: Mutex::new(Feed::new(gakubu, category)),
+            feed: Mutex::new(Feed::from_board(board.clone()).with_config(config)),
+            board,
+            storage,
             information: RwLock::new(InfoBundle::new(Vec::new(), Instant::now())),
         }
     }
 
-    async fn update(&self) {
+    /// id of the board, used as the key in storage
+    pub fn id(&self) -> &str {
+        &self.board.id
+    }
+
+    /// the board of the feed
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// group of the board, e.g. `technology`
+    pub fn group(&self) -> &str {
+        &self.board.group
+    }
+
+    /// category of the board, e.g. `academic`
+    pub fn category(&self) -> &str {
+        &self.board.category
+    }
+
+    /// what changed between the posts `old` and `new` on this feed
+    fn changes(&self, old: &[Post], new: &[Post]) -> FeedChanges {
+        let old_posts = old
+            .iter()
+            .map(|post| (post.post_id, post))
+            .collect::<HashMap<_, _>>();
+        let new_ids = new.iter().map(|post| post.post_id).collect::<Vec<_>>();
+        let mut changes = FeedChanges {
+            feed: self.board.id.clone(),
+            group: self.board.group.clone(),
+            category: self.board.category.clone(),
+            info_url: self.board.info_url.clone(),
+            new: Vec::new(),
+            updated: Vec::new(),
+            removed: Vec::new(),
+        };
+        for post in new {
+            match old_posts.get(&post.post_id) {
+                None => changes.new.push(post.clone()),
+                Some(old) if !old.same_content(post) => changes.updated.push(post.clone()),
+                Some(_) => {}
+            }
+        }
+        changes.removed = old
+            .iter()
+            .map(|post| post.post_id)
+            .filter(|id| !new_ids.contains(id))
+            .collect();
+        changes
+    }
+
+    /// url of the page of a post on the board
+    pub fn post_url(&self, post_id: u32) -> String {
+        self.board.post_url(post_id)
+    }
+
+    /// url of the top page of the board
+    pub fn board_url(&self) -> &str {
+        &self.board.board_url
+    }
+
+    /// restore the last known posts and the post cache from storage.
+    async fn load(&self) {
+        let mut feed = self.feed.lock().await;
+        match self.storage.load_cache(self.id()).await {
+            Ok(cache) => feed.extend_cache(cache),
+            Err(e) => warn!("could not load cache of {}: {:?}", self.id(), e),
+        }
+        match self.storage.load_posts(self.id()).await {
+            Ok(posts) => {
+                info!("loaded {} posts of {}", posts.len(), self.id());
+                self.information.write().await.post = posts;
+            }
+            Err(e) => warn!("could not load posts of {}: {:?}", self.id(), e),
+        }
+    }
+
+    /// fetches the feed and stores the posts.
+    /// the text of the attachments is extracted with `attachments` when given.
+    /// returns what changed, or `None` when the feed could not be fetched.
+    /// nothing is reported as changed when no posts were known before, e.g. on the first update.
+    async fn update(&self, attachments: Option<&AttachmentProxy>) -> Option<FeedChanges> {
         let mut feed = self.feed.lock().await;
         let new_info = feed.fetch().await;
         if new_info.is_err() {
             warn!("encounted error in {:?}", feed);
-            return;
+            return None;
+        }
+        let mut new_info = new_info.unwrap();
+        let now = Utc::now();
+
+        if let Some(attachments) = attachments {
+            if let Err(e) =
+                fill_texts(&self.storage, attachments, self.board_url(), &mut new_info).await
+            {
+                warn!("could not extract attachments of {}: {:?}", self.id(), e);
+            }
+        }
+
+        let cache = feed.cached_posts().cloned().collect();
+        if let Err(e) = self.storage.save_cache(self.id(), cache).await {
+            warn!("could not save cache of {}: {:?}", self.id(), e);
         }
-        let new_info = new_info.unwrap();
+        if let Err(e) = self.storage.save_posts(self.id(), new_info.clone()).await {
+            warn!("could not save posts of {}: {:?}", self.id(), e);
+        }
+        if let Err(e) = self
+            .storage
+            .record_seen(self.id(), new_info.clone(), feed.listed_ids().cloned(), now)
+            .await
+        {
+            warn!("could not archive posts of {}: {:?}", self.id(), e);
+        }
+        match self
+            .storage
+            .record_revisions(self.id(), new_info.clone(), now)
+            .await
+        {
+            Ok(changed) if !changed.is_empty() => {
+                info!("posts {:?} of {} were updated", changed, self.id())
+            }
+            Ok(_) => {}
+            Err(e) => warn!("could not record revisions of {}: {:?}", self.id(), e),
+        }
+
         let mut information = self.information.write().await;
+        let changes = if information.post.is_empty() {
+            self.changes(&[], &[])
+        } else {
+            self.changes(&information.post, &new_info)
+        };
         information.post = new_info;
+        Some(changes)
     }
 }
 
 impl ServerState {
     /// initializes the state.
     /// fetches the data from tuat feed and stores it.
+    /// nothing is persisted; use [`ServerState::with_storage`] to keep posts across restarts.
     pub fn init() -> Self {
+        Self::with_storage(Storage::in_memory().expect("could not create in-memory storage"))
+    }
+
+    /// initializes the state of the four official boards backed by `storage`.
+    /// call [`ServerState::load`] to restore the stored posts.
+    pub fn with_storage(storage: Storage) -> Self {
+        Self::with_boards(storage, Board::defaults())
+    }
+
+    /// initializes the state of `boards` backed by `storage`.
+    /// the ids of the boards should be unique, see [`crate::boards::validate`].
+    /// the boards share the default rate limit; see [`ServerState::with_feed_config`] to change it.
+    pub fn with_boards(storage: Storage, boards: Vec<Board>) -> Self {
         info!("initializing state");
+        let config = FeedConfig::default();
 
         Self {
-            technology_academic: FeedState::new(Gakubu::Technology, FeedCategory::Academic),
-            technology_campus: FeedState::new(Gakubu::Technology, FeedCategory::Campus),
-            agriculture_academic: FeedState::new(Gakubu::Agriculture, FeedCategory::Academic),
-            agriculture_campus: FeedState::new(Gakubu::Agriculture, FeedCategory::Campus),
+            feeds: boards
+                .into_iter()
+                .map(|board| FeedState::new(board, config.clone(), storage.clone()))
+                .collect(),
+            webhooks: Webhooks::new(storage.clone()),
+            stream: ChangeStream::new(),
+            push: WebPush::new(storage.clone()),
+            attachments: AttachmentProxy::new().with_config(config.clone()),
+            extract_texts: false,
+            storage,
+            search: RwLock::new(SearchIndex::default()),
+            admin_token: None,
+            public_url: None,
+        }
+    }
+
+    /// require `token` for the admin api. `None` disables the admin api.
+    pub fn with_admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token;
+        self
+    }
+
+    /// link back to the api at `url`, like `https://example.com/tuat`.
+    /// `None` guesses it from each request.
+    pub fn with_public_url(mut self, url: Option<String>) -> Self {
+        self.public_url = url.map(|url| url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// sign web push messages with `vapid`. `None` disables web push.
+    pub fn with_vapid(mut self, vapid: Option<VapidKeys>) -> Self {
+        self.push = self.push.with_vapid(vapid);
+        self
+    }
+
+    /// access the boards as set in `config`, e.g. through a mock board in tests.
+    /// all boards and the attachment downloads share the client and rate limit of `config`.
+    /// the urls of the boards are not changed; build them with [`FeedConfig::boards`] to use its base url.
+    pub fn with_feed_config(mut self, config: FeedConfig) -> Self {
+        self.attachments = self.attachments.with_config(config.clone());
+        for feed in &mut self.feeds {
+            feed.feed =
+                Mutex::new(Feed::from_board(feed.board.clone()).with_config(config.clone()));
+        }
+        self
+    }
+
+    /// extract the text of attachments (pdf, office documents and plain text) into the posts
+    pub fn with_text_extraction(mut self, enabled: bool) -> Self {
+        self.extract_texts = enabled;
+        self
+    }
+
+    /// all feeds, in the order of the registry
+    pub fn feeds(&self) -> &[FeedState] {
+        &self.feeds
+    }
+
+    /// the feed of the board `id`
+    pub fn feed(&self, id: &str) -> Option<&FeedState> {
+        self.feeds.iter().find(|feed| feed.id() == id)
+    }
+
+    /// the feeds of the boards in `group` and of `category`, in the order of the registry.
+    /// both are compared ignoring ascii case, and `None` selects every group or category.
+    pub fn feeds_of<'a>(
+        &'a self,
+        group: Option<&'a str>,
+        category: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a FeedState> {
+        self.feeds
+            .iter()
+            .filter(move |feed| selects(group, feed.group()) && selects(category, feed.category()))
+    }
+
+    /// load all feeds from storage
+    pub async fn load(&self) {
+        info!("loading state from storage");
+        for feed in self.feeds() {
+            feed.load().await;
+        }
+        self.rebuild_search_index().await;
+    }
+
+    /// rebuild the search index from the current posts of all feeds
+    pub async fn rebuild_search_index(&self) {
+        let mut posts = Vec::new();
+        for feed in self.feeds() {
+            let information = feed.information.read().await;
+            posts.extend(
+                information
+                    .post
+                    .iter()
+                    .map(|post| (feed.id().to_string(), post.clone())),
+            );
         }
+        let index = tokio::task::spawn_blocking(move || SearchIndex::new(posts))
+            .await
+            .unwrap_or_default();
+        *self.search.write().await = index;
     }
 
-    /// update all feeds
+    /// update all feeds concurrently.
+    /// the changes are published to the stream.
+    /// new and updated posts are delivered to the webhooks, and new posts are pushed to the browsers,
+    /// in the background.
     #[tracing::instrument]
     pub async fn update(&self) {
         info!("updating state");
-        self.technology_academic
-            .update()
-            .instrument(info_span!("update technology_academic"))
-            .await;
-        self.technology_campus
-            .update()
-            .instrument(info_span!("update technology_campus"))
-            .await;
-        self.agriculture_academic
-            .update()
-            .instrument(info_span!("update agriculture_academic"))
-            .await;
-        self.agriculture_campus
-            .update()
-            .instrument(info_span!("update agriculture_campus"))
+        let attachments = self.extract_texts.then_some(&self.attachments);
+        let changes = join_all(self.feeds().iter().map(|feed| {
+            feed.update(attachments)
+                .instrument(info_span!("update", feed = feed.id()))
+        }))
+        .await
+        .into_iter()
+        .flatten()
+        .filter(|changes| !changes.is_empty())
+        .collect::<Vec<_>>();
+        self.rebuild_search_index()
+            .instrument(info_span!("rebuild search index"))
             .await;
+
+        for feed_changes in &changes {
+            self.stream.publish(feed_changes.clone());
+        }
+        if !changes.is_empty() {
+            let webhooks = self.webhooks.clone();
+            let webhook_changes = changes.clone();
+            tokio::spawn(async move {
+                if let Err(e) = webhooks.dispatch(&webhook_changes).await {
+                    warn!("could not deliver to webhooks: {:?}", e);
+                }
+            });
+            let push = self.push.clone();
+            tokio::spawn(async move {
+                if let Err(e) = push.dispatch(&changes).await {
+                    warn!("could not send push notifications: {:?}", e);
+                }
+            });
+        }
         info!("state updated");
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ServerState;
+    use super::{FeedState, ServerState};
+    use crate::storage::Storage;
+    use std::sync::Arc;
+    use tokio_stream::StreamExt;
+    use tuat_feed_scraper::{board::Board, mock::MockBoard, post::Post};
+
+    #[test]
+    fn feed_changes() {
+        let state = ServerState::init();
+        let feed = state.feed("agriculture_campus").unwrap();
+        let mut edited = Post::new(2);
+        edited.title = "変更".to_string();
+
+        let changes = feed.changes(
+            &[Post::new(1), Post::new(2), Post::new(3)],
+            &[Post::new(4), edited.clone(), Post::new(3)],
+        );
+        assert_eq!(changes.feed, "agriculture_campus");
+        assert_eq!(changes.new, vec![Post::new(4)]);
+        assert_eq!(changes.updated, vec![edited]);
+        assert_eq!(changes.removed, vec![1]);
+
+        assert!(feed.changes(&[Post::new(1)], &[Post::new(1)]).is_empty());
+
+        // what the scraper adds is not an edit
+        let mut described = Post::new(1);
+        described.contents_markdown = "本文".to_string();
+        assert!(feed.changes(&[Post::new(1)], &[described]).is_empty());
+    }
+
     #[test]
     fn state_init() {
         ServerState::init();
     }
+
+    #[test]
+    fn custom_boards() {
+        let [technology_academic, _, _, agriculture_campus] = &Board::defaults()[..] else {
+            panic!("there are four official boards");
+        };
+        let mut graduate = technology_academic.clone();
+        graduate.id = "graduate_technology".to_string();
+        graduate.name = "工学府".to_string();
+        graduate.group = "graduate".to_string();
+        let boards = vec![
+            technology_academic.clone(),
+            agriculture_campus.clone(),
+            graduate,
+        ];
+        let state = ServerState::with_boards(Storage::in_memory().unwrap(), boards);
+
+        let ids = |feeds: Vec<&FeedState>| {
+            feeds
+                .iter()
+                .map(|feed| feed.id().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(state.feeds().iter().collect()),
+            [
+                "technology_academic",
+                "agriculture_campus",
+                "graduate_technology"
+            ]
+        );
+        assert_eq!(
+            ids(state
+                .feeds_of(Some("technology"), Some("academic"))
+                .collect()),
+            ["technology_academic"]
+        );
+        assert_eq!(
+            ids(state.feeds_of(Some("Graduate"), None).collect()),
+            ["graduate_technology"]
+        );
+        assert_eq!(
+            ids(state.feeds_of(None, Some("academic")).collect()),
+            ["technology_academic", "graduate_technology"]
+        );
+        assert_eq!(
+            ids(state.feeds_of(None, Some("campus")).collect()),
+            ["agriculture_campus"]
+        );
+        assert!(state.feed("technology_campus").is_none());
+    }
+
+    #[tokio::test]
+    async fn state_load() {
+        let storage = Storage::in_memory().unwrap();
+        storage
+            .save_posts("technology_campus", vec![Post::new(1), Post::new(2)])
+            .await
+            .unwrap();
+
+        let state = ServerState::with_storage(storage);
+        state.load().await;
+
+        let info = state
+            .feed("technology_campus")
+            .unwrap()
+            .information
+            .read()
+            .await;
+        assert_eq!(info.post, vec![Post::new(1), Post::new(2)]);
+        assert!(state
+            .feed("technology_academic")
+            .unwrap()
+            .information
+            .read()
+            .await
+            .post
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_from_mock() {
+        let mock = Arc::new(MockBoard::default());
+        let config = mock.config();
+        let storage = Storage::in_memory().unwrap();
+        let state =
+            ServerState::with_boards(storage.clone(), config.boards()).with_feed_config(config);
+        let mut stream = Box::pin(state.stream.subscribe(None));
+
+        state.update().await;
+        for feed in state.feeds() {
+            assert_eq!(
+                feed.information.read().await.post.len(),
+                20,
+                "{}",
+                feed.id()
+            );
+            assert_eq!(storage.load_posts(feed.id()).await.unwrap().len(), 20);
+        }
+        assert_eq!(state.search.read().await.len(), 80);
+        assert!(mock
+            .requests()
+            .contains(&"http://t-board.test/A/boar/resAjax.php?bAnno=0&par=20&skip=0".to_string()));
+
+        // the first update and an unchanged board report nothing
+        let requests = mock.requests().len();
+        state.update().await;
+        assert_eq!(mock.requests().len(), requests + 8);
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(10), stream.next())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn filled_texts_are_not_changes() {
+        let mock = Arc::new(MockBoard::default());
+        let config = mock.config();
+        let storage = Storage::in_memory().unwrap();
+        let state =
+            ServerState::with_boards(storage.clone(), config.boards()).with_feed_config(config);
+        let feed = state.feed("technology_academic").unwrap();
+
+        feed.update(None).await.unwrap();
+        feed.update(None).await.unwrap();
+        let urls = feed.information.read().await.post[0]
+            .attachments
+            .iter()
+            .map(|attachment| attachment.url.clone())
+            .collect::<Vec<_>>();
+        assert!(!urls.is_empty());
+        for url in &urls {
+            storage
+                .save_attachment_text(
+                    url.clone(),
+                    Some("講義一覧".to_string()),
+                    chrono::Utc::now(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let changes = feed.update(Some(&state.attachments)).await.unwrap();
+        assert!(changes.is_empty());
+        let post = feed.information.read().await.post[0].clone();
+        assert_eq!(post.attachments[0].text.as_deref(), Some("講義一覧"));
+        assert_eq!(
+            storage
+                .load_revisions(feed.id(), post.post_id)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn update_with_limits() {
+        let mock = Arc::new(MockBoard::default());
+        // every post of the mock was updated on 2021/10/07
+        let config = mock
+            .config()
+            .with_max_pages(Some(1))
+            .with_max_age(Some(std::time::Duration::from_secs(24 * 60 * 60)));
+        let state = ServerState::with_boards(Storage::in_memory().unwrap(), config.boards())
+            .with_feed_config(config);
+
+        state.update().await;
+        for feed in state.feeds() {
+            assert!(feed.information.read().await.post.is_empty());
+        }
+        let lists = mock
+            .requests()
+            .iter()
+            .filter(|url| url.contains("resAjax.php"))
+            .count();
+        assert_eq!(lists, 4);
+    }
 }
@@ -0,0 +1,1036 @@
+use crate::{
+    push::{PushFilter, PushKeys, PushSubscription},
+    webhooks::{Webhook, WebhookDelivery, WebhookFilter},
+};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_derive::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+use tuat_feed_scraper::post::Post;
+use utoipa::ToSchema;
+
+/// the error that happens when reading or writing the storage
+#[derive(Error, Debug)]
+pub enum StorageError {
+    /// error from sqlite
+    #[error("database error")]
+    DatabaseError(#[from] rusqlite::Error),
+    /// a stored post could not be (de)serialized
+    #[error("serialization error")]
+    SerializationError(#[from] serde_json::Error),
+    /// the blocking task panicked or was cancelled
+    #[error("storage task failed")]
+    TaskError(#[from] tokio::task::JoinError),
+}
+
+/// a post kept in the archive
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct ArchivedPost {
+    /// the feed the post was seen on
+    pub feed: String,
+    /// when the post first appeared on the board
+    pub first_seen: DateTime<Utc>,
+    /// the last update the post was still on the board
+    pub last_seen: DateTime<Utc>,
+    /// when the post was found to be gone from the board
+    pub removed_at: Option<DateTime<Utc>>,
+    /// the last known version of the post
+    pub post: Post,
+}
+
+/// a version of a post as it was seen on the board
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct PostRevision {
+    /// the feed the post was seen on
+    pub feed: String,
+    /// revision number, starting at 1 for the first version seen
+    pub revision: u32,
+    /// when this version was first seen
+    pub recorded_at: DateTime<Utc>,
+    /// the post at this revision
+    pub post: Post,
+}
+
+/// Sqlite backed storage for the posts of each feed.
+///
+/// four sets of posts are kept per feed:
+/// the posts currently on the board (in board order),
+/// the post cache of the scraper, so that posts are not fetched again after a restart,
+/// an archive of every post ever seen, including the ones that dropped off the board, and
+/// every revision of each post.
+/// the registered webhooks and the log of their deliveries are kept as well,
+/// along with the web push subscriptions and the text extracted from attachments.
+#[derive(Clone)]
+pub struct Storage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl fmt::Debug for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Storage").finish_non_exhaustive()
+    }
+}
+
+impl Storage {
+    /// opens (or creates) the database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// creates a storage that only lives in memory.
+    pub fn in_memory() -> Result<Self, StorageError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self, StorageError> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS feed_posts (
+                feed TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                post_id INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (feed, position)
+            );
+            CREATE TABLE IF NOT EXISTS post_cache (
+                feed TEXT NOT NULL,
+                post_id INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (feed, post_id)
+            );
+            CREATE TABLE IF NOT EXISTS archive (
+                feed TEXT NOT NULL,
+                post_id INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                first_seen INTEGER NOT NULL,
+                last_seen INTEGER NOT NULL,
+                removed_at INTEGER,
+                PRIMARY KEY (feed, post_id)
+            );
+            CREATE TABLE IF NOT EXISTS revisions (
+                feed TEXT NOT NULL,
+                post_id INTEGER NOT NULL,
+                revision INTEGER NOT NULL,
+                recorded_at INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (feed, post_id, revision)
+            );
+            CREATE TABLE IF NOT EXISTS webhooks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                url TEXT NOT NULL,
+                secret TEXT NOT NULL,
+                filter TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                webhook_id INTEGER NOT NULL,
+                event_id TEXT NOT NULL,
+                attempt INTEGER NOT NULL,
+                status INTEGER,
+                error TEXT,
+                attempted_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS push_subscriptions (
+                endpoint TEXT PRIMARY KEY,
+                p256dh TEXT NOT NULL,
+                auth TEXT NOT NULL,
+                filter TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS attachment_texts (
+                url TEXT PRIMARY KEY,
+                text TEXT,
+                extracted_at INTEGER NOT NULL
+            );",
+        )?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// runs `f` with the connection on the blocking thread pool.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut connection)
+        })
+        .await?
+    }
+
+    /// loads the posts that were last on the board of `feed`.
+    pub async fn load_posts(&self, feed: &str) -> Result<Vec<Post>, StorageError> {
+        let feed = feed.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection
+                .prepare("SELECT data FROM feed_posts WHERE feed = ?1 ORDER BY position")?;
+            let rows = statement.query_map(params![feed], |row| row.get::<_, String>(0))?;
+            rows.map(|data| Ok(serde_json::from_str(&data?)?)).collect()
+        })
+        .await
+    }
+
+    /// replaces the posts on the board of `feed`.
+    pub async fn save_posts(&self, feed: &str, posts: Vec<Post>) -> Result<(), StorageError> {
+        let feed = feed.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute("DELETE FROM feed_posts WHERE feed = ?1", params![feed])?;
+            {
+                let mut statement = transaction.prepare(
+                    "INSERT INTO feed_posts (feed, position, post_id, data) VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                for (position, post) in posts.iter().enumerate() {
+                    statement.execute(params![
+                        feed,
+                        position as i64,
+                        post.post_id,
+                        serde_json::to_string(post)?
+                    ])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// loads the scraper cache of `feed`.
+    pub async fn load_cache(&self, feed: &str) -> Result<Vec<Post>, StorageError> {
+        let feed = feed.to_string();
+        self.with_connection(move |connection| {
+            let mut statement =
+                connection.prepare("SELECT data FROM post_cache WHERE feed = ?1")?;
+            let rows = statement.query_map(params![feed], |row| row.get::<_, String>(0))?;
+            rows.map(|data| Ok(serde_json::from_str(&data?)?)).collect()
+        })
+        .await
+    }
+
+    /// replaces the scraper cache of `feed` with the given posts.
+    /// cached posts that are not in `posts`, e.g. because they left the board, are deleted.
+    pub async fn save_cache(&self, feed: &str, posts: Vec<Post>) -> Result<(), StorageError> {
+        let feed = feed.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let kept = posts
+                    .iter()
+                    .map(|post| post.post_id)
+                    .collect::<HashSet<_>>();
+                let cached = transaction
+                    .prepare("SELECT post_id FROM post_cache WHERE feed = ?1")?
+                    .query_map(params![feed], |row| row.get::<_, u32>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut statement = transaction
+                    .prepare("DELETE FROM post_cache WHERE feed = ?1 AND post_id = ?2")?;
+                for post_id in cached.into_iter().filter(|id| !kept.contains(id)) {
+                    statement.execute(params![feed, post_id])?;
+                }
+            }
+            {
+                let mut statement = transaction.prepare(
+                    "INSERT OR REPLACE INTO post_cache (feed, post_id, data) VALUES (?1, ?2, ?3)",
+                )?;
+                for post in posts.iter() {
+                    statement.execute(params![feed, post.post_id, serde_json::to_string(post)?])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// records that `posts` are on the board of `feed` at `now`.
+    /// `listed` is every post id on the board, see [`tuat_feed_scraper::Feed::listed_ids`].
+    /// archived posts of `feed` that are not in it are marked as removed.
+    /// nothing is marked when `listed` is `None`, i.e. the board was not read to the end.
+    pub async fn record_seen(
+        &self,
+        feed: &str,
+        posts: Vec<Post>,
+        listed: Option<HashSet<u32>>,
+        now: DateTime<Utc>,
+    ) -> Result<(), StorageError> {
+        let now = now.timestamp_millis();
+        let feed = feed.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare(
+                    "INSERT INTO archive (feed, post_id, data, first_seen, last_seen, removed_at)
+                    VALUES (?1, ?2, ?3, ?4, ?4, NULL)
+                    ON CONFLICT (feed, post_id) DO UPDATE
+                    SET data = excluded.data, last_seen = excluded.last_seen, removed_at = NULL",
+                )?;
+                for post in posts.iter() {
+                    statement.execute(params![
+                        feed,
+                        post.post_id,
+                        serde_json::to_string(post)?,
+                        now
+                    ])?;
+                }
+            }
+            if let Some(listed) = listed {
+                let mut unseen = transaction.prepare(
+                    "SELECT post_id FROM archive
+                    WHERE feed = ?1 AND removed_at IS NULL AND last_seen < ?2",
+                )?;
+                let missing = unseen
+                    .query_map(params![feed, now], |row| row.get::<_, u32>(0))?
+                    .filter(|id| id.as_ref().map_or(true, |id| !listed.contains(id)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut statement = transaction.prepare(
+                    "UPDATE archive SET removed_at = ?3 WHERE feed = ?1 AND post_id = ?2",
+                )?;
+                for post_id in missing {
+                    statement.execute(params![feed, post_id, now])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// loads the archived posts of `feed` that were on the board at some point between `from` and `to`.
+    /// newest first.
+    pub async fn load_archive(
+        &self,
+        feed: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<ArchivedPost>, StorageError> {
+        let from = from.map(|from| from.timestamp_millis()).unwrap_or(i64::MIN);
+        let to = to.map(|to| to.timestamp_millis()).unwrap_or(i64::MAX);
+        let feed = feed.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT data, first_seen, last_seen, removed_at FROM archive
+                WHERE feed = ?1 AND first_seen <= ?3 AND (removed_at IS NULL OR removed_at >= ?2)
+                ORDER BY last_seen DESC, post_id DESC",
+            )?;
+            let rows = statement.query_map(params![feed, from, to], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                ))
+            })?;
+            rows.map(|row| {
+                let (data, first_seen, last_seen, removed_at) = row?;
+                Ok(ArchivedPost {
+                    feed: feed.to_string(),
+                    first_seen: from_millis(first_seen),
+                    last_seen: from_millis(last_seen),
+                    removed_at: removed_at.map(from_millis),
+                    post: serde_json::from_str(&data)?,
+                })
+            })
+            .collect()
+        })
+        .await
+    }
+
+    /// loads a single archived post.
+    pub async fn load_archived_post(
+        &self,
+        feed: &str,
+        post_id: u32,
+    ) -> Result<Option<ArchivedPost>, StorageError> {
+        let feed = feed.to_string();
+        self.with_connection(move |connection| {
+            let row = connection
+                .query_row(
+                    "SELECT data, first_seen, last_seen, removed_at FROM archive
+                    WHERE feed = ?1 AND post_id = ?2",
+                    params![feed, post_id],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, i64>(1)?,
+                            row.get::<_, i64>(2)?,
+                            row.get::<_, Option<i64>>(3)?,
+                        ))
+                    },
+                )
+                .optional()?;
+            row.map(|(data, first_seen, last_seen, removed_at)| {
+                Ok(ArchivedPost {
+                    feed: feed.to_string(),
+                    first_seen: from_millis(first_seen),
+                    last_seen: from_millis(last_seen),
+                    removed_at: removed_at.map(from_millis),
+                    post: serde_json::from_str(&data)?,
+                })
+            })
+            .transpose()
+        })
+        .await
+    }
+
+    /// stores a new revision for each post in `posts` that differs from its latest revision.
+    /// returns the ids of posts that changed since they were first recorded.
+    pub async fn record_revisions(
+        &self,
+        feed: &str,
+        posts: Vec<Post>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<u32>, StorageError> {
+        let now = now.timestamp_millis();
+        let feed = feed.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let mut changed = Vec::new();
+            {
+                let mut latest = transaction.prepare(
+                    "SELECT revision, data FROM revisions WHERE feed = ?1 AND post_id = ?2
+                    ORDER BY revision DESC LIMIT 1",
+                )?;
+                let mut insert = transaction.prepare(
+                    "INSERT INTO revisions (feed, post_id, revision, recorded_at, data)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                )?;
+                for post in posts.iter() {
+                    let last = latest
+                        .query_row(params![feed, post.post_id], |row| {
+                            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+                        })
+                        .optional()?;
+                    let revision = match last {
+                        Some((revision, data)) => {
+                            if serde_json::from_str::<Post>(&data)?.same_content(post) {
+                                continue;
+                            }
+                            changed.push(post.post_id);
+                            revision + 1
+                        }
+                        None => 1,
+                    };
+                    insert.execute(params![
+                        feed,
+                        post.post_id,
+                        revision,
+                        now,
+                        serde_json::to_string(post)?
+                    ])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(changed)
+        })
+        .await
+    }
+
+    /// loads every revision of a post, oldest first.
+    pub async fn load_revisions(
+        &self,
+        feed: &str,
+        post_id: u32,
+    ) -> Result<Vec<PostRevision>, StorageError> {
+        let feed = feed.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT revision, recorded_at, data FROM revisions
+                WHERE feed = ?1 AND post_id = ?2 ORDER BY revision",
+            )?;
+            let rows = statement.query_map(params![feed, post_id], |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?;
+            rows.map(|row| {
+                let (revision, recorded_at, data) = row?;
+                Ok(PostRevision {
+                    feed: feed.to_string(),
+                    revision,
+                    recorded_at: from_millis(recorded_at),
+                    post: serde_json::from_str(&data)?,
+                })
+            })
+            .collect()
+        })
+        .await
+    }
+
+    /// registers a webhook.
+    pub async fn add_webhook(
+        &self,
+        url: String,
+        secret: String,
+        filter: WebhookFilter,
+        now: DateTime<Utc>,
+    ) -> Result<Webhook, StorageError> {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO webhooks (url, secret, filter, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    url,
+                    secret,
+                    serde_json::to_string(&filter)?,
+                    now.timestamp_millis()
+                ],
+            )?;
+            Ok(Webhook {
+                id: connection.last_insert_rowid(),
+                url,
+                secret,
+                filter,
+                created_at: from_millis(now.timestamp_millis()),
+            })
+        })
+        .await
+    }
+
+    /// unregisters a webhook and drops its deliveries.
+    /// returns whether it was registered.
+    pub async fn remove_webhook(&self, id: i64) -> Result<bool, StorageError> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let removed = transaction.execute("DELETE FROM webhooks WHERE id = ?1", params![id])?;
+            transaction.execute(
+                "DELETE FROM webhook_deliveries WHERE webhook_id = ?1",
+                params![id],
+            )?;
+            transaction.commit()?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    /// loads every registered webhook, oldest first.
+    pub async fn load_webhooks(&self) -> Result<Vec<Webhook>, StorageError> {
+        self.with_connection(move |connection| {
+            let mut statement = connection
+                .prepare("SELECT id, url, secret, filter, created_at FROM webhooks ORDER BY id")?;
+            let rows = statement.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            })?;
+            rows.map(|row| {
+                let (id, url, secret, filter, created_at) = row?;
+                Ok(Webhook {
+                    id,
+                    url,
+                    secret,
+                    filter: serde_json::from_str(&filter)?,
+                    created_at: from_millis(created_at),
+                })
+            })
+            .collect()
+        })
+        .await
+    }
+
+    /// logs an attempt to deliver an event.
+    pub async fn record_delivery(&self, delivery: WebhookDelivery) -> Result<(), StorageError> {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO webhook_deliveries
+                (webhook_id, event_id, attempt, status, error, attempted_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    delivery.webhook_id,
+                    delivery.event_id,
+                    delivery.attempt,
+                    delivery.status,
+                    delivery.error,
+                    delivery.attempted_at.timestamp_millis()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// loads the delivery log of a webhook, newest first.
+    pub async fn load_deliveries(
+        &self,
+        webhook_id: i64,
+    ) -> Result<Vec<WebhookDelivery>, StorageError> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT event_id, attempt, status, error, attempted_at FROM webhook_deliveries
+                WHERE webhook_id = ?1 ORDER BY id DESC",
+            )?;
+            let rows = statement.query_map(params![webhook_id], |row| {
+                Ok(WebhookDelivery {
+                    webhook_id,
+                    event_id: row.get(0)?,
+                    attempt: row.get(1)?,
+                    status: row.get(2)?,
+                    error: row.get(3)?,
+                    attempted_at: from_millis(row.get(4)?),
+                })
+            })?;
+            Ok(rows.collect::<Result<_, _>>()?)
+        })
+        .await
+    }
+
+    /// registers a web push subscription.
+    /// a subscription with the same endpoint is replaced.
+    /// returns `false` and stores nothing when there are already `max_subscriptions` other subscriptions.
+    pub async fn save_push_subscription(
+        &self,
+        subscription: PushSubscription,
+        max_subscriptions: usize,
+    ) -> Result<bool, StorageError> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let (count, exists) = transaction.query_row(
+                "SELECT COUNT(*), COUNT(*) FILTER (WHERE endpoint = ?1) FROM push_subscriptions",
+                params![subscription.endpoint],
+                |row| Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)? > 0)),
+            )?;
+            if !exists && count >= max_subscriptions {
+                return Ok(false);
+            }
+            transaction.execute(
+                "INSERT OR REPLACE INTO push_subscriptions
+                (endpoint, p256dh, auth, filter, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    subscription.endpoint,
+                    subscription.keys.p256dh,
+                    subscription.keys.auth,
+                    serde_json::to_string(&subscription.filter)?,
+                    subscription.created_at.timestamp_millis()
+                ],
+            )?;
+            transaction.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    /// unregisters the web push subscription of `endpoint`.
+    /// returns whether it was registered.
+    pub async fn remove_push_subscription(&self, endpoint: String) -> Result<bool, StorageError> {
+        self.with_connection(move |connection| {
+            let removed = connection.execute(
+                "DELETE FROM push_subscriptions WHERE endpoint = ?1",
+                params![endpoint],
+            )?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    /// loads every web push subscription, oldest first.
+    pub async fn load_push_subscriptions(&self) -> Result<Vec<PushSubscription>, StorageError> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT endpoint, p256dh, auth, filter, created_at FROM push_subscriptions
+                ORDER BY created_at, endpoint",
+            )?;
+            let rows = statement.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            })?;
+            rows.map(|row| {
+                let (endpoint, p256dh, auth, filter, created_at) = row?;
+                Ok(PushSubscription {
+                    endpoint,
+                    keys: PushKeys { p256dh, auth },
+                    filter: serde_json::from_str::<PushFilter>(&filter)?,
+                    created_at: from_millis(created_at),
+                })
+            })
+            .collect()
+        })
+        .await
+    }
+
+    /// loads the text extracted from the attachments at `urls`.
+    /// urls that were never extracted are missing. `None` means the file has no text.
+    pub async fn load_attachment_texts(
+        &self,
+        urls: Vec<String>,
+    ) -> Result<HashMap<String, Option<String>>, StorageError> {
+        self.with_connection(move |connection| {
+            let mut statement =
+                connection.prepare("SELECT text FROM attachment_texts WHERE url = ?1")?;
+            let mut texts = HashMap::new();
+            for url in urls {
+                let text = statement
+                    .query_row(params![url], |row| row.get::<_, Option<String>>(0))
+                    .optional()?;
+                if let Some(text) = text {
+                    texts.insert(url, text);
+                }
+            }
+            Ok(texts)
+        })
+        .await
+    }
+
+    /// records the text extracted from the attachment at `url`. `None` when it has no text.
+    pub async fn save_attachment_text(
+        &self,
+        url: String,
+        text: Option<String>,
+        extracted_at: DateTime<Utc>,
+    ) -> Result<(), StorageError> {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT OR REPLACE INTO attachment_texts (url, text, extracted_at) VALUES (?1, ?2, ?3)",
+                params![url, text, extracted_at.timestamp_millis()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+fn from_millis(millis: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(millis).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Storage;
+    use crate::webhooks::{WebhookDelivery, WebhookFilter};
+    use chrono::{Duration, Utc};
+    use std::collections::HashSet;
+    use tuat_feed_scraper::post::Post;
+
+    #[tokio::test]
+    async fn webhooks_and_deliveries() {
+        let storage = Storage::in_memory().unwrap();
+        let filter = WebhookFilter {
+            group: Some("technology".to_string()),
+            keyword: Some("試験".to_string()),
+            ..Default::default()
+        };
+        let first = storage
+            .add_webhook(
+                "http://example.com/a".to_string(),
+                "secret".to_string(),
+                filter.clone(),
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+        let second = storage
+            .add_webhook(
+                "http://example.com/b".to_string(),
+                "secret".to_string(),
+                WebhookFilter::default(),
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.load_webhooks().await.unwrap(),
+            vec![first.clone(), second.clone()]
+        );
+        assert_eq!(storage.load_webhooks().await.unwrap()[0].filter, filter);
+
+        for (attempt, status) in [(1, None), (2, Some(200))] {
+            storage
+                .record_delivery(WebhookDelivery {
+                    webhook_id: first.id,
+                    event_id: "event".to_string(),
+                    attempt,
+                    status,
+                    error: status.is_none().then(|| "timeout".to_string()),
+                    attempted_at: Utc::now(),
+                })
+                .await
+                .unwrap();
+        }
+        let deliveries = storage.load_deliveries(first.id).await.unwrap();
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[0].attempt, 2);
+        assert!(deliveries[0].succeeded());
+        assert_eq!(deliveries[1].error.as_deref(), Some("timeout"));
+        assert!(storage.load_deliveries(second.id).await.unwrap().is_empty());
+
+        assert!(storage.remove_webhook(first.id).await.unwrap());
+        assert!(!storage.remove_webhook(first.id).await.unwrap());
+        assert_eq!(storage.load_webhooks().await.unwrap(), vec![second]);
+        assert!(storage.load_deliveries(first.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn posts_round_trip() {
+        let storage = Storage::in_memory().unwrap();
+        let mut post = Post::new(3);
+        post.title = "タイトル".to_string();
+
+        storage
+            .save_posts("feed", vec![post.clone(), Post::new(1)])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.load_posts("feed").await.unwrap(),
+            vec![post.clone(), Post::new(1)]
+        );
+
+        // saving again replaces the list
+        storage
+            .save_posts("feed", vec![Post::new(2)])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.load_posts("feed").await.unwrap(),
+            vec![Post::new(2)]
+        );
+        assert!(storage.load_posts("other").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cache_is_replaced() {
+        let storage = Storage::in_memory().unwrap();
+        storage
+            .save_cache("feed", vec![Post::new(1), Post::new(2)])
+            .await
+            .unwrap();
+        storage
+            .save_cache("other", vec![Post::new(1)])
+            .await
+            .unwrap();
+        storage
+            .save_cache("feed", vec![Post::new(2), Post::new(3)])
+            .await
+            .unwrap();
+
+        let mut cache = storage.load_cache("feed").await.unwrap();
+        cache.sort_by_key(|post| post.post_id);
+        assert_eq!(cache, vec![Post::new(2), Post::new(3)]);
+        assert_eq!(
+            storage.load_cache("other").await.unwrap(),
+            vec![Post::new(1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn persists_to_file() {
+        let path = std::env::temp_dir().join(format!("tuat-feed-{}.sqlite", std::process::id()));
+        {
+            let storage = Storage::open(&path).unwrap();
+            storage
+                .save_posts("feed", vec![Post::new(1)])
+                .await
+                .unwrap();
+        }
+        let storage = Storage::open(&path).unwrap();
+        assert_eq!(
+            storage.load_posts("feed").await.unwrap(),
+            vec![Post::new(1)]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn archive_keeps_removed_posts() {
+        let storage = Storage::in_memory().unwrap();
+        let t0 = Utc::now();
+        let t1 = t0 + Duration::minutes(15);
+
+        storage
+            .record_seen(
+                "feed",
+                vec![Post::new(1), Post::new(2)],
+                Some(HashSet::from([1, 2])),
+                t0,
+            )
+            .await
+            .unwrap();
+        storage
+            .record_seen(
+                "feed",
+                vec![Post::new(2), Post::new(3)],
+                Some(HashSet::from([2, 3])),
+                t1,
+            )
+            .await
+            .unwrap();
+
+        let archive = storage.load_archive("feed", None, None).await.unwrap();
+        assert_eq!(archive.len(), 3);
+
+        let removed = storage
+            .load_archived_post("feed", 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(removed.post, Post::new(1));
+        assert_eq!(removed.first_seen.timestamp_millis(), t0.timestamp_millis());
+        assert_eq!(
+            removed.removed_at.map(|t| t.timestamp_millis()),
+            Some(t1.timestamp_millis())
+        );
+
+        let kept = storage
+            .load_archived_post("feed", 2)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(kept.first_seen.timestamp_millis(), t0.timestamp_millis());
+        assert_eq!(kept.last_seen.timestamp_millis(), t1.timestamp_millis());
+        assert_eq!(kept.removed_at, None);
+
+        // post 3 only appeared at t1
+        let before = storage
+            .load_archive("feed", None, Some(t0 + Duration::minutes(1)))
+            .await
+            .unwrap();
+        let mut ids = before.iter().map(|p| p.post.post_id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+
+        // post 1 was gone by then
+        let after = storage
+            .load_archive("feed", Some(t1 + Duration::minutes(1)), None)
+            .await
+            .unwrap();
+        let mut ids = after.iter().map(|p| p.post.post_id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn unlisted_posts_are_removed_only_after_a_full_listing() {
+        let storage = Storage::in_memory().unwrap();
+        let t0 = Utc::now();
+        let removed_at = |id| {
+            let storage = storage.clone();
+            async move {
+                storage
+                    .load_archived_post("feed", id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .removed_at
+            }
+        };
+        storage
+            .record_seen(
+                "feed",
+                vec![Post::new(1), Post::new(2), Post::new(3)],
+                Some(HashSet::from([1, 2, 3])),
+                t0,
+            )
+            .await
+            .unwrap();
+
+        // cut off by the page limit
+        storage
+            .record_seen("feed", vec![Post::new(1)], None, t0 + Duration::minutes(15))
+            .await
+            .unwrap();
+        assert_eq!(removed_at(2).await, None);
+
+        // too old to be returned, but still on the board
+        storage
+            .record_seen(
+                "feed",
+                vec![Post::new(1)],
+                Some(HashSet::from([1, 2])),
+                t0 + Duration::minutes(30),
+            )
+            .await
+            .unwrap();
+        assert_eq!(removed_at(2).await, None);
+        assert!(removed_at(3).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn revisions_are_recorded_on_change() {
+        let storage = Storage::in_memory().unwrap();
+        let t0 = Utc::now();
+        let mut post = Post::new(1);
+
+        let changed = storage
+            .record_revisions("feed", vec![post.clone()], t0)
+            .await
+            .unwrap();
+        assert!(changed.is_empty());
+
+        // nothing changed
+        let changed = storage
+            .record_revisions("feed", vec![post.clone()], t0 + Duration::minutes(15))
+            .await
+            .unwrap();
+        assert!(changed.is_empty());
+
+        // what the scraper adds is not an edit
+        let mut described = post.clone();
+        described.contents_html = "<p></p>".to_string();
+        let changed = storage
+            .record_revisions("feed", vec![described], t0 + Duration::minutes(20))
+            .await
+            .unwrap();
+        assert!(changed.is_empty());
+
+        post.title = "10/7更新".to_string();
+        let changed = storage
+            .record_revisions("feed", vec![post.clone()], t0 + Duration::minutes(30))
+            .await
+            .unwrap();
+        assert_eq!(changed, vec![1]);
+
+        let revisions = storage.load_revisions("feed", 1).await.unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].revision, 1);
+        assert_eq!(revisions[0].post, Post::new(1));
+        assert_eq!(revisions[1].revision, 2);
+        assert_eq!(revisions[1].post, post);
+        assert!(storage.load_revisions("other", 1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn attachment_texts() {
+        let storage = Storage::in_memory().unwrap();
+        let (a, b) = (
+            "http://example.com/a".to_string(),
+            "http://example.com/b".to_string(),
+        );
+        storage
+            .save_attachment_text(a.clone(), Some("日程".to_string()), Utc::now())
+            .await
+            .unwrap();
+        storage
+            .save_attachment_text(b.clone(), None, Utc::now())
+            .await
+            .unwrap();
+
+        let texts = storage
+            .load_attachment_texts(vec![
+                a.clone(),
+                b.clone(),
+                "http://example.com/c".to_string(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(texts.len(), 2);
+        assert_eq!(texts[&a].as_deref(), Some("日程"));
+        assert_eq!(texts[&b], None);
+    }
+}
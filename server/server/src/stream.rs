@@ -0,0 +1,185 @@
+use crate::state::FeedChanges;
+use chrono::Utc;
+use std::{collections::VecDeque, sync::Mutex};
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+
+/// number of past events kept for clients resuming with `Last-Event-ID`
+const HISTORY_LENGTH: usize = 64;
+
+/// changes of a feed, numbered in the order they were published
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamEvent {
+    /// id of the event. larger ids are newer.
+    pub id: u64,
+    /// what changed
+    pub changes: FeedChanges,
+}
+
+/// a message sent to a subscriber
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamMessage {
+    /// changes of a feed
+    Changes(StreamEvent),
+    /// some events were missed. the subscriber should fetch the posts again.
+    Resync,
+}
+
+#[derive(Debug)]
+struct History {
+    next_id: u64,
+    events: VecDeque<StreamEvent>,
+}
+
+/// broadcasts the changes found by [`crate::state::ServerState::update`]
+/// and keeps the latest ones so that subscribers can resume.
+#[derive(Debug)]
+pub struct ChangeStream {
+    sender: broadcast::Sender<StreamEvent>,
+    history: Mutex<History>,
+}
+
+impl Default for ChangeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeStream {
+    /// creates a stream without subscribers.
+    /// ids start at the current unix time in milliseconds so that they keep increasing across restarts.
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(HISTORY_LENGTH);
+        Self {
+            sender,
+            history: Mutex::new(History {
+                next_id: Utc::now().timestamp_millis().max(0) as u64,
+                events: VecDeque::with_capacity(HISTORY_LENGTH),
+            }),
+        }
+    }
+
+    /// sends `changes` to every subscriber and returns the id of the event
+    pub fn publish(&self, changes: FeedChanges) -> u64 {
+        let mut history = self.history.lock().unwrap();
+        let event = StreamEvent {
+            id: history.next_id,
+            changes,
+        };
+        history.next_id += 1;
+        if history.events.len() == HISTORY_LENGTH {
+            history.events.pop_front();
+        }
+        history.events.push_back(event.clone());
+        // no subscribers is not an error
+        let _ = self.sender.send(event);
+        history.next_id - 1
+    }
+
+    /// every message published from now on.
+    ///
+    /// with `last_event_id`, the events published after it are sent first.
+    /// [`StreamMessage::Resync`] is sent when they are no longer kept,
+    /// and whenever the subscriber falls too far behind.
+    pub fn subscribe(&self, last_event_id: Option<u64>) -> impl Stream<Item = StreamMessage> {
+        // subscribe while holding the lock so that no event is missed or sent twice
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        let mut missed = Vec::new();
+        if let Some(last_event_id) = last_event_id {
+            let oldest = history
+                .events
+                .front()
+                .map(|event| event.id)
+                .unwrap_or(history.next_id);
+            if last_event_id.saturating_add(1) < oldest {
+                missed.push(StreamMessage::Resync);
+            }
+            missed.extend(
+                history
+                    .events
+                    .iter()
+                    .filter(|event| event.id > last_event_id)
+                    .cloned()
+                    .map(StreamMessage::Changes),
+            );
+        }
+        drop(history);
+
+        let live = BroadcastStream::new(receiver).map(|message| match message {
+            Ok(event) => StreamMessage::Changes(event),
+            Err(BroadcastStreamRecvError::Lagged(_)) => StreamMessage::Resync,
+        });
+        tokio_stream::iter(missed).chain(live)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChangeStream, StreamMessage};
+    use crate::state::FeedChanges;
+    use tokio_stream::StreamExt;
+    use tuat_feed_scraper::post::Post;
+
+    fn changes(id: u32) -> FeedChanges {
+        FeedChanges {
+            feed: "technology_academic".to_string(),
+            group: "technology".to_string(),
+            category: "academic".to_string(),
+            info_url: "http://example.com/post?i=".to_string(),
+            new: vec![Post::new(id)],
+            updated: Vec::new(),
+            removed: Vec::new(),
+        }
+    }
+
+    fn ids(messages: &[StreamMessage]) -> Vec<Option<u64>> {
+        messages
+            .iter()
+            .map(|message| match message {
+                StreamMessage::Changes(event) => Some(event.id),
+                StreamMessage::Resync => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn live_events() {
+        let stream = ChangeStream::new();
+        let subscriber = stream.subscribe(None);
+        let first = stream.publish(changes(1));
+        let second = stream.publish(changes(2));
+        assert_eq!(second, first + 1);
+
+        let messages = subscriber.take(2).collect::<Vec<_>>().await;
+        assert_eq!(ids(&messages), vec![Some(first), Some(second)]);
+        let StreamMessage::Changes(event) = &messages[1] else {
+            panic!("expected changes");
+        };
+        assert_eq!(event.changes, changes(2));
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        let stream = ChangeStream::new();
+        let first = stream.publish(changes(1));
+        let second = stream.publish(changes(2));
+
+        let subscriber = stream.subscribe(Some(first));
+        let third = stream.publish(changes(3));
+        let messages = subscriber.take(2).collect::<Vec<_>>().await;
+        assert_eq!(ids(&messages), vec![Some(second), Some(third)]);
+
+        // events before the oldest kept one are gone
+        let subscriber = stream.subscribe(Some(first - 10));
+        let messages = subscriber.take(4).collect::<Vec<_>>().await;
+        assert_eq!(
+            ids(&messages),
+            vec![None, Some(first), Some(second), Some(third)]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use atom_syndication::{
+    ContentBuilder, EntryBuilder, FeedBuilder, FixedDateTime, LinkBuilder, PersonBuilder,
+};
+use chrono::{DateTime, NaiveDate, NaiveTime};
+use rss::{ChannelBuilder, EnclosureBuilder, GuidBuilder, ItemBuilder};
+use tuat_feed_scraper::{
+    jst,
+    post::{Attachment, Post},
+};
+
+/// a post and the url of its page on the board
+#[derive(Debug, Clone)]
+pub struct FeedItem {
+    /// url of the page of the post. also used as the stable id of the item.
+    pub link: String,
+    /// url each attachment is downloaded from through this api, in the order of the post
+    pub attachments: Vec<String>,
+    /// the post
+    pub post: Post,
+}
+
+impl FeedItem {
+    /// each attachment of the post with the url it is downloaded from
+    pub fn attachments(&self) -> impl Iterator<Item = (&Attachment, &str)> {
+        self.post
+            .attachments
+            .iter()
+            .zip(self.attachments.iter().map(String::as_str))
+    }
+}
+
+/// `date` at midnight in JST, the timezone of the board
+fn board_datetime(date: NaiveDate) -> FixedDateTime {
+    date.and_time(NaiveTime::MIN)
+        .and_local_timezone(jst())
+        .unwrap()
+}
+
+/// when the post was last updated.
+/// falls back to the start of 公開期間 when the board shows no update date.
+fn post_datetime(post: &Post) -> Option<FixedDateTime> {
+    post.updated_date
+        .date
+        .or(post.show_date.0.date)
+        .map(board_datetime)
+}
+
+/// the body of the post followed by its attachments
+pub fn description(item: &FeedItem) -> String {
+    let mut description = item.post.contents.clone();
+    for (attachment, url) in item.attachments() {
+        description.push_str(&format!("\n{}: {}", attachment.name, url));
+    }
+    description
+}
+
+/// renders the posts as an RSS 2.0 feed.
+///
+/// rss allows a single enclosure per item, so only the first attachment is an enclosure.
+/// every attachment is also listed at the end of the description.
+pub fn rss(title: &str, link: &str, items: &[FeedItem]) -> String {
+    let items = items
+        .iter()
+        .map(|item @ FeedItem { link, post, .. }| {
+            let enclosure = item.attachments().next().map(|(attachment, url)| {
+                EnclosureBuilder::default()
+                    .url(url)
+                    .length(attachment.size.unwrap_or(0).to_string())
+                    .mime_type(attachment.file_type.as_str())
+                    .build()
+            });
+            ItemBuilder::default()
+                .title(post.title.clone())
+                .link(link.clone())
+                .description(description(item))
+                .author((!post.origin.is_empty()).then(|| post.origin.clone()))
+                .categories(
+                    (!post.category.is_empty())
+                        .then(|| rss::Category {
+                            name: post.category.clone(),
+                            domain: None,
+                        })
+                        .into_iter()
+                        .collect::<Vec<_>>(),
+                )
+                .guid(
+                    GuidBuilder::default()
+                        .value(link.clone())
+                        .permalink(true)
+                        .build(),
+                )
+                .pub_date(post_datetime(post).map(|date| date.to_rfc2822()))
+                .enclosure(enclosure)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    ChannelBuilder::default()
+        .title(title)
+        .link(link)
+        .description(title)
+        .language("ja".to_string())
+        .items(items)
+        .build()
+        .to_string()
+}
+
+/// renders the posts as an Atom feed.
+/// every attachment is an `enclosure` link.
+pub fn atom(title: &str, link: &str, items: &[FeedItem]) -> String {
+    let epoch = DateTime::UNIX_EPOCH.fixed_offset();
+    let updated = items
+        .iter()
+        .filter_map(|item| post_datetime(&item.post))
+        .max()
+        .unwrap_or(epoch);
+    let entries = items
+        .iter()
+        .map(|item @ FeedItem { link, post, .. }| {
+            let mut links = vec![LinkBuilder::default()
+                .href(link.clone())
+                .rel("alternate")
+                .build()];
+            links.extend(item.attachments().map(|(attachment, url)| {
+                LinkBuilder::default()
+                    .href(url.to_string())
+                    .rel("enclosure")
+                    .title(attachment.name.clone())
+                    .mime_type(attachment.file_type.clone())
+                    .length(attachment.size.map(|size| size.to_string()))
+                    .build()
+            }));
+            EntryBuilder::default()
+                .id(link.clone())
+                .title(post.title.clone())
+                .updated(post_datetime(post).unwrap_or(updated))
+                .authors(
+                    (!post.origin.is_empty())
+                        .then(|| PersonBuilder::default().name(post.origin.clone()).build())
+                        .into_iter()
+                        .collect::<Vec<_>>(),
+                )
+                .links(links)
+                .content(
+                    ContentBuilder::default()
+                        .value(post.contents.clone())
+                        .content_type("text".to_string())
+                        .build(),
+                )
+                .build()
+        })
+        .collect::<Vec<_>>();
+    FeedBuilder::default()
+        .id(link)
+        .title(title)
+        .updated(updated)
+        .lang("ja".to_string())
+        .links(vec![LinkBuilder::default()
+            .href(link)
+            .rel("alternate")
+            .build()])
+        .entries(entries)
+        .build()
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{atom, rss, FeedItem};
+    use tuat_feed_scraper::post::{Attachment, BoardDate, Post};
+
+    fn items() -> Vec<FeedItem> {
+        let mut post = Post::new(10641);
+        post.title = "集中講義 <変更>".to_string();
+        post.contents = "日程が変わりました".to_string();
+        post.origin = "学務課".to_string();
+        post.category = "授業".to_string();
+        post.updated_date = BoardDate::parse("2021/10/07(Thu)");
+        let mut xlsx = Attachment::new("a.xlsx".to_string(), "http://example.com/a".to_string());
+        xlsx.size = Some(2048);
+        post.attachments.push(xlsx);
+        post.attachments.push(Attachment::new(
+            "b.pdf".to_string(),
+            "http://example.com/b".to_string(),
+        ));
+        vec![
+            FeedItem {
+                link: "http://example.com/post?i=10641".to_string(),
+                attachments: vec![
+                    "http://api.example.com/v2/attachments/10641/0".to_string(),
+                    "http://api.example.com/v2/attachments/10641/1".to_string(),
+                ],
+                post,
+            },
+            FeedItem {
+                link: "http://example.com/post?i=1".to_string(),
+                attachments: vec![],
+                post: Post::new(1),
+            },
+        ]
+    }
+
+    #[test]
+    fn rss_items() {
+        let xml = rss("工学部 教務情報", "http://example.com/", &items());
+        let channel = ::rss::Channel::read_from(xml.as_bytes()).unwrap();
+        assert_eq!(channel.title(), "工学部 教務情報");
+        assert_eq!(channel.items().len(), 2);
+
+        let item = &channel.items()[0];
+        assert_eq!(item.title(), Some("集中講義 <変更>"));
+        let guid = item.guid().unwrap();
+        assert_eq!(guid.value(), "http://example.com/post?i=10641");
+        assert!(guid.is_permalink());
+        assert_eq!(item.pub_date(), Some("Thu, 7 Oct 2021 00:00:00 +0900"));
+        let enclosure = item.enclosure().unwrap();
+        assert_eq!(
+            enclosure.url(),
+            "http://api.example.com/v2/attachments/10641/0"
+        );
+        assert_eq!(enclosure.length(), "2048");
+        assert_eq!(
+            enclosure.mime_type(),
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+        );
+        assert!(item
+            .description()
+            .unwrap()
+            .ends_with("b.pdf: http://api.example.com/v2/attachments/10641/1"));
+
+        let item = &channel.items()[1];
+        assert!(item.pub_date().is_none());
+        assert!(item.enclosure().is_none());
+    }
+
+    #[test]
+    fn atom_entries() {
+        let xml = atom("工学部 教務情報", "http://example.com/", &items());
+        let feed = xml.parse::<atom_syndication::Feed>().unwrap();
+        assert_eq!(feed.title().as_str(), "工学部 教務情報");
+        assert_eq!(feed.updated().to_rfc3339(), "2021-10-07T00:00:00+09:00");
+
+        let entry = &feed.entries()[0];
+        assert_eq!(entry.id(), "http://example.com/post?i=10641");
+        assert_eq!(entry.authors()[0].name(), "学務課");
+        let enclosures = entry
+            .links()
+            .iter()
+            .filter(|link| link.rel() == "enclosure")
+            .map(|link| (link.href(), link.mime_type().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            enclosures,
+            vec![
+                (
+                    "http://api.example.com/v2/attachments/10641/0",
+                    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+                ),
+                (
+                    "http://api.example.com/v2/attachments/10641/1",
+                    "application/pdf"
+                ),
+            ]
+        );
+
+        // a post without dates takes the time of the feed
+        assert_eq!(feed.entries()[1].updated(), feed.updated());
+    }
+}
@@ -0,0 +1,515 @@
+use crate::{
+    boards::selects,
+    state::FeedChanges,
+    storage::{Storage, StorageError},
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+use tuat_feed_scraper::{post::Post, GetError, RetryPolicy};
+use utoipa::ToSchema;
+
+/// header with the hex encoded HMAC-SHA256 of the body, prefixed with `sha256=`
+pub const SIGNATURE_HEADER: &str = "X-Tuat-Feed-Signature";
+/// header with the id of the event, the same for every attempt
+pub const EVENT_ID_HEADER: &str = "X-Tuat-Feed-Event-Id";
+
+/// time a receiver has to answer a delivery before the attempt counts as failed
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// which posts a webhook is interested in. an empty filter matches every post.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct WebhookFilter {
+    /// only posts on the board with this id
+    #[schema(example = "technology_academic")]
+    pub board: Option<String>,
+    /// only posts on the boards of this group, ignoring case. also accepted as `gakubu`.
+    #[serde(alias = "gakubu")]
+    #[schema(example = "technology")]
+    pub group: Option<String>,
+    /// only posts on the boards of this category, ignoring case
+    #[schema(example = "academic")]
+    pub category: Option<String>,
+    /// only posts whose title, body or target contains this
+    pub keyword: Option<String>,
+}
+
+impl WebhookFilter {
+    /// whether posts on the board of `changes` can match
+    fn matches_board(&self, changes: &FeedChanges) -> bool {
+        self.board
+            .as_ref()
+            .is_none_or(|board| *board == changes.feed)
+            && selects(self.group.as_deref(), &changes.group)
+            && selects(self.category.as_deref(), &changes.category)
+    }
+
+    fn matches_post(&self, post: &Post) -> bool {
+        let Some(keyword) = &self.keyword else {
+            return true;
+        };
+        [&post.title, &post.contents, &post.target]
+            .iter()
+            .any(|text| text.contains(keyword.as_str()))
+    }
+}
+
+/// a registered webhook
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Webhook {
+    /// id of the webhook
+    pub id: i64,
+    /// url the events are posted to
+    pub url: String,
+    /// key of the signature. never returned by the api.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// which posts are delivered
+    pub filter: WebhookFilter,
+    /// when the webhook was registered
+    pub created_at: DateTime<Utc>,
+}
+
+/// an attempt to deliver an event to a webhook
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    /// id of the webhook
+    pub webhook_id: i64,
+    /// id of the event
+    pub event_id: String,
+    /// 1 for the first attempt
+    pub attempt: u32,
+    /// http status of the response, if there was one
+    pub status: Option<u16>,
+    /// why the attempt failed, if it did
+    pub error: Option<String>,
+    /// when the attempt was made
+    pub attempted_at: DateTime<Utc>,
+}
+
+impl WebhookDelivery {
+    /// whether the event was delivered
+    pub fn succeeded(&self) -> bool {
+        self.status
+            .is_some_and(|status| (200..300).contains(&status))
+    }
+}
+
+/// the body posted to a webhook
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookEvent {
+    /// id of the event
+    pub event_id: String,
+    /// the feed the posts are on
+    pub feed: String,
+    /// group of the board, e.g. `technology`
+    pub group: String,
+    /// category of the board, e.g. `academic`
+    pub category: String,
+    /// posts that appeared on the board
+    pub new: Vec<Post>,
+    /// posts that were edited
+    pub updated: Vec<Post>,
+}
+
+/// how failed deliveries are retried by default: up to 4 retries,
+/// waiting about 10s, 20s, 40s and 80s, giving up after 10 minutes
+fn default_retry() -> RetryPolicy {
+    RetryPolicy::default()
+        .with_max_retries(4)
+        .with_backoff(Duration::from_secs(10), 2.0, Duration::from_secs(80))
+        .with_max_elapsed(Duration::from_secs(10 * 60))
+}
+
+/// `sha256=` and the hex encoded HMAC-SHA256 of `body` keyed with `secret`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("could not build the webhook client")
+}
+
+/// delivers the changes found on each update to the registered webhooks
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    storage: Storage,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl Webhooks {
+    /// webhooks registered in `storage`
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            storage,
+            client: client(DELIVERY_TIMEOUT),
+            retry: default_retry(),
+        }
+    }
+
+    /// give up on a delivery attempt after `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = client(timeout);
+        self
+    }
+
+    /// use `retry` for failed deliveries.
+    /// connection errors, timeouts, `408`, `429` and `5xx` are retried.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// the event for `changes` as `webhook` should see it.
+    /// `None` when none of the posts match its filter.
+    fn event(webhook: &Webhook, changes: &FeedChanges, event_id: &str) -> Option<WebhookEvent> {
+        if !webhook.filter.matches_board(changes) {
+            return None;
+        }
+        let matching = |posts: &[Post]| {
+            posts
+                .iter()
+                .filter(|post| webhook.filter.matches_post(post))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let new = matching(&changes.new);
+        let updated = matching(&changes.updated);
+        if new.is_empty() && updated.is_empty() {
+            return None;
+        }
+        Some(WebhookEvent {
+            event_id: event_id.to_string(),
+            feed: changes.feed.to_string(),
+            group: changes.group.clone(),
+            category: changes.category.clone(),
+            new,
+            updated,
+        })
+    }
+
+    /// delivers `changes` to every webhook whose filter matches.
+    /// returns once every delivery succeeded or ran out of attempts.
+    pub async fn dispatch(&self, changes: &[FeedChanges]) -> Result<(), StorageError> {
+        let webhooks = self.storage.load_webhooks().await?;
+        let now = Utc::now();
+        let mut deliveries = JoinSet::new();
+        for webhook in webhooks {
+            for changes in changes {
+                let event_id =
+                    format!("{}-{}-{}", changes.feed, now.timestamp_millis(), webhook.id);
+                let Some(event) = Self::event(&webhook, changes, &event_id) else {
+                    continue;
+                };
+                let this = self.clone();
+                let webhook = webhook.clone();
+                deliveries.spawn(async move { this.deliver(&webhook, &event).await });
+            }
+        }
+        while deliveries.join_next().await.is_some() {}
+        Ok(())
+    }
+
+    /// posts `event` to `webhook`, retrying with backoff. every attempt is logged.
+    async fn deliver(&self, webhook: &Webhook, event: &WebhookEvent) {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("could not serialize event {}: {:?}", event.event_id, e);
+                return;
+            }
+        };
+        let signature = sign(&webhook.secret, &body);
+        let attempts = AtomicU32::new(0);
+        let result = self
+            .retry
+            .retry(|| async {
+                let attempt = attempts.fetch_add(1, Ordering::Relaxed) + 1;
+                let response = self
+                    .client
+                    .post(&webhook.url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .header(SIGNATURE_HEADER, &signature)
+                    .header(EVENT_ID_HEADER, &event.event_id)
+                    .body(body.clone())
+                    .send()
+                    .await;
+                let delivery = WebhookDelivery {
+                    webhook_id: webhook.id,
+                    event_id: event.event_id.clone(),
+                    attempt,
+                    status: response.as_ref().ok().map(|r| r.status().as_u16()),
+                    error: match &response {
+                        Ok(r) if !r.status().is_success() => Some(format!("status {}", r.status())),
+                        Ok(_) => None,
+                        Err(e) => Some(e.to_string()),
+                    },
+                    attempted_at: Utc::now(),
+                };
+                if let Err(e) = self.storage.record_delivery(delivery).await {
+                    warn!("could not log delivery of {}: {:?}", event.event_id, e);
+                }
+                match response {
+                    Ok(r) if r.status().is_success() => Ok(()),
+                    Ok(r) => Err(GetError::HttpStatus(r.status())),
+                    Err(e) => Err(GetError::ConnectionError(e)),
+                }
+            })
+            .await;
+        match result {
+            Ok(()) => info!("delivered {} to webhook {}", event.event_id, webhook.id),
+            Err(e) => warn!(
+                "gave up delivering {} to webhook {}: {}",
+                event.event_id, webhook.id, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Bytes, extract::State, http::HeaderMap, routing::post, Router};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+    /// a server that fails the first `failures` requests and records every request
+    async fn receiver(failures: usize) -> (String, Received) {
+        let received: Received = Arc::default();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    move |State(received): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                        let mut received = received.lock().unwrap();
+                        received.push((headers, body));
+                        if received.len() <= failures {
+                            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+                        } else {
+                            axum::http::StatusCode::OK
+                        }
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/hook", address), received)
+    }
+
+    fn titled(id: u32, title: &str) -> Post {
+        let mut post = Post::new(id);
+        post.title = title.to_string();
+        post
+    }
+
+    fn changes() -> FeedChanges {
+        FeedChanges {
+            feed: "technology_academic".to_string(),
+            group: "technology".to_string(),
+            category: "academic".to_string(),
+            info_url: "http://example.com/post?i=".to_string(),
+            new: vec![titled(1, "定期試験"), titled(2, "奨学金")],
+            updated: vec![titled(3, "試験の変更")],
+            removed: vec![4],
+        }
+    }
+
+    fn webhooks(storage: Storage) -> Webhooks {
+        Webhooks::new(storage).with_retry(RetryPolicy::default().with_max_retries(2).with_backoff(
+            Duration::from_millis(1),
+            2.0,
+            Duration::from_millis(4),
+        ))
+    }
+
+    #[test]
+    fn signature() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let webhook = |filter| Webhook {
+            id: 1,
+            url: String::new(),
+            secret: String::new(),
+            filter,
+            created_at: Utc::now(),
+        };
+        let event = Webhooks::event(&webhook(WebhookFilter::default()), &changes(), "e").unwrap();
+        assert_eq!(event.new.len(), 2);
+        assert_eq!(event.updated.len(), 1);
+
+        let keyword = WebhookFilter {
+            keyword: Some("試験".to_string()),
+            ..Default::default()
+        };
+        let event = Webhooks::event(&webhook(keyword), &changes(), "e").unwrap();
+        assert_eq!(event.new, vec![titled(1, "定期試験")]);
+        assert_eq!(event.updated, vec![titled(3, "試験の変更")]);
+
+        let other_group = WebhookFilter {
+            group: Some("agriculture".to_string()),
+            ..Default::default()
+        };
+        assert!(Webhooks::event(&webhook(other_group), &changes(), "e").is_none());
+        let other_board = WebhookFilter {
+            board: Some("technology_campus".to_string()),
+            ..Default::default()
+        };
+        assert!(Webhooks::event(&webhook(other_board), &changes(), "e").is_none());
+
+        // filters registered with a gakubu are still understood
+        let gakubu: WebhookFilter =
+            serde_json::from_str(r#"{"gakubu": "Technology", "category": "Academic"}"#).unwrap();
+        assert!(Webhooks::event(&webhook(gakubu), &changes(), "e").is_some());
+
+        let no_match = WebhookFilter {
+            category: Some("academic".to_string()),
+            keyword: Some("休講".to_string()),
+            ..Default::default()
+        };
+        assert!(Webhooks::event(&webhook(no_match), &changes(), "e").is_none());
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_events() {
+        let (url, received) = receiver(0).await;
+        let storage = Storage::in_memory().unwrap();
+        let webhook = storage
+            .add_webhook(
+                url,
+                "secret".to_string(),
+                WebhookFilter {
+                    keyword: Some("奨学金".to_string()),
+                    ..Default::default()
+                },
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+
+        webhooks(storage.clone())
+            .dispatch(&[changes()])
+            .await
+            .unwrap();
+
+        let received = std::mem::take(&mut *received.lock().unwrap());
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        assert_eq!(headers[SIGNATURE_HEADER], sign("secret", body));
+        let event: WebhookEvent = serde_json::from_slice(body).unwrap();
+        assert_eq!(headers[EVENT_ID_HEADER], event.event_id.as_str());
+        assert_eq!(event.new, vec![titled(2, "奨学金")]);
+        assert!(event.updated.is_empty());
+
+        let deliveries = storage.load_deliveries(webhook.id).await.unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert!(deliveries[0].succeeded());
+    }
+
+    #[tokio::test]
+    async fn retries_failed_deliveries() {
+        let (url, received) = receiver(1).await;
+        let storage = Storage::in_memory().unwrap();
+        let webhook = storage
+            .add_webhook(
+                url,
+                "secret".to_string(),
+                WebhookFilter::default(),
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+        // nothing listens here
+        let unreachable = storage
+            .add_webhook(
+                "http://127.0.0.1:1/hook".to_string(),
+                "secret".to_string(),
+                WebhookFilter::default(),
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+
+        webhooks(storage.clone())
+            .dispatch(&[changes()])
+            .await
+            .unwrap();
+
+        assert_eq!(received.lock().unwrap().len(), 2);
+        // newest first
+        let deliveries = storage.load_deliveries(webhook.id).await.unwrap();
+        let attempts = deliveries
+            .iter()
+            .map(|d| (d.attempt, d.status))
+            .collect::<Vec<_>>();
+        assert_eq!(attempts, vec![(2, Some(200)), (1, Some(500))]);
+        assert_eq!(deliveries[0].event_id, deliveries[1].event_id);
+
+        let deliveries = storage.load_deliveries(unreachable.id).await.unwrap();
+        assert_eq!(deliveries.len(), 3);
+        assert!(deliveries
+            .iter()
+            .all(|d| d.status.is_none() && d.error.is_some()));
+    }
+
+    #[tokio::test]
+    async fn stalled_receivers_time_out() {
+        // accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+        let storage = Storage::in_memory().unwrap();
+        let webhook = storage
+            .add_webhook(
+                format!("http://{}/hook", address),
+                "secret".to_string(),
+                WebhookFilter::default(),
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            webhooks(storage.clone())
+                .with_timeout(Duration::from_millis(50))
+                .dispatch(&[changes()]),
+        )
+        .await
+        .expect("dispatch should not wait for a stalled receiver")
+        .unwrap();
+
+        let deliveries = storage.load_deliveries(webhook.id).await.unwrap();
+        assert_eq!(deliveries.len(), 3);
+        assert!(deliveries.iter().all(|d| d.status.is_none()));
+    }
+}